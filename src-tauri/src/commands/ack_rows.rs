@@ -0,0 +1,8 @@
+use crate::pg::result_stream::ResultStreams;
+use tauri::State;
+
+/// Tell a streaming query that a batch of rows was handled, letting it send the next one.
+#[tauri::command]
+pub fn ack_rows(streams: State<'_, ResultStreams>, query_id: String) {
+    streams.ack(&query_id);
+}
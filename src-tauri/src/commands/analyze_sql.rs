@@ -0,0 +1,9 @@
+use crate::pg::pg_connect::PgConnectionConfig;
+use crate::pg::sql_analysis::{self, SqlAnalysis};
+
+/// Classify the statements of a script without running it, to warn about the destructive
+/// ones while they are typed and get the token confirming them.
+#[tauri::command]
+pub fn analyze_sql(connection: PgConnectionConfig, sql: String) -> SqlAnalysis {
+    sql_analysis::analyze_sql(&connection.id, &connection.safety_policy, &sql)
+}
@@ -0,0 +1,18 @@
+use crate::error::CommandError;
+use crate::pg::pg_connect::PgConnectionConfig;
+use crate::pg::pg_pool::PgPools;
+use crate::pg::transactions::{TransactionOptions, Transactions};
+use tauri::State;
+
+/// Start a transaction on a session of its own and return its id, to run statements
+/// with `execute_in_transaction` until `commit` or `rollback`.
+#[tauri::command]
+pub async fn begin_transaction(
+    pools: State<'_, PgPools>,
+    transactions: State<'_, Transactions>,
+    connection: PgConnectionConfig,
+    options: Option<TransactionOptions>,
+) -> Result<String, CommandError> {
+    let client = pools.get(&connection).await?;
+    transactions.begin(client, &connection, &options.unwrap_or_default()).await
+}
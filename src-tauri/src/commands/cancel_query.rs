@@ -0,0 +1,10 @@
+use crate::error::CommandError;
+use crate::pg::running_queries::RunningQueries;
+use tauri::State;
+
+/// Cancel a query started by `raw_query` or `get_table_data` with the given id.
+/// Returns `false` if the query had already finished.
+#[tauri::command]
+pub async fn cancel_query(queries: State<'_, RunningQueries>, query_id: String) -> Result<bool, CommandError> {
+    queries.cancel(&query_id).await
+}
@@ -0,0 +1,23 @@
+use crate::error::CommandError;
+use crate::pg::cursors::Cursors;
+use crate::pg::listeners::Listeners;
+use crate::pg::pg_pool::PgPools;
+use crate::pg::transactions::Transactions;
+use tauri::State;
+
+/// Forget the pool of a connection, closing its cursors and rolling back its transactions
+/// so their sessions are not kept open.
+#[tauri::command]
+pub async fn close_connection(
+    pools: State<'_, PgPools>,
+    listeners: State<'_, Listeners>,
+    cursors: State<'_, Cursors>,
+    transactions: State<'_, Transactions>,
+    connection_id: String,
+) -> Result<(), CommandError> {
+    cursors.close_connection(&connection_id).await;
+    transactions.rollback_connection(&connection_id).await;
+    pools.remove(&connection_id);
+    listeners.remove(&connection_id).await;
+    Ok(())
+}
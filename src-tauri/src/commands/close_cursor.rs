@@ -0,0 +1,10 @@
+use crate::error::CommandError;
+use crate::pg::cursors::Cursors;
+use tauri::State;
+
+/// Close a cursor before it is exhausted, giving its session back to the pool.
+/// Returns `false` if it was already closed.
+#[tauri::command]
+pub async fn close_cursor(cursors: State<'_, Cursors>, cursor_id: String) -> Result<bool, CommandError> {
+    Ok(cursors.close(&cursor_id).await)
+}
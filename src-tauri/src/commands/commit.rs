@@ -0,0 +1,9 @@
+use crate::error::CommandError;
+use crate::pg::transactions::Transactions;
+use tauri::State;
+
+/// Commit a transaction started with `begin_transaction`.
+#[tauri::command]
+pub async fn commit(transactions: State<'_, Transactions>, transaction_id: String) -> Result<(), CommandError> {
+    transactions.commit(&transaction_id).await
+}
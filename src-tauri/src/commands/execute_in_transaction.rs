@@ -0,0 +1,64 @@
+use crate::commands::raw_query::{check_read_only, history_entry, run_script, OnError, QueryMode, ScriptOptions, ScriptResult};
+use crate::error::CommandError;
+use crate::pg::query_history::QueryHistory;
+use crate::pg::result_stream::RowSink;
+use crate::pg::running_queries::RunningQueries;
+use crate::pg::sql_analysis::check_confirmation;
+use crate::pg::sql_split::{ends_transaction, split_statements};
+use crate::pg::transactions::Transactions;
+use serde_json::Value as JsonValue;
+use std::time::Instant;
+use chrono::Utc;
+use tauri::State;
+
+/// Same as `raw_query`, in a transaction started with `begin_transaction`. With `on_error`,
+/// a failed statement is rolled back to a savepoint so that the transaction goes on.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn execute_in_transaction(
+    queries: State<'_, RunningQueries>,
+    transactions: State<'_, Transactions>,
+    history: State<'_, QueryHistory>,
+    transaction_id: String,
+    sql: String,
+    query_id: String,
+    mode: Option<QueryMode>,
+    params: Option<Vec<JsonValue>>,
+    on_error: Option<OnError>,
+    confirmation: Option<String>,
+    keep_copy_output: Option<bool>,
+) -> Result<ScriptResult, CommandError> {
+    if split_statements(&sql).iter().any(|statement| ends_transaction(&statement.sql)) {
+        return Err(CommandError::with_code(
+            "transaction_control",
+            "Use commit or rollback to end the transaction",
+        ));
+    }
+    let session = transactions.session(&transaction_id).await?;
+    check_read_only(session.read_only_connection, &sql)?;
+    check_confirmation(&session.connection_id, &session.safety_policy, &sql, confirmation.as_deref())?;
+
+    println!("psql > {}", sql);
+
+    let options = ScriptOptions {
+        mode: mode.unwrap_or_default(),
+        params: params.unwrap_or_default(),
+        on_error,
+        confirmation,
+        keep_copy_output: keep_copy_output.unwrap_or_default(),
+    };
+    let mut sink = RowSink::collect();
+    let started_at = Utc::now();
+    let start = Instant::now();
+    let canceller = transactions.canceller(&transaction_id)?;
+    session.notices().start(&query_id);
+    let result = queries.run(&query_id, canceller, run_script(&session, &sql, &options, true, &mut sink)).await;
+    let notices = session.notices().finish();
+    let result = result.map(|statements| ScriptResult {
+        statements,
+        duration_ms: start.elapsed().as_millis() as u64,
+        notices,
+    });
+    history.record(history_entry(&session.connection_id, &session.database, &sql, &options, started_at, &result));
+    result
+}
@@ -0,0 +1,85 @@
+use crate::error::CommandError;
+use crate::pg::explain::ExplainResult;
+use crate::pg::pg_connect::PgConnectionConfig;
+use crate::pg::pg_pool::PgPools;
+use crate::pg::running_queries::RunningQueries;
+use crate::pg::sql_split::split_statements;
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use tauri::State;
+use tokio_postgres::Client as PgClient;
+
+/// Show the plan of a statement with `EXPLAIN`, flagging its hot nodes and common problems.
+///
+/// With `analyze` the statement really runs, inside a transaction that is rolled back
+/// so that `INSERT`, `UPDATE` or `DELETE` leave no trace. `buffers` only applies then.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn explain_query(
+    pools: State<'_, PgPools>,
+    queries: State<'_, RunningQueries>,
+    connection: PgConnectionConfig,
+    sql: String,
+    query_id: String,
+    analyze: bool,
+    buffers: bool,
+) -> Result<ExplainResult, CommandError> {
+    let statements = split_statements(&sql);
+    let [statement] = statements.as_slice() else {
+        return Err(CommandError::from("Only one statement can be explained at a time"));
+    };
+
+    let mut client = pools.get(&connection).await?;
+    let run = async {
+        // SETTINGS only exists since Postgres 12
+        let version: i32 = client.query_one("select current_setting('server_version_num')::int", &[]).await?.get(0);
+        let mut options = vec!["format json", "verbose"];
+        if version >= 120000 {
+            options.push("settings");
+        }
+        if analyze {
+            options.push("analyze");
+            if buffers {
+                options.push("buffers");
+            }
+            client.batch_execute("begin").await?;
+        }
+        let explain = format!("explain ({}) {}", options.join(", "), statement.sql);
+        println!("psql > {}", explain);
+
+        let raw: JsonValue = client
+            .query_one(&explain, &[])
+            .await
+            .map_err(|e| CommandError::from(e).sent_in(&explain, &statement.sql).locate(&sql, statement))?
+            .get(0);
+        let mut result = ExplainResult::parse(raw)?;
+        let table_rows = table_rows(&client, result.seq_scan_relations()).await?;
+        result.flag_problems(&table_rows);
+        Ok::<_, CommandError>(result)
+    };
+    let result = queries.run(&query_id, client.canceller(), run).await;
+
+    // Rolls back what ANALYZE did
+    client.reset_session().await;
+    result
+}
+
+/// The estimated row count of the given tables, from the statistics of the planner.
+async fn table_rows(
+    client: &PgClient,
+    relations: Vec<(String, String)>,
+) -> Result<HashMap<(String, String), f64>, CommandError> {
+    if relations.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let (schemas, names): (Vec<String>, Vec<String>) = relations.into_iter().unzip();
+    let rows = client
+        .query(
+            "select s, r, c.reltuples::float8
+            from unnest($1::text[], $2::text[]) as t(s, r)
+            join pg_class c on c.oid = to_regclass(format('%I.%I', s, r))",
+            &[&schemas, &names],
+        )
+        .await?;
+    Ok(rows.iter().map(|row| ((row.get(0), row.get(1)), row.get(2))).collect())
+}
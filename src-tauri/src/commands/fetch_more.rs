@@ -0,0 +1,20 @@
+use crate::error::CommandError;
+use crate::pg::cursors::{CursorPage, Cursors};
+use crate::pg::running_queries::RunningQueries;
+use tauri::State;
+
+/// Read the next `count` rows of a cursor opened with `open_cursor`.
+#[tauri::command]
+pub async fn fetch_more(
+    queries: State<'_, RunningQueries>,
+    cursors: State<'_, Cursors>,
+    cursor_id: String,
+    count: u32,
+    query_id: String,
+) -> Result<CursorPage, CommandError> {
+    if count == 0 {
+        return Err(CommandError::from("The number of rows to fetch must be at least 1"));
+    }
+    let canceller = cursors.canceller(&cursor_id)?;
+    queries.run(&query_id, canceller, cursors.fetch(&cursor_id, count)).await
+}
@@ -2,11 +2,12 @@ use futures::lock::Mutex;
 use reqwest::Client;
 use serde_json::json;
 use std::sync::Arc;
-use tauri::{AppHandle, Emitter};
+use tauri::{AppHandle, Emitter, State};
 
 use crate::ai::tool_registry::ToolRegistry;
 use crate::ai::tools;
-use crate::pg::pg_connect::{SharedDb, pg_connect};
+use crate::pg::pg_connect::{PgConnectionConfig, SharedDb};
+use crate::pg::pg_pool::PgPools;
 use crate::ai::stream::{ReasoningEffort, run_agentic_loop};
 
 const SYSTEM_PROMPT: &str = r#"
@@ -55,9 +56,11 @@ return a short explanation.
 "#;
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn generate_query(
     app:                  AppHandle,
-    connection_string:    String,
+    pools:                State<'_, PgPools>,
+    connection:           PgConnectionConfig,
     api_key:              String,
     model:                String,
     reasoning:            Option<ReasoningEffort>,
@@ -66,16 +69,10 @@ pub async fn generate_query(
 ) -> Result<Option<String>, String> {
     let http = Client::new();
 
-    let (pg_client, connection) = pg_connect(&connection_string)
+    let pg_client = pools.get(&connection)
         .await
         .map_err(|e| e.message)?;
 
-    tokio::spawn(async move {
-        if let Err(e) = connection.await_connection().await {
-            eprintln!("DB connection error: {e}");
-        }
-    });
-
     let db: SharedDb = Arc::new(Mutex::new(pg_client));
     let mut registry = ToolRegistry::new();
 
@@ -94,7 +91,7 @@ pub async fn generate_query(
         vec![json!({ "role": "user", "content": prompt })]
     };
 
-    run_agentic_loop(
+    let result = run_agentic_loop(
         &http,
         &api_key,
         &model,
@@ -105,5 +102,8 @@ pub async fn generate_query(
         &mut |event| {
             app.emit("generate-query", event).ok();
         },
-    ).await
+    ).await;
+    // The tools only run single reads
+    db.lock().await.mark_clean();
+    result
 }
\ No newline at end of file
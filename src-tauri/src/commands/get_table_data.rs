@@ -1,12 +1,21 @@
 use crate::error::CommandError;
 use crate::pg::models::PgTableData;
-use crate::pg::pg_connect::pg_connect;
+use crate::pg::pg_connect::PgConnectionConfig;
+use crate::pg::pg_pool::PgPools;
 use crate::pg::quote_ident::quote_ident;
+use crate::pg::result_stream::RowSink;
+use crate::pg::running_queries::RunningQueries;
+use futures::TryStreamExt;
 use serde_json::Value as JsonValue;
+use tokio_postgres::Client as PgClient;
+use tauri::State;
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn get_table_data(
-    connection_string: String,
+    pools: State<'_, PgPools>,
+    queries: State<'_, RunningQueries>,
+    connection: PgConnectionConfig,
     schema: String,
     table: String,
     columns: Option<String>,
@@ -14,22 +23,37 @@ pub async fn get_table_data(
     limit: Option<i64>,
     where_clause: Option<String>,
     order_by: Option<String>,
+    query_id: String,
 ) -> Result<PgTableData, CommandError> {
-    let (client, connection) = pg_connect(&connection_string).await?;
-
-    tokio::spawn(async move {
-        if let Err(e) = connection.await_connection().await {
-            eprintln!("DB connection error: {e}");
-        }
-    });
+    let (select_sql, count_sql) = table_data_sql(&schema, &table, columns, offset, limit, where_clause, order_by);
+    let (select_sql, count_sql, query_id, queries) = (&select_sql, &count_sql, &query_id, &*queries);
+    pools.read(&connection, |client| async move {
+        let mut sink = RowSink::collect();
+        let count = queries
+            .run(query_id, client.canceller(), read_table_data(&client, select_sql, count_sql, &mut sink))
+            .await?;
+        let rows = sink.finish("SELECT", None, 0).await?;
+        Ok(PgTableData { rows, count })
+    }).await
+}
 
+/// The query returning the rows as JSON text, and the one counting the rows of the table.
+pub(crate) fn table_data_sql(
+    schema: &str,
+    table: &str,
+    columns: Option<String>,
+    offset: Option<i64>,
+    limit: Option<i64>,
+    where_clause: Option<String>,
+    order_by: Option<String>,
+) -> (String, String) {
     let columns = columns.unwrap_or("*".to_string());
     let offset = offset.unwrap_or(0);
     let where_clause = where_clause.unwrap_or_default();
     let order_by = order_by.unwrap_or_default();
 
-    let schema_q = quote_ident(&schema);
-    let table_q = quote_ident(&table);
+    let schema_q = quote_ident(schema);
+    let table_q = quote_ident(table);
 
     let select_sql = match limit {
         Some(l) if l > 0 => format!(
@@ -44,18 +68,25 @@ pub async fn get_table_data(
 
     println!("psql > {}", select_sql);
 
-    let rows = client.query(&select_sql, &[]).await.map_err(CommandError::from)?;
+    let count_sql = format!("select count(*) as count from {}.{}", schema_q, table_q);
+    (select_sql, count_sql)
+}
 
-    let mut json_rows: Vec<JsonValue> = Vec::with_capacity(rows.len());
-    for row in rows.iter() {
-        let txt: String = row.get("json_text");
-        let v: JsonValue = serde_json::from_str(&txt).map_err(CommandError::from)?;
-        json_rows.push(v);
+/// Read the rows into `sink` and return the row count of the table.
+pub(crate) async fn read_table_data(
+    client: &PgClient,
+    select_sql: &str,
+    count_sql: &str,
+    sink: &mut RowSink<'_>,
+) -> Result<i64, CommandError> {
+    let stream = client.query_raw(select_sql, std::iter::empty::<&str>()).await?;
+    futures::pin_mut!(stream);
+    while let Some(row) = stream.try_next().await? {
+        let txt: &str = row.get("json_text");
+        let v: JsonValue = serde_json::from_str(txt).map_err(CommandError::from)?;
+        sink.push(v).await?;
     }
 
-    let count_sql = format!("select count(*) as count from {}.{}", schema_q, table_q);
-    let count_row = client.query_one(&count_sql, &[]).await.map_err(CommandError::from)?;
-    let count: i64 = count_row.get("count");
-
-    Ok(PgTableData { rows: json_rows, count })
-}
\ No newline at end of file
+    let count_row = client.query_one(count_sql, &[]).await?;
+    Ok(count_row.get("count"))
+}
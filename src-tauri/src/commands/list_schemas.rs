@@ -1,36 +1,32 @@
 use crate::error::CommandError;
-use crate::pg::pg_connect::pg_connect;
+use crate::pg::pg_connect::PgConnectionConfig;
+use crate::pg::pg_pool::PgPools;
+use tauri::State;
 
 #[tauri::command]
-pub async fn list_schemas(connection_string: String, hide_system_tables: bool) -> Result<Vec<String>, CommandError> {
-    let (client, connection) = pg_connect(&connection_string).await?;
+pub async fn list_schemas(pools: State<'_, PgPools>, connection: PgConnectionConfig, hide_system_tables: bool) -> Result<Vec<String>, CommandError> {
+    pools.read(&connection, |client| async move {
+        let system_schemas_filter = if hide_system_tables {
+            "nspname NOT IN ('pg_catalog', 'information_schema')
+            AND nspname NOT LIKE 'pg_toast%'
+            AND nspname NOT LIKE 'pg_temp%'"
+        } else {
+            "true"
+        };
 
-    tokio::spawn(async move {
-        if let Err(e) = connection.await_connection().await {
-            eprintln!("DB connection error: {e}");
-        }
-    });
+        let query = format!(
+            r#"
+            SELECT nspname AS schema_name
+            FROM pg_catalog.pg_namespace
+            WHERE {}
+            ORDER BY (CASE WHEN nspname = 'public' THEN 0 ELSE 1 END), nspname ASC;
+            "#,
+            system_schemas_filter
+        );
 
-    let system_schemas_filter = if hide_system_tables {
-        "nspname NOT IN ('pg_catalog', 'information_schema')
-        AND nspname NOT LIKE 'pg_toast%'
-        AND nspname NOT LIKE 'pg_temp%'"
-    } else {
-        "true"
-    };
+        let rows = client.query(&query, &[]).await.map_err(CommandError::from)?;
 
-    let query = format!(
-        r#"
-        SELECT nspname AS schema_name
-        FROM pg_catalog.pg_namespace
-        WHERE {}
-        ORDER BY (CASE WHEN nspname = 'public' THEN 0 ELSE 1 END), nspname ASC;
-        "#,
-        system_schemas_filter
-    );
-
-    let rows = client.query(&query, &[]).await.map_err(CommandError::from)?;
-
-    let schemas: Vec<String> = rows.iter().map(|row| row.get("schema_name")).collect();
-    Ok(schemas)
+        let schemas: Vec<String> = rows.iter().map(|row| row.get("schema_name")).collect();
+        Ok(schemas)
+    }).await
 }
@@ -1,75 +1,71 @@
 use crate::error::CommandError;
 use crate::pg::models::PgTable;
-use crate::pg::pg_connect::pg_connect;
+use crate::pg::pg_connect::PgConnectionConfig;
+use crate::pg::pg_pool::PgPools;
+use tauri::State;
 
 #[tauri::command]
-pub async fn list_tables(connection_string: String, hide_system_tables: bool, hide_views: bool) -> Result<Vec<PgTable>, CommandError> {
-    let (client, connection) = pg_connect(&connection_string).await?;
+pub async fn list_tables(pools: State<'_, PgPools>, connection: PgConnectionConfig, hide_system_tables: bool, hide_views: bool) -> Result<Vec<PgTable>, CommandError> {
+    pools.read(&connection, |client| async move {
+        let system_schemas_filter = if hide_system_tables {
+            "t.table_schema NOT IN ('pg_catalog', 'information_schema')
+            AND t.table_schema NOT LIKE 'pg_toast%'
+            AND t.table_schema NOT LIKE 'pg_temp%'"
+        } else {
+            "true"
+        };
 
-    tokio::spawn(async move {
-        if let Err(e) = connection.await_connection().await {
-            eprintln!("DB connection error: {e}");
-        }
-    });
+        let views_filter = if hide_views {
+            "t.table_type = 'BASE TABLE'"
+        } else {
+            "true"
+        };
 
-    let system_schemas_filter = if hide_system_tables {
-        "t.table_schema NOT IN ('pg_catalog', 'information_schema')
-        AND t.table_schema NOT LIKE 'pg_toast%'
-        AND t.table_schema NOT LIKE 'pg_temp%'"
-    } else {
-        "true"
-    };
+        let query = format!(
+            "SELECT 
+                t.table_schema as schema,
+                t.table_name as name,
+                t.table_type as type,
+                COALESCE(pg_relation_size(quote_ident(t.table_schema) || '.' || quote_ident(t.table_name))::float8 / 1024 / 1024, 0) as size_mb,
+                COALESCE(
+                    (SELECT json_agg(column_name ORDER BY ordinal_position)::text
+                    FROM information_schema.columns c
+                    WHERE c.table_schema = t.table_schema 
+                    AND c.table_name = t.table_name),
+                    '[]'
+                ) as columns
+            FROM 
+                information_schema.tables t
+            WHERE 
+                {}
+                AND {}
+            ORDER BY 
+                (CASE WHEN t.table_schema = 'public' THEN 0 ELSE 1 END),
+                t.table_schema ASC,
+                t.table_name ASC;",
+            system_schemas_filter,
+            views_filter
+        );
 
-    let views_filter = if hide_views {
-        "t.table_type = 'BASE TABLE'"
-    } else {
-        "true"
-    };
+        let rows = client.query(&query, &[]).await.map_err(CommandError::from)?;
 
-    let query = format!(
-        "SELECT 
-            t.table_schema as schema,
-            t.table_name as name,
-            t.table_type as type,
-            COALESCE(pg_relation_size(quote_ident(t.table_schema) || '.' || quote_ident(t.table_name))::float8 / 1024 / 1024, 0) as size_mb,
-            COALESCE(
-                (SELECT json_agg(column_name ORDER BY ordinal_position)::text
-                FROM information_schema.columns c
-                WHERE c.table_schema = t.table_schema 
-                AND c.table_name = t.table_name),
-                '[]'
-            ) as columns
-        FROM 
-            information_schema.tables t
-        WHERE 
-            {}
-            AND {}
-        ORDER BY 
-            (CASE WHEN t.table_schema = 'public' THEN 0 ELSE 1 END),
-            t.table_schema ASC,
-            t.table_name ASC;",
-        system_schemas_filter,
-        views_filter
-    );
+        let tables = rows
+            .iter()
+            .map(|row| {
+                let size_mb: f64 = row.get("size_mb");
+                let columns_str: String = row.get("columns");
+                let column_names: Vec<String> = serde_json::from_str(&columns_str).unwrap_or_default();
 
-    let rows = client.query(&query, &[]).await.map_err(CommandError::from)?;
+                PgTable {
+                    schema: row.get("schema"),
+                    name: row.get("name"),
+                    table_type: row.get("type"),
+                    size_mb,
+                    column_names,
+                }
+            })
+            .collect();
 
-    let tables = rows
-        .iter()
-        .map(|row| {
-            let size_mb: f64 = row.get("size_mb");
-            let columns_str: String = row.get("columns");
-            let column_names: Vec<String> = serde_json::from_str(&columns_str).unwrap_or_default();
-
-            PgTable {
-                schema: row.get("schema"),
-                name: row.get("name"),
-                table_type: row.get("type"),
-                size_mb,
-                column_names,
-            }
-        })
-        .collect();
-
-    Ok(tables)
+        Ok(tables)
+    }).await
 }
\ No newline at end of file
@@ -0,0 +1,15 @@
+use crate::error::CommandError;
+use crate::pg::listeners::Listeners;
+use crate::pg::pg_connect::PgConnectionConfig;
+use tauri::State;
+
+/// Listen to a channel on a session kept open for it, forwarding its notifications
+/// as `pg-notification` events.
+#[tauri::command]
+pub async fn listen(
+    listeners: State<'_, Listeners>,
+    connection: PgConnectionConfig,
+    channel: String,
+) -> Result<(), CommandError> {
+    listeners.listen(&connection, &channel).await
+}
@@ -1,11 +1,32 @@
+pub mod ack_rows;
+pub mod analyze_sql;
+pub mod begin_transaction;
+pub mod cancel_query;
+pub mod close_connection;
+pub mod close_cursor;
+pub mod commit;
 pub mod create_new_window;
+pub mod execute_in_transaction;
+pub mod explain_query;
+pub mod fetch_more;
 pub mod generate_chat_title;
 pub mod generate_query;
 pub mod get_table_data;
 pub mod list_table_columns;
+pub mod listen;
 pub mod list_schemas;
 pub mod list_tables;
 pub mod list_tables_for_graph;
+pub mod notify;
+pub mod open_cursor;
 pub mod raw_query;
+pub mod recent_notifications;
+pub mod rerun_history_entry;
+pub mod rollback;
+pub mod savepoint;
+pub mod search_history;
 pub mod show_main_window;
+pub mod stream_query;
+pub mod stream_table_data;
 pub mod test_connection;
+pub mod unlisten;
@@ -0,0 +1,20 @@
+use crate::error::CommandError;
+use crate::pg::pg_connect::PgConnectionConfig;
+use crate::pg::pg_pool::PgPools;
+use tauri::State;
+
+/// Send a notification on a channel, to test the sessions listening to it.
+#[tauri::command]
+pub async fn notify(
+    pools: State<'_, PgPools>,
+    connection: PgConnectionConfig,
+    channel: String,
+    payload: Option<String>,
+) -> Result<(), CommandError> {
+    let client = pools.get(&connection).await?;
+    client
+        .execute("SELECT pg_notify($1, $2)", &[&channel, &payload.unwrap_or_default()])
+        .await?;
+    client.mark_clean();
+    Ok(())
+}
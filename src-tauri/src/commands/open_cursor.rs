@@ -0,0 +1,65 @@
+use crate::commands::raw_query::{check_read_only, run_history_entry};
+use crate::error::CommandError;
+use crate::pg::cursors::{CursorPage, Cursors};
+use crate::pg::pg_connect::PgConnectionConfig;
+use crate::pg::pg_pool::PgPools;
+use crate::pg::query_history::QueryHistory;
+use crate::pg::running_queries::RunningQueries;
+use crate::pg::sql_analysis::check_confirmation;
+use crate::pg::sql_split::split_statements;
+use chrono::Utc;
+use std::time::Instant;
+use tauri::State;
+
+/// Open a server-side cursor for a single query and return its first `page_size` rows.
+/// The next pages are read with `fetch_more`.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn open_cursor(
+    pools: State<'_, PgPools>,
+    queries: State<'_, RunningQueries>,
+    cursors: State<'_, Cursors>,
+    history: State<'_, QueryHistory>,
+    connection: PgConnectionConfig,
+    sql: String,
+    page_size: u32,
+    query_id: String,
+    confirmation: Option<String>,
+) -> Result<CursorPage, CommandError> {
+    if page_size == 0 {
+        return Err(CommandError::from("The page size must be at least 1"));
+    }
+    let [statement] = split_statements(&sql).try_into().map_err(|_| {
+        CommandError::with_code("single_statement", "A cursor can only be opened for a single query")
+    })?;
+    check_read_only(connection.read_only, &sql)?;
+    check_confirmation(&connection.id, &connection.safety_policy, &sql, confirmation.as_deref())?;
+
+    let client = pools.get(&connection).await?;
+
+    println!("psql > {}", sql);
+
+    let database = client.database().to_string();
+    let started_at = Utc::now();
+    let start = Instant::now();
+    let canceller = client.canceller();
+    let result = queries
+        .run(&query_id, canceller, async {
+            let cursor_id =
+                cursors.open(client, &connection.id, &statement.sql).await.map_err(|e| e.locate(&sql, &statement))?;
+            cursors.fetch(&cursor_id, page_size).await
+        })
+        .await;
+    let duration_ms = start.elapsed().as_millis() as u64;
+    let rows = result.as_ref().ok().map(|page| page.fetched_rows);
+    history.record(run_history_entry(
+        &connection.id,
+        &database,
+        &sql,
+        started_at,
+        duration_ms,
+        rows,
+        result.as_ref().err(),
+    ));
+    result
+}
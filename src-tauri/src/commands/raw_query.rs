@@ -1,37 +1,375 @@
 use crate::error::CommandError;
-use crate::pg::pg_connect::pg_connect;
-use tokio_postgres::SimpleQueryMessage;
-use serde::Serialize;
+use crate::pg::bind_params::bind_params;
+use crate::pg::copy::{client_copy, copy_in_data, copy_in_file, copy_out, copy_out_file, ClientCopy};
+use crate::pg::models::PgResultColumn;
+use crate::pg::notices::PgNotice;
+use crate::pg::pg_connect::PgConnectionConfig;
+use crate::pg::pg_pool::PgPools;
+use crate::pg::query_history::{HistoryEntry, HistoryStatus, QueryHistory};
+use crate::pg::result_stream::RowSink;
+use crate::pg::running_queries::RunningQueries;
+use crate::pg::sql_analysis::{check_confirmation, is_read_only};
+use crate::pg::sql_split::{command_tag, split_statements, uses_savepoints, SqlStatement};
+use crate::pg::value_codec::{prepare_decodable, row_to_json};
+use futures::TryStreamExt;
+use tokio_postgres::{Client as PgClient, SimpleQueryMessage};
+use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value as JsonValue};
 use std::time::Instant;
+use chrono::{DateTime, Utc};
+use tauri::State;
 
+/// The results of a script, one entry per statement in the order they ran.
 #[derive(Serialize)]
-pub struct QueryResponse {
+pub struct ScriptResult {
+    pub statements: Vec<StatementResult>,
+    pub duration_ms: u64,
+    /// The notices and warnings the server sent, also emitted as `query-notice` events while the script runs.
+    pub notices: Vec<PgNotice>,
+}
+
+#[derive(Serialize)]
+pub struct StatementResult {
+    #[serde(flatten)]
+    pub statement: SqlStatement,
+    pub command_tag: String,
+    /// Rows inserted, updated, deleted or selected; `None` for statements without a count, like DDL.
+    pub rows_affected: Option<u64>,
+    /// Empty when the statement returns no rows.
+    pub columns: Vec<PgResultColumn>,
+    /// Empty when the rows were streamed.
     pub rows: Vec<JsonValue>,
+    /// Time between the end of the previous statement and the end of this one.
     pub duration_ms: u64,
+    pub status: StatementStatus,
+    /// Why the statement failed, when the script goes on after errors. Its position is in the script.
+    pub error: Option<CommandError>,
+    /// What a `COPY ... TO STDOUT` sent, to save as a file, when the script was run with
+    /// `keep_copy_output`. Its rows are parsed into `rows` too.
+    pub copy_output: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatementStatus {
+    Ok,
+    Error,
+    /// Not run because a previous statement failed.
+    Skipped,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryMode {
+    /// The whole script is sent as one simple query, so its statements share the
+    /// implicit transaction. Every value comes back as text.
+    #[default]
+    Simple,
+    /// Each statement is prepared and run on its own, returning typed values and
+    /// column metadata. Statements are committed one by one. Scripts with parameters
+    /// always run this way.
+    Typed,
+}
+
+/// What to do when a statement fails. Scripts run with one are sent one statement
+/// at a time, each in its own transaction unless the script opens one. In a transaction
+/// of `begin_transaction`, each one is rolled back to a savepoint when it fails.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnError {
+    /// Skip the statements after the failed one.
+    Stop,
+    /// Run the next statements anyway.
+    Continue,
+}
+
+/// How `execute_script` runs a script. The history keeps all of it but the confirmation.
+pub(crate) struct ScriptOptions {
+    pub mode: QueryMode,
+    pub params: Vec<JsonValue>,
+    /// Without it, the first error fails the whole script.
+    pub on_error: Option<OnError>,
+    /// The token `analyze_sql` returned, for scripts the safety policy wants confirmed.
+    pub confirmation: Option<String>,
+    /// Keep what `COPY ... TO STDOUT` sends in `copy_output`, to save it as a file.
+    pub keep_copy_output: bool,
 }
 
+/// Run a script. `params` are bound to the `$1..$n` parameters of every statement,
+/// converted to the types the server inferred for them. Scripts the safety policy of the
+/// connection wants confirmed need the `confirmation` token of `analyze_sql`. With
+/// `keep_copy_output`, what `COPY ... TO STDOUT` sends is returned to be saved as a file.
 #[tauri::command]
-pub async fn raw_query(connection_string: String, sql: String) -> Result<QueryResponse, CommandError> {
-    let (client, connection) = pg_connect(&connection_string).await?;
+#[allow(clippy::too_many_arguments)]
+pub async fn raw_query(
+    pools: State<'_, PgPools>,
+    queries: State<'_, RunningQueries>,
+    history: State<'_, QueryHistory>,
+    connection: PgConnectionConfig,
+    sql: String,
+    query_id: String,
+    mode: Option<QueryMode>,
+    params: Option<Vec<JsonValue>>,
+    on_error: Option<OnError>,
+    confirmation: Option<String>,
+    keep_copy_output: Option<bool>,
+) -> Result<ScriptResult, CommandError> {
+    let mut sink = RowSink::collect();
+    let options = ScriptOptions {
+        mode: mode.unwrap_or_default(),
+        params: params.unwrap_or_default(),
+        on_error,
+        confirmation,
+        keep_copy_output: keep_copy_output.unwrap_or_default(),
+    };
+    execute_script(&pools, &queries, &history, &connection, &sql, &query_id, &options, &mut sink).await
+}
 
-    tokio::spawn(async move {
-        if let Err(e) = connection.await_connection().await {
-            eprintln!("DB connection error: {e}");
-        }
-    });
+/// Run a script, putting the rows of each statement into `sink`, and record it in the history.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn execute_script(
+    pools: &PgPools,
+    queries: &RunningQueries,
+    history: &QueryHistory,
+    connection: &PgConnectionConfig,
+    sql: &str,
+    query_id: &str,
+    options: &ScriptOptions,
+    sink: &mut RowSink<'_>,
+) -> Result<ScriptResult, CommandError> {
+    check_read_only(connection.read_only, sql)?;
+    check_confirmation(&connection.id, &connection.safety_policy, sql, options.confirmation.as_deref())?;
+
+    let mut client = pools.get(connection).await?;
 
     println!("psql > {}", sql);
 
+    let started_at = Utc::now();
     let start = Instant::now();
-    let messages = client.simple_query(&sql).await.map_err(CommandError::from)?;
+    client.notices().start(query_id);
+    let run = async {
+        if connection.read_only {
+            // The server then refuses writes for the whole script, whatever settings it changes
+            client.batch_execute("begin read only").await?;
+        }
+        run_script(&client, sql, options, connection.read_only, sink).await
+    };
+    let result = queries.run(query_id, client.canceller(), run).await;
     let duration = start.elapsed();
+    let notices = client.notices().finish();
+
+    // The script may have left a transaction open or changed settings with `SET`
+    client.reset_session().await;
+    let result = result.map(|statements| ScriptResult {
+        statements,
+        duration_ms: duration.as_millis() as u64,
+        notices,
+    });
+    history.record(history_entry(&connection.id, client.database(), sql, options, started_at, &result));
+    result
+}
+
+/// What the history keeps of a script run, with the options to run it again the same way.
+pub(crate) fn history_entry(
+    connection_id: &str,
+    database: &str,
+    sql: &str,
+    options: &ScriptOptions,
+    started_at: DateTime<Utc>,
+    result: &Result<ScriptResult, CommandError>,
+) -> HistoryEntry {
+    let (duration_ms, rows, error) = match result {
+        Ok(result) => {
+            let counts = result.statements.iter().filter_map(|statement| statement.rows_affected);
+            let error = result.statements.iter().find_map(|statement| statement.error.as_ref());
+            (result.duration_ms, counts.reduce(|total, count| total + count), error)
+        }
+        Err(e) => ((Utc::now() - started_at).num_milliseconds().max(0) as u64, None, Some(e)),
+    };
+    // The confirmation only holds for this run, running the entry again needs a new one
+    let ScriptOptions { mode, params, on_error, confirmation: _, keep_copy_output } = options;
+    HistoryEntry {
+        mode: *mode,
+        params: params.clone(),
+        on_error: *on_error,
+        keep_copy_output: *keep_copy_output,
+        ..run_history_entry(connection_id, database, sql, started_at, duration_ms, rows, error)
+    }
+}
+
+/// What the history keeps of a run, given the rows it returned or affected and its first error.
+/// The run is recorded as a script with the default options.
+pub(crate) fn run_history_entry(
+    connection_id: &str,
+    database: &str,
+    sql: &str,
+    started_at: DateTime<Utc>,
+    duration_ms: u64,
+    rows: Option<u64>,
+    error: Option<&CommandError>,
+) -> HistoryEntry {
+    let status = match error.map(CommandError::code) {
+        None => HistoryStatus::Ok,
+        Some(Some("query_cancelled")) => HistoryStatus::Cancelled,
+        Some(_) => HistoryStatus::Error,
+    };
+    HistoryEntry {
+        id: uuid::Uuid::new_v4().to_string(),
+        sql: sql.to_string(),
+        connection_id: connection_id.to_string(),
+        database: database.to_string(),
+        started_at,
+        duration_ms,
+        rows,
+        status,
+        error_code: error.and_then(|e| e.code().map(String::from)),
+        mode: QueryMode::default(),
+        params: Vec::new(),
+        on_error: None,
+        keep_copy_output: false,
+    }
+}
+
+/// Refuse scripts the safety analysis does not find read-only on a read-only connection.
+/// This only catches mistakes early, the server refuses the writes that get through.
+pub(crate) fn check_read_only(read_only: bool, sql: &str) -> Result<(), CommandError> {
+    if read_only && !is_read_only(sql) {
+        return Err(CommandError::with_code(
+            "read_only",
+            "This connection is read-only, only statements reading data are allowed",
+        ));
+    }
+    Ok(())
+}
+
+/// Run a script on the given session, leaving it as the script left it. `in_transaction`
+/// tells that the session is in a transaction block, where statements run with `on_error`
+/// are rolled back to a savepoint when they fail so that the next ones can run.
+pub(crate) async fn run_script(
+    client: &PgClient,
+    sql: &str,
+    options: &ScriptOptions,
+    in_transaction: bool,
+    sink: &mut RowSink<'_>,
+) -> Result<Vec<StatementResult>, CommandError> {
+    let statements = split_statements(sql);
+    // COPY through the client needs a round trip of its own
+    let copies = statements.iter().any(|statement| !matches!(client_copy(&statement.sql), Ok(None)));
+    if options.on_error.is_none() && uses_simple_protocol(options) && !copies {
+        let script = SqlStatement { sql: sql.to_string(), start: 0, end: sql.encode_utf16().count(), copy_data: None };
+        return run_simple(client, sql, statements, Instant::now(), sink).await.map_err(|e| e.locate(sql, &script));
+    }
+    run_statements(client, sql, statements, options, in_transaction, sink).await
+}
+
+/// The simple query protocol has no parameters.
+fn uses_simple_protocol(options: &ScriptOptions) -> bool {
+    matches!(options.mode, QueryMode::Simple) && options.params.is_empty()
+}
+
+/// Run the statements one at a time. With `on_error`, their errors are recorded instead
+/// of failing the script.
+async fn run_statements(
+    client: &PgClient,
+    sql: &str,
+    statements: Vec<SqlStatement>,
+    options: &ScriptOptions,
+    in_transaction: bool,
+    sink: &mut RowSink<'_>,
+) -> Result<Vec<StatementResult>, CommandError> {
+    let savepoints = in_transaction && options.on_error.is_some();
+    let mut results = Vec::new();
+    let mut failed = false;
+    for statement in statements {
+        if failed && matches!(options.on_error, Some(OnError::Stop)) {
+            results.push(StatementResult::not_run(statement, StatementStatus::Skipped, None, 0));
+            continue;
+        }
+
+        let started = Instant::now();
+        // Releasing or rolling back to the savepoint would undo those of the script
+        let savepoint = savepoints && !uses_savepoints(&statement.sql);
+        if savepoint {
+            client.batch_execute("savepoint pgditor_statement").await?;
+        }
+        let result = match client_copy(&statement.sql) {
+            Ok(Some(copy)) => run_copy(client, statement.clone(), copy, options.keep_copy_output, sink)
+                .await
+                .map(|result| vec![result]),
+            Ok(None) if uses_simple_protocol(options) => {
+                run_simple(client, &statement.sql, vec![statement.clone()], started, sink).await
+            }
+            Ok(None) => {
+                run_typed_statement(client, statement.clone(), &options.params, sink).await.map(|result| vec![result])
+            }
+            Err(e) => Err(e),
+        };
+        if savepoint && !client.is_closed() {
+            let end = match result {
+                Ok(_) => "release savepoint pgditor_statement",
+                Err(_) => "rollback to savepoint pgditor_statement",
+            };
+            client.batch_execute(end).await?;
+        }
+        match result.map_err(|e| e.locate(sql, &statement)) {
+            Ok(statement_results) => results.extend(statement_results),
+            Err(e) if options.on_error.is_none() => return Err(e),
+            // Going on makes no sense once the script is cancelled or the connection lost
+            Err(e) if e.code() == Some("57014") || client.is_closed() => return Err(e),
+            Err(e) => {
+                let duration_ms = started.elapsed().as_millis() as u64;
+                sink.fail(&e.to_string(), duration_ms)?;
+                results.push(StatementResult::not_run(statement, StatementStatus::Error, Some(e), duration_ms));
+                failed = true;
+            }
+        }
+    }
+    Ok(results)
+}
 
-    let mut current_rows: Vec<JsonValue> = Vec::new();
-    let mut last_rows: Vec<JsonValue> = Vec::new();
+impl StatementResult {
+    fn not_run(
+        statement: SqlStatement,
+        status: StatementStatus,
+        error: Option<CommandError>,
+        duration_ms: u64,
+    ) -> Self {
+        StatementResult {
+            statement,
+            command_tag: String::new(),
+            rows_affected: None,
+            columns: Vec::new(),
+            rows: Vec::new(),
+            duration_ms,
+            status,
+            error,
+            copy_output: None,
+        }
+    }
+}
+
+/// Messages are timed as they arrive to measure each statement of the script.
+async fn run_simple(
+    client: &PgClient,
+    sql: &str,
+    statements: Vec<SqlStatement>,
+    start: Instant,
+    sink: &mut RowSink<'_>,
+) -> Result<Vec<StatementResult>, CommandError> {
+    let stream = client.simple_query_raw(sql).await?;
+    futures::pin_mut!(stream);
 
-    for message in messages {
+    let mut statements = statements.into_iter();
+    let mut results = Vec::new();
+    let mut columns: Vec<PgResultColumn> = Vec::new();
+    let mut previous_end = start;
+
+    while let Some(message) = stream.try_next().await? {
         match message {
+            SimpleQueryMessage::RowDescription(description) => {
+                columns = description.iter().map(|column| untyped_column(column.name())).collect();
+                sink.columns(columns.clone())?;
+            }
             SimpleQueryMessage::Row(row) => {
                 let mut map = Map::new();
                 for i in 0..row.len() {
@@ -42,17 +380,148 @@ pub async fn raw_query(connection_string: String, sql: String) -> Result<QueryRe
                         value.map(JsonValue::from).unwrap_or(JsonValue::Null),
                     );
                 }
-                current_rows.push(JsonValue::Object(map));
+                sink.push(JsonValue::Object(map)).await?;
             }
-            SimpleQueryMessage::CommandComplete(_) => {
-                last_rows = std::mem::take(&mut current_rows);
+            SimpleQueryMessage::CommandComplete(count) => {
+                // Should the splitter disagree with the server, the extra results get the whole script
+                let statement = statements.next().unwrap_or_else(|| SqlStatement {
+                    sql: sql.to_string(),
+                    start: 0,
+                    end: sql.encode_utf16().count(),
+                    copy_data: None,
+                });
+                let (command_tag, rows_affected) = command_tag(&statement.sql, count);
+                let now = Instant::now();
+                let duration_ms = (now - previous_end).as_millis() as u64;
+                results.push(StatementResult {
+                    rows: sink.finish(&command_tag, rows_affected, duration_ms).await?,
+                    statement,
+                    command_tag,
+                    rows_affected,
+                    columns: std::mem::take(&mut columns),
+                    duration_ms,
+                    status: StatementStatus::Ok,
+                    error: None,
+                    copy_output: None,
+                });
+                previous_end = now;
             }
             _ => {}
         }
     }
+    Ok(results)
+}
 
-    Ok(QueryResponse {
-        rows: last_rows,
-        duration_ms: duration.as_millis() as u64,
+async fn run_typed_statement(
+    client: &PgClient,
+    statement: SqlStatement,
+    params: &[JsonValue],
+    sink: &mut RowSink<'_>,
+) -> Result<StatementResult, CommandError> {
+    let started = Instant::now();
+    let (prepared, columns, sent) = prepare_decodable(client, &statement.sql).await?;
+    if !columns.is_empty() {
+        sink.columns(columns.clone())?;
+    }
+
+    let params = bind_params(prepared.params(), params)?;
+    let sent_in = |e: tokio_postgres::Error| CommandError::from(e).sent_in(&sent, &statement.sql);
+    let stream = client.query_raw(&prepared, params).await.map_err(sent_in)?;
+    futures::pin_mut!(stream);
+    while let Some(row) = stream.try_next().await.map_err(sent_in)? {
+        sink.push(row_to_json(&row)?).await?;
+    }
+    let count = stream.rows_affected().unwrap_or_else(|| sink.rows_read());
+    let (command_tag, rows_affected) = command_tag(&statement.sql, count);
+    let duration_ms = started.elapsed().as_millis() as u64;
+
+    Ok(StatementResult {
+        rows: sink.finish(&command_tag, rows_affected, duration_ms).await?,
+        statement,
+        command_tag,
+        rows_affected,
+        columns,
+        duration_ms,
+        status: StatementStatus::Ok,
+        error: None,
+        copy_output: None,
     })
-}
\ No newline at end of file
+}
+
+/// Run a `COPY` reading or writing its data through the client, or a psql `\copy`.
+async fn run_copy(
+    client: &PgClient,
+    statement: SqlStatement,
+    copy: ClientCopy,
+    keep_copy_output: bool,
+    sink: &mut RowSink<'_>,
+) -> Result<StatementResult, CommandError> {
+    let started = Instant::now();
+    let mut columns = Vec::new();
+    let mut copy_output = None;
+    let rows = match copy {
+        ClientCopy::FromStdin { sql } => copy_in_data(client, &sql, statement.copy_data.as_deref().unwrap_or("")).await?,
+        ClientCopy::FromFile { sql, path } => copy_in_file(client, &sql, &path).await?,
+        ClientCopy::ToFile { sql, path } => copy_out_file(client, &sql, &path).await?,
+        ClientCopy::ToStdout { sql } => {
+            let mut output = copy_out(client, &sql, keep_copy_output).await?;
+            // Without a header, the first row tells how many columns there are
+            let mut fields = output.next_row().await?;
+            let width = match (&output.header, &fields) {
+                (Some(header), _) => header.len(),
+                (None, Some(fields)) => fields.len(),
+                (None, None) => 0,
+            };
+            let names: Vec<String> = (0..width)
+                .map(|i| match output.header.as_ref().and_then(|header| header.get(i)) {
+                    Some(name) => name.clone(),
+                    None => format!("column{}", i + 1),
+                })
+                .collect();
+            columns = names.iter().map(|name| untyped_column(name)).collect();
+            if !columns.is_empty() {
+                sink.columns(columns.clone())?;
+            }
+            let mut rows = 0;
+            while let Some(values) = fields {
+                let row: Map<String, JsonValue> = names
+                    .iter()
+                    .zip(values)
+                    .map(|(name, value)| (name.clone(), value.map_or(JsonValue::Null, JsonValue::String)))
+                    .collect();
+                sink.push(JsonValue::Object(row)).await?;
+                rows += 1;
+                fields = output.next_row().await?;
+            }
+            copy_output = output.text;
+            rows
+        }
+    };
+    let command_tag = format!("COPY {}", rows);
+    let duration_ms = started.elapsed().as_millis() as u64;
+
+    Ok(StatementResult {
+        rows: sink.finish(&command_tag, Some(rows), duration_ms).await?,
+        statement,
+        command_tag,
+        rows_affected: Some(rows),
+        columns,
+        duration_ms,
+        status: StatementStatus::Ok,
+        error: None,
+        copy_output,
+    })
+}
+
+/// A column of a result that comes as text, without type information.
+fn untyped_column(name: &str) -> PgResultColumn {
+    PgResultColumn {
+        name: name.to_string(),
+        type_oid: None,
+        type_name: None,
+        nullable: None,
+        table_oid: None,
+        column_id: None,
+        binary: false,
+    }
+}
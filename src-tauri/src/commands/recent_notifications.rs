@@ -0,0 +1,8 @@
+use crate::pg::listeners::{Listeners, PgNotification};
+use tauri::State;
+
+/// The last notifications received, oldest first, for one connection or all of them.
+#[tauri::command]
+pub fn recent_notifications(listeners: State<'_, Listeners>, connection_id: Option<String>) -> Vec<PgNotification> {
+    listeners.recent(connection_id.as_deref())
+}
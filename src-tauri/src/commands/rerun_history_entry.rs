@@ -0,0 +1,41 @@
+use crate::commands::raw_query::{execute_script, ScriptOptions, ScriptResult};
+use crate::error::CommandError;
+use crate::pg::pg_connect::PgConnectionConfig;
+use crate::pg::pg_pool::PgPools;
+use crate::pg::query_history::QueryHistory;
+use crate::pg::result_stream::RowSink;
+use crate::pg::running_queries::RunningQueries;
+use tauri::State;
+
+/// Run the SQL of a history entry again on its connection, with the options it was run with.
+/// The frontend looks up the connection by the id of the entry since the history keeps no
+/// credentials.
+#[tauri::command]
+pub async fn rerun_history_entry(
+    pools: State<'_, PgPools>,
+    queries: State<'_, RunningQueries>,
+    history: State<'_, QueryHistory>,
+    connection: PgConnectionConfig,
+    entry_id: String,
+    query_id: String,
+    confirmation: Option<String>,
+) -> Result<ScriptResult, CommandError> {
+    let entry = history
+        .get(&entry_id)
+        .ok_or_else(|| CommandError::with_code("history_entry_missing", "This history entry no longer exists"))?;
+    if entry.connection_id != connection.id {
+        return Err(CommandError::with_code(
+            "connection_mismatch",
+            "This history entry was run on another connection",
+        ));
+    }
+    let mut sink = RowSink::collect();
+    let options = ScriptOptions {
+        mode: entry.mode,
+        params: entry.params.clone(),
+        on_error: entry.on_error,
+        confirmation,
+        keep_copy_output: entry.keep_copy_output,
+    };
+    execute_script(&pools, &queries, &history, &connection, &entry.sql, &query_id, &options, &mut sink).await
+}
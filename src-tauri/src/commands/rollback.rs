@@ -0,0 +1,14 @@
+use crate::error::CommandError;
+use crate::pg::transactions::Transactions;
+use tauri::State;
+
+/// Roll back a transaction started with `begin_transaction`, or only what happened
+/// since `savepoint` when given.
+#[tauri::command]
+pub async fn rollback(
+    transactions: State<'_, Transactions>,
+    transaction_id: String,
+    savepoint: Option<String>,
+) -> Result<(), CommandError> {
+    transactions.rollback(&transaction_id, savepoint.as_deref()).await
+}
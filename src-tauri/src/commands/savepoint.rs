@@ -0,0 +1,13 @@
+use crate::error::CommandError;
+use crate::pg::transactions::Transactions;
+use tauri::State;
+
+/// Set a savepoint in a transaction, to roll back to with `rollback`.
+#[tauri::command]
+pub async fn savepoint(
+    transactions: State<'_, Transactions>,
+    transaction_id: String,
+    name: String,
+) -> Result<(), CommandError> {
+    transactions.savepoint(&transaction_id, &name).await
+}
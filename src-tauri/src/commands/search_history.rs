@@ -0,0 +1,8 @@
+use crate::pg::query_history::{HistoryEntry, HistoryFilter, QueryHistory};
+use tauri::State;
+
+/// The scripts run so far matching `filter`, newest first.
+#[tauri::command]
+pub fn search_history(history: State<'_, QueryHistory>, filter: Option<HistoryFilter>) -> Vec<HistoryEntry> {
+    history.search(&filter.unwrap_or_default())
+}
@@ -0,0 +1,40 @@
+use crate::commands::raw_query::{execute_script, OnError, QueryMode, ScriptOptions, ScriptResult};
+use crate::error::CommandError;
+use crate::pg::pg_connect::PgConnectionConfig;
+use crate::pg::pg_pool::PgPools;
+use crate::pg::query_history::QueryHistory;
+use crate::pg::result_stream::{ResultEvent, ResultStreams, RowSink};
+use crate::pg::running_queries::RunningQueries;
+use serde_json::Value as JsonValue;
+use tauri::ipc::Channel;
+use tauri::State;
+
+/// Same as `raw_query`, but the rows are sent over `on_event` in batches instead of
+/// being returned, so the statements of the result have no rows.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn stream_query(
+    pools: State<'_, PgPools>,
+    queries: State<'_, RunningQueries>,
+    streams: State<'_, ResultStreams>,
+    history: State<'_, QueryHistory>,
+    connection: PgConnectionConfig,
+    sql: String,
+    query_id: String,
+    mode: Option<QueryMode>,
+    params: Option<Vec<JsonValue>>,
+    on_error: Option<OnError>,
+    confirmation: Option<String>,
+    keep_copy_output: Option<bool>,
+    on_event: Channel<ResultEvent>,
+) -> Result<ScriptResult, CommandError> {
+    let mut sink = RowSink::stream(&streams, &query_id, on_event);
+    let options = ScriptOptions {
+        mode: mode.unwrap_or_default(),
+        params: params.unwrap_or_default(),
+        on_error,
+        confirmation,
+        keep_copy_output: keep_copy_output.unwrap_or_default(),
+    };
+    execute_script(&pools, &queries, &history, &connection, &sql, &query_id, &options, &mut sink).await
+}
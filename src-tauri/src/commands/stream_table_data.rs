@@ -0,0 +1,48 @@
+use crate::commands::get_table_data::{read_table_data, table_data_sql};
+use crate::error::CommandError;
+use crate::pg::models::PgTableData;
+use crate::pg::pg_connect::PgConnectionConfig;
+use crate::pg::pg_pool::PgPools;
+use crate::pg::result_stream::{ResultEvent, ResultStreams, RowSink};
+use crate::pg::running_queries::RunningQueries;
+use std::time::Instant;
+use tauri::ipc::Channel;
+use tauri::State;
+
+/// Same as `get_table_data`, but the rows are sent over `on_event` in batches and
+/// the returned `rows` is empty.
+///
+/// Unlike `get_table_data` it is not retried when the connection drops, since rows may
+/// already have been sent.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn stream_table_data(
+    pools: State<'_, PgPools>,
+    queries: State<'_, RunningQueries>,
+    streams: State<'_, ResultStreams>,
+    connection: PgConnectionConfig,
+    schema: String,
+    table: String,
+    columns: Option<String>,
+    offset: Option<i64>,
+    limit: Option<i64>,
+    where_clause: Option<String>,
+    order_by: Option<String>,
+    query_id: String,
+    on_event: Channel<ResultEvent>,
+) -> Result<PgTableData, CommandError> {
+    let (select_sql, count_sql) = table_data_sql(&schema, &table, columns, offset, limit, where_clause, order_by);
+    let client = pools.get(&connection).await?;
+
+    let start = Instant::now();
+    let mut sink = RowSink::stream(&streams, &query_id, on_event);
+    let count = queries
+        .run(&query_id, client.canceller(), read_table_data(&client, &select_sql, &count_sql, &mut sink))
+        .await;
+    client.mark_clean();
+    let count = count?;
+    let rows = sink.rows_read();
+    sink.finish(&format!("SELECT {}", rows), Some(rows), start.elapsed().as_millis() as u64).await?;
+
+    Ok(PgTableData { rows: Vec::new(), count })
+}
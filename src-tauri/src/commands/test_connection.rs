@@ -1,8 +1,93 @@
+use std::time::Instant;
+
 use crate::error::CommandError;
-use crate::pg::pg_connect::pg_connect;
+use crate::pg::models::{PgConnectionReport, PgExtension, PgPrivileges, PgTlsInfo};
+use crate::pg::pg_connect::{init_session, pg_connect, PgConnectionConfig};
 
 #[tauri::command]
-pub async fn test_connection(connection_string: String) -> Result<bool, CommandError> {
-    pg_connect(&connection_string).await?;
-    Ok(true)
-}
\ No newline at end of file
+pub async fn test_connection(connection: PgConnectionConfig) -> Result<PgConnectionReport, CommandError> {
+    let started = Instant::now();
+    let (client, driver) = pg_connect(&connection).await?;
+    let connect_ms = started.elapsed().as_secs_f64() * 1000.0;
+    let (host, port) = (driver.host.clone(), driver.port);
+
+    tokio::spawn(async move {
+        if let Err(e) = driver.await_connection(|_| {}).await {
+            eprintln!("DB connection error: {e}");
+        }
+    });
+    init_session(&client, &connection).await?;
+
+    let started = Instant::now();
+    client.simple_query("").await.map_err(CommandError::from)?;
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+    let query = r#"
+        SELECT
+            current_setting('server_version') AS server_version,
+            current_setting('server_version_num')::int AS server_version_num,
+            current_user::text AS user_name,
+            current_database()::text AS database,
+            pg_is_in_recovery() AS in_hot_standby,
+            current_setting('transaction_read_only') = 'on' AS read_only,
+            coalesce(s.ssl, false) AS ssl,
+            s.version AS tls_version,
+            s.cipher AS tls_cipher,
+            s.bits AS tls_bits,
+            r.rolsuper AS is_superuser,
+            has_database_privilege(current_database(), 'CREATE') AS can_create_schema,
+            coalesce(has_schema_privilege(current_schema(), 'CREATE'), false) AS can_create_table,
+            EXISTS (
+                SELECT 1
+                FROM pg_catalog.pg_class c
+                JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace
+                WHERE c.relkind IN ('r', 'p')
+                AND n.nspname NOT IN ('pg_catalog', 'information_schema')
+                AND n.nspname NOT LIKE 'pg_toast%'
+                AND has_table_privilege(c.oid, 'INSERT, UPDATE, DELETE')
+            ) AS can_modify_data
+        FROM pg_catalog.pg_roles r
+        LEFT JOIN pg_catalog.pg_stat_ssl s ON s.pid = pg_backend_pid()
+        WHERE r.rolname = current_user;
+    "#;
+    let row = client.query_one(query, &[]).await.map_err(CommandError::from)?;
+
+    let extensions = client
+        .query("SELECT extname::text, extversion FROM pg_catalog.pg_extension ORDER BY extname", &[])
+        .await
+        .map_err(CommandError::from)?
+        .iter()
+        .map(|row| PgExtension { name: row.get(0), version: row.get(1) })
+        .collect();
+
+    let tls = if row.get("ssl") {
+        Some(PgTlsInfo {
+            version: row.get("tls_version"),
+            cipher: row.get("tls_cipher"),
+            bits: row.get("tls_bits"),
+        })
+    } else {
+        None
+    };
+
+    Ok(PgConnectionReport {
+        host,
+        port,
+        connect_ms,
+        latency_ms,
+        server_version: row.get("server_version"),
+        server_version_num: row.get("server_version_num"),
+        user: row.get("user_name"),
+        database: row.get("database"),
+        in_hot_standby: row.get("in_hot_standby"),
+        read_only: row.get("read_only"),
+        tls,
+        extensions,
+        privileges: PgPrivileges {
+            is_superuser: row.get("is_superuser"),
+            can_create_schema: row.get("can_create_schema"),
+            can_create_table: row.get("can_create_table"),
+            can_modify_data: row.get("can_modify_data"),
+        },
+    })
+}
@@ -0,0 +1,12 @@
+use crate::error::CommandError;
+use crate::pg::listeners::Listeners;
+use tauri::State;
+
+#[tauri::command]
+pub async fn unlisten(
+    listeners: State<'_, Listeners>,
+    connection_id: String,
+    channel: String,
+) -> Result<(), CommandError> {
+    listeners.unlisten(&connection_id, &channel).await
+}
@@ -1,28 +1,137 @@
 use serde::Serialize;
 
+use postgres::error::ErrorPosition;
+
 use crate::pg::pg_connect::PgConnectError;
+use crate::pg::sql_split::{script_position, ScriptPosition, SqlStatement};
+use crate::pg::sql_state::{self, SqlStateClass};
 
 #[derive(Debug, Serialize)]
 pub struct CommandError {
     message: String,
     code: Option<String>,
+    /// Boxed to keep `Result<T, CommandError>` small.
+    #[serde(flatten)]
+    details: Box<ErrorDetails>,
+}
+
+/// What the server reported besides the message, set for database errors only.
+#[derive(Debug, Default, Serialize)]
+struct ErrorDetails {
+    /// `ERROR`, `FATAL` or `PANIC`, never translated.
+    severity: Option<String>,
+    /// The condition name of the SQLSTATE in `code`, like `unique_violation`.
+    code_name: Option<&'static str>,
+    code_class: Option<SqlStateClass>,
     detail: Option<String>,
     hint: Option<String>,
-    position: Option<String>,
+    /// Where the error is in the submitted script, once located with `locate`. Commands
+    /// running queries of their own around the SQL they are given, like `get_table_data`,
+    /// or running no SQL of the user, like `fetch_more`, leave it unset.
+    position: Option<ScriptPosition>,
+    /// The 1-based character position of the error in the query that was sent.
+    query_position: Option<u32>,
+    /// Where the statement starts in the query that was sent, in characters, when it was
+    /// sent inside another one.
+    #[serde(skip)]
+    statement_offset: u32,
+    /// The error is in a query the server ran on its own, like the body of a SQL function.
+    internal_position: Option<InternalPosition>,
+    /// The call stack of functions and statements that led to the error.
+    #[serde(rename = "where")]
+    where_: Option<String>,
+    schema: Option<String>,
+    table: Option<String>,
+    column: Option<String>,
+    datatype: Option<String>,
+    constraint: Option<String>,
+    /// The function of the server source that reported the error.
+    routine: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct InternalPosition {
+    /// 1-based, in characters.
+    position: u32,
+    query: String,
+}
+
+impl CommandError {
+    /// An error raised by pgditor itself, with a lowercase code the frontend can match on.
+    pub fn with_code(code: &str, message: impl Into<String>) -> Self {
+        CommandError {
+            message: message.into(),
+            code: Some(code.to_string()),
+            details: Box::default(),
+        }
+    }
+
+    pub fn code(&self) -> Option<&str> {
+        self.code.as_deref()
+    }
+
+    /// Point the error at its line and column in `script`, given the statement it was sent for.
+    /// Errors outside of the statement, in the query sent around it, are not located.
+    pub fn locate(mut self, script: &str, statement: &SqlStatement) -> Self {
+        let start = self.details.statement_offset;
+        // Syntax errors at the end of the input are just past it
+        let end = start + statement.sql.chars().count() as u32 + 1;
+        if let Some(position) = self.details.query_position.filter(|position| (start + 1..=end).contains(position)) {
+            self.details.position = Some(script_position(script, statement, position - start));
+        }
+        self
+    }
+
+    /// Tell that the error is for `statement` sent inside `query`, like `EXPLAIN` or a cursor
+    /// declaration, for `locate` to find its position in the statement.
+    pub fn sent_in(mut self, query: &str, statement: &str) -> Self {
+        if let Some(start) = query.find(statement) {
+            self.details.statement_offset = query[..start].chars().count() as u32;
+        }
+        self
+    }
 }
 
 impl From<postgres::Error> for CommandError {
     fn from(err: postgres::Error) -> Self {
-        let db_error = err.as_db_error();
+        let Some(db_error) = err.as_db_error() else {
+            return CommandError::from(err.to_string());
+        };
+        let code = db_error.code().code();
+        let (query_position, internal_position) = match db_error.position() {
+            Some(ErrorPosition::Original(position)) => (Some(*position), None),
+            Some(ErrorPosition::Internal { position, query }) => {
+                (None, Some(InternalPosition { position: *position, query: query.clone() }))
+            }
+            None => (None, None),
+        };
 
         CommandError {
-            message: db_error
-                .map(|e| e.message().to_string())
-                .unwrap_or_else(|| err.to_string()),
-            code: db_error.map(|e| e.code().code().to_string()),
-            detail: db_error.and_then(|e| e.detail().map(String::from)),
-            hint: db_error.and_then(|e| e.hint().map(String::from)),
-            position: db_error.and_then(|e| e.position().map(|p| format!("{:?}", p))),
+            message: db_error.message().to_string(),
+            code: Some(code.to_string()),
+            details: Box::new(ErrorDetails {
+                // Unlike `severity`, the parsed one is never translated
+                severity: Some(
+                    db_error
+                        .parsed_severity()
+                        .map_or_else(|| db_error.severity().to_string(), |severity| severity.to_string()),
+                ),
+                code_name: sql_state::condition_name(code),
+                code_class: Some(sql_state::class(code)),
+                detail: db_error.detail().map(String::from),
+                hint: db_error.hint().map(String::from),
+                position: None,
+                query_position,
+                statement_offset: 0,
+                internal_position,
+                where_: db_error.where_().map(String::from),
+                schema: db_error.schema().map(String::from),
+                table: db_error.table().map(String::from),
+                column: db_error.column().map(String::from),
+                datatype: db_error.datatype().map(String::from),
+                constraint: db_error.constraint().map(String::from),
+                routine: db_error.routine().map(String::from),
+            }),
         }
     }
 }
@@ -32,9 +141,7 @@ impl From<String> for CommandError {
         CommandError {
             message,
             code: None,
-            detail: None,
-            hint: None,
-            position: None,
+            details: Box::default(),
         }
     }
 }
@@ -44,9 +151,7 @@ impl From<&str> for CommandError {
         CommandError {
             message: message.to_string(),
             code: None,
-            detail: None,
-            hint: None,
-            position: None,
+            details: Box::default(),
         }
     }
 }
@@ -56,9 +161,7 @@ impl From<serde_json::Error> for CommandError {
         CommandError {
             message: format!("JSON error: {}", err),
             code: None,
-            detail: None,
-            hint: None,
-            position: None,
+            details: Box::default(),
         }
     }
 }
@@ -68,9 +171,7 @@ impl From<tokio::task::JoinError> for CommandError {
         CommandError {
             message: format!("Task execution failed: {}", err),
             code: None,
-            detail: None,
-            hint: None,
-            position: None,
+            details: Box::default(),
         }
     }
 }
@@ -80,9 +181,7 @@ impl From<native_tls::Error> for CommandError {
         CommandError {
             message: format!("TLS error: {}", err),
             code: None,
-            detail: None,
-            hint: None,
-            position: None,
+            details: Box::default(),
         }
     }
 }
@@ -97,10 +196,53 @@ impl From<PgConnectError> for CommandError {
     fn from(err: PgConnectError) -> Self {
         CommandError {
             message: err.message,
-            code: None,
-            detail: None,
-            hint: None,
-            position: None,
+            code: err.kind.code().map(String::from),
+            details: Box::default(),
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pg::sql_split::split_statements;
+
+    fn error_at(query_position: u32) -> CommandError {
+        CommandError {
+            details: Box::new(ErrorDetails { query_position: Some(query_position), ..ErrorDetails::default() }),
+            ..CommandError::from("column \"nope\" does not exist")
+        }
+    }
+
+    fn line_column(error: &CommandError) -> Option<(usize, usize, usize)> {
+        error.details.position.as_ref().map(|position| (position.line, position.column, position.offset))
+    }
+
+    #[test]
+    fn located_in_multi_statement_scripts() {
+        let script = "select 1;\nselect 'é😀', nope;\n";
+        let statements = split_statements(script);
+        // `nope` is the 14th character of the statement, but 😀 takes two UTF-16 code units
+        let error = error_at(14).locate(script, &statements[1]);
+        assert_eq!(line_column(&error), Some((2, 15, 24)));
+    }
+
+    #[test]
+    fn located_in_statements_sent_inside_another() {
+        let script = "select 1;\nselect 'é😀', nope;\n";
+        let statements = split_statements(script);
+        let explain = format!("explain (format json) {}", statements[1].sql);
+        let error = error_at(36).sent_in(&explain, &statements[1].sql).locate(script, &statements[1]);
+        assert_eq!(line_column(&error), Some((2, 15, 24)));
+
+        let error = error_at(3).sent_in(&explain, &statements[1].sql).locate(script, &statements[1]);
+        assert_eq!(line_column(&error), None);
+    }
+
+    #[test]
+    fn located_at_the_end_of_the_input() {
+        let script = "select (";
+        let statements = split_statements(script);
+        assert_eq!(line_column(&error_at(9).locate(script, &statements[0])), Some((1, 9, 8)));
+        assert_eq!(line_column(&error_at(10).locate(script, &statements[0])), None);
+    }
+}
@@ -2,8 +2,15 @@ pub mod commands;
 pub mod error;
 pub mod pg;
 pub mod ai;
-use tauri::Emitter;
+use tauri::{Emitter, Manager};
 use tauri::async_runtime::block_on;
+use pg::cursors::Cursors;
+use pg::listeners::Listeners;
+use pg::pg_pool::{PgPools, EVICTION_INTERVAL, KEEPALIVE_INTERVAL};
+use pg::query_history::QueryHistory;
+use pg::result_stream::ResultStreams;
+use pg::running_queries::RunningQueries;
+use pg::transactions::Transactions;
 
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -18,6 +25,33 @@ pub fn run() {
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_persisted_scope::init())
+        .manage(RunningQueries::default())
+        .manage(ResultStreams::default())
+        .manage(Cursors::default())
+        .setup(|app| {
+            app.manage(PgPools::new(app.handle().clone()));
+            app.manage(Transactions::new(app.handle().clone()));
+            app.manage(Listeners::new(app.handle().clone()));
+            app.manage(QueryHistory::load(app.path().app_data_dir()?.join("query_history.jsonl")));
+
+            let handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                loop {
+                    tokio::time::sleep(EVICTION_INTERVAL).await;
+                    handle.state::<PgPools>().evict_idle();
+                    handle.state::<Cursors>().close_idle().await;
+                    handle.state::<Transactions>().warn_idle();
+                }
+            });
+            let handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                loop {
+                    tokio::time::sleep(KEEPALIVE_INTERVAL).await;
+                    handle.state::<PgPools>().keepalive().await;
+                }
+            });
+            Ok(())
+        })
         .on_menu_event(|app, event| {
             let event_id = event.id.0.as_str();
             if event_id == "new-window" {
@@ -37,6 +71,27 @@ pub fn run() {
             commands::generate_query::generate_query,
             commands::generate_chat_title::generate_chat_title,
             commands::create_new_window::create_new_window,
+            commands::close_connection::close_connection,
+            commands::cancel_query::cancel_query,
+            commands::stream_query::stream_query,
+            commands::stream_table_data::stream_table_data,
+            commands::ack_rows::ack_rows,
+            commands::open_cursor::open_cursor,
+            commands::fetch_more::fetch_more,
+            commands::close_cursor::close_cursor,
+            commands::explain_query::explain_query,
+            commands::begin_transaction::begin_transaction,
+            commands::execute_in_transaction::execute_in_transaction,
+            commands::commit::commit,
+            commands::rollback::rollback,
+            commands::savepoint::savepoint,
+            commands::search_history::search_history,
+            commands::rerun_history_entry::rerun_history_entry,
+            commands::listen::listen,
+            commands::unlisten::unlisten,
+            commands::notify::notify,
+            commands::recent_notifications::recent_notifications,
+            commands::analyze_sql::analyze_sql,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,259 @@
+use std::error::Error;
+use serde_json::Value as JsonValue;
+use bytes::BytesMut;
+use tokio_postgres::types::{to_sql_checked, Format, IsNull, Kind, ToSql, Type};
+
+use crate::error::CommandError;
+
+/// A value bound to a `$n` parameter, converted from JSON for the type the server inferred.
+#[derive(Debug)]
+pub enum BindValue {
+    Null,
+    Bool(bool),
+    Int2(i16),
+    Int4(i32),
+    Int8(i64),
+    Oid(u32),
+    Float4(f32),
+    Float8(f64),
+    Text(String),
+    Json(JsonValue),
+    /// Sent in text format, for the server to parse with the input function of the type.
+    Literal(String),
+}
+
+impl ToSql for BindValue {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        // Domains are encoded like their base type
+        let ty = base_type(ty);
+        match self {
+            BindValue::Null => Ok(IsNull::Yes),
+            BindValue::Bool(value) => value.to_sql(ty, out),
+            BindValue::Int2(value) => value.to_sql(ty, out),
+            BindValue::Int4(value) => value.to_sql(ty, out),
+            BindValue::Int8(value) => value.to_sql(ty, out),
+            BindValue::Oid(value) => value.to_sql(ty, out),
+            BindValue::Float4(value) => value.to_sql(ty, out),
+            BindValue::Float8(value) => value.to_sql(ty, out),
+            BindValue::Text(value) => value.to_sql(ty, out),
+            BindValue::Json(value) => value.to_sql(ty, out),
+            BindValue::Literal(value) => {
+                out.extend_from_slice(value.as_bytes());
+                Ok(IsNull::No)
+            }
+        }
+    }
+
+    fn accepts(_: &Type) -> bool {
+        true
+    }
+
+    fn encode_format(&self, _ty: &Type) -> Format {
+        match self {
+            BindValue::Literal(_) => Format::Text,
+            _ => Format::Binary,
+        }
+    }
+
+    to_sql_checked!();
+}
+
+/// Convert the values given for a statement to the types of its parameters.
+///
+/// Strings are parsed by the server like literals of the parameter type, so dates,
+/// UUIDs or intervals are given in their usual text form. Numbers, booleans, arrays
+/// and objects are checked against the type here.
+pub fn bind_params(types: &[Type], values: &[JsonValue]) -> Result<Vec<BindValue>, CommandError> {
+    if values.len() < types.len() {
+        return Err(CommandError::from(format!(
+            "The statement uses ${} but only {} value{} given",
+            types.len(),
+            values.len(),
+            if values.len() == 1 { " was" } else { "s were" }
+        )));
+    }
+    types
+        .iter()
+        .zip(values)
+        .enumerate()
+        .map(|(i, (ty, value))| {
+            bind_value(ty, value).map_err(|e| {
+                CommandError::from(format!("Invalid value for ${} of type {}: {}", i + 1, ty.name(), e))
+            })
+        })
+        .collect()
+}
+
+fn bind_value(ty: &Type, value: &JsonValue) -> Result<BindValue, String> {
+    let base = base_type(ty);
+    let value = match value {
+        JsonValue::Null => BindValue::Null,
+        JsonValue::String(text) if is_text_type(base) => BindValue::Text(text.clone()),
+        JsonValue::String(text) => BindValue::Literal(text.clone()),
+        JsonValue::Bool(value) => match *base {
+            Type::BOOL => BindValue::Bool(*value),
+            Type::JSON | Type::JSONB => BindValue::Json(JsonValue::Bool(*value)),
+            _ => return Err("a boolean was given".to_string()),
+        },
+        JsonValue::Number(number) => match *base {
+            Type::INT2 => BindValue::Int2(integer(number)?),
+            Type::INT4 => BindValue::Int4(integer(number)?),
+            Type::INT8 => BindValue::Int8(integer(number)?),
+            Type::OID | Type::XID | Type::CID | Type::REGCLASS | Type::REGPROC | Type::REGTYPE => {
+                BindValue::Oid(integer(number)?)
+            }
+            Type::FLOAT4 => BindValue::Float4(number.as_f64().unwrap_or_default() as f32),
+            Type::FLOAT8 => BindValue::Float8(number.as_f64().unwrap_or_default()),
+            Type::NUMERIC | Type::MONEY => BindValue::Literal(number.to_string()),
+            Type::JSON | Type::JSONB => BindValue::Json(JsonValue::Number(number.clone())),
+            _ if is_text_type(base) => BindValue::Text(number.to_string()),
+            _ => return Err("a number was given".to_string()),
+        },
+        JsonValue::Array(_) | JsonValue::Object(_) => match base.kind() {
+            _ if matches!(*base, Type::JSON | Type::JSONB) => BindValue::Json(value.clone()),
+            Kind::Array(_) if value.is_array() => BindValue::Literal(array_literal(value)),
+            _ if value.is_array() => return Err("an array was given".to_string()),
+            _ => return Err("an object was given".to_string()),
+        },
+    };
+    Ok(value)
+}
+
+fn integer<T: TryFrom<i64>>(number: &serde_json::Number) -> Result<T, String> {
+    number
+        .as_i64()
+        .and_then(|value| T::try_from(value).ok())
+        .ok_or_else(|| format!("{} is not an integer in range", number))
+}
+
+fn is_text_type(ty: &Type) -> bool {
+    matches!(*ty, Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME | Type::UNKNOWN)
+}
+
+fn base_type(ty: &Type) -> &Type {
+    match ty.kind() {
+        Kind::Domain(base) => base_type(base),
+        _ => ty,
+    }
+}
+
+/// The text form of an array, like `{1,"a b",NULL}`. Nested arrays are sub-arrays.
+fn array_literal(value: &JsonValue) -> String {
+    match value {
+        JsonValue::Array(items) => format!("{{{}}}", items.iter().map(array_literal).collect::<Vec<_>>().join(",")),
+        JsonValue::Null => "NULL".to_string(),
+        JsonValue::Bool(value) => value.to_string(),
+        JsonValue::Number(number) => number.to_string(),
+        JsonValue::String(text) => quote_array_element(text),
+        JsonValue::Object(_) => quote_array_element(&value.to_string()),
+    }
+}
+
+fn quote_array_element(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn bind(ty: Type, value: JsonValue) -> Result<BindValue, String> {
+        bind_params(&[ty], &[value]).map(|mut values| values.remove(0)).map_err(|e| e.to_string())
+    }
+
+    fn encoded(ty: Type, value: JsonValue) -> Vec<u8> {
+        let mut out = BytesMut::new();
+        bind(ty.clone(), value).unwrap().to_sql(&ty, &mut out).unwrap();
+        out.to_vec()
+    }
+
+    #[test]
+    fn integers_out_of_range() {
+        assert!(matches!(bind(Type::INT2, json!(32767)), Ok(BindValue::Int2(32767))));
+        assert_eq!(
+            bind(Type::INT2, json!(32768)).unwrap_err(),
+            "Invalid value for $1 of type int2: 32768 is not an integer in range"
+        );
+        assert!(matches!(bind(Type::INT4, json!(-2147483648)), Ok(BindValue::Int4(i32::MIN))));
+        assert!(bind(Type::INT4, json!(3_000_000_000u64)).is_err());
+        assert!(bind(Type::INT8, json!(u64::MAX)).is_err());
+        assert!(bind(Type::INT8, json!(1.5)).is_err());
+        assert!(bind(Type::OID, json!(-1)).is_err());
+
+        let small = Type::new("small".to_string(), 1, Kind::Domain(Type::INT2), "public".to_string());
+        assert!(bind(small, json!(100_000)).is_err());
+    }
+
+    #[test]
+    fn strings_are_literals() {
+        assert!(matches!(bind(Type::NUMERIC, json!("12.50")), Ok(BindValue::Literal(text)) if text == "12.50"));
+        assert!(matches!(bind(Type::NUMERIC, json!(12.5)), Ok(BindValue::Literal(text)) if text == "12.5"));
+        assert!(matches!(bind(Type::INT4, json!("42")), Ok(BindValue::Literal(text)) if text == "42"));
+        assert!(matches!(bind(Type::TEXT, json!(42)), Ok(BindValue::Text(text)) if text == "42"));
+        assert!(matches!(bind(Type::VARCHAR, json!("42")), Ok(BindValue::Text(text)) if text == "42"));
+        assert!(matches!(bind(Type::INT4, json!(true)), Err(e) if e.ends_with("a boolean was given")));
+
+        let literal = bind(Type::NUMERIC, json!("1e3")).unwrap();
+        assert!(matches!(literal.encode_format(&Type::NUMERIC), Format::Text));
+        assert!(matches!(bind(Type::INT4, json!(1)).unwrap().encode_format(&Type::INT4), Format::Binary));
+    }
+
+    #[test]
+    fn json_and_jsonb() {
+        assert_eq!(encoded(Type::JSON, json!({"a": [1, true]})), b"{\"a\":[1,true]}");
+        // Binary jsonb starts with its format version
+        assert_eq!(encoded(Type::JSONB, json!({"a": [1, true]})), b"\x01{\"a\":[1,true]}");
+        assert_eq!(encoded(Type::JSONB, json!(1.5)), b"\x011.5");
+        assert_eq!(encoded(Type::JSON, json!(false)), b"false");
+        // Strings are the JSON text, not a JSON string
+        assert!(matches!(bind(Type::JSONB, json!("[1, 2]")), Ok(BindValue::Literal(text)) if text == "[1, 2]"));
+    }
+
+    #[test]
+    fn arrays() {
+        let literal = |ty: Type, value: JsonValue| match bind(ty, value) {
+            Ok(BindValue::Literal(text)) => text,
+            other => panic!("not a literal: {:?}", other),
+        };
+        assert_eq!(literal(Type::INT4_ARRAY, json!([1, null, 3])), "{1,NULL,3}");
+        assert_eq!(literal(Type::INT4_ARRAY, json!([[1, 2], [3, 4]])), "{{1,2},{3,4}}");
+        assert_eq!(
+            literal(Type::TEXT_ARRAY, json!(["a b", "q\"uote", "back\\slash", "NULL"])),
+            r#"{"a b","q\"uote","back\\slash","NULL"}"#
+        );
+        assert_eq!(literal(Type::JSONB_ARRAY, json!([{"a": 1}])), r#"{"{\"a\":1}"}"#);
+        assert_eq!(literal(Type::BOOL_ARRAY, json!([])), "{}");
+        assert!(matches!(bind(Type::INT4, json!([1])), Err(e) if e.ends_with("an array was given")));
+        assert!(matches!(bind(Type::INT4_ARRAY, json!({"a": 1})), Err(e) if e.ends_with("an object was given")));
+    }
+
+    #[test]
+    fn nulls() {
+        let types = [
+            Type::BOOL, Type::INT2, Type::INT4, Type::INT8, Type::OID, Type::FLOAT4, Type::FLOAT8, Type::NUMERIC,
+            Type::TEXT, Type::JSON, Type::JSONB, Type::UUID, Type::TIMESTAMPTZ, Type::INT4_ARRAY, Type::TEXT_ARRAY,
+        ];
+        for ty in types {
+            let value = bind(ty.clone(), JsonValue::Null).unwrap();
+            assert!(matches!(value, BindValue::Null), "{}", ty);
+            assert!(matches!(value.to_sql(&ty, &mut BytesMut::new()), Ok(IsNull::Yes)), "{}", ty);
+        }
+    }
+
+    #[test]
+    fn parameter_count() {
+        let types = [Type::INT4, Type::TEXT];
+        assert_eq!(
+            bind_params(&types, &[json!(1)]).unwrap_err().to_string(),
+            "The statement uses $2 but only 1 value was given"
+        );
+        assert_eq!(
+            bind_params(&types, &[]).unwrap_err().to_string(),
+            "The statement uses $2 but only 0 values were given"
+        );
+        // The values are shared by the statements of a script, some may use fewer
+        assert_eq!(bind_params(&types, &[json!(1), json!("a"), json!(true)]).unwrap().len(), 2);
+        assert!(bind_params(&[], &[json!(1)]).unwrap().is_empty());
+    }
+}
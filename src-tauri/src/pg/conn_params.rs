@@ -0,0 +1,237 @@
+use std::path::PathBuf;
+use percent_encoding::percent_decode_str;
+use tokio_postgres::Config;
+
+use crate::pg::pg_connect::{PgConnectError, PgConnectErrorKind};
+
+/// Parameters understood by libpq but not by `tokio_postgres::Config`.
+/// They are handled by pgditor itself and never forwarded to the driver.
+const LIBPQ_ONLY_PARAMS: &[&str] = &[
+    "sslmode",
+    "sslrootcert",
+    "sslcert",
+    "sslkey",
+    "sslpassword",
+    "sslcrl",
+    "sslcrldir",
+    "sslsni",
+    "sslcompression",
+    "ssl_min_protocol_version",
+    "ssl_max_protocol_version",
+    "requiressl",
+    "gssencmode",
+    "krbsrvname",
+    "gsslib",
+    "requirepeer",
+    "fallback_application_name",
+    "service",
+    "servicefile",
+    "passfile",
+    "target_session_attrs",
+];
+
+/// Connection parameters parsed from a libpq connection string, in either the
+/// `key=value` form or the `postgresql://` URI form.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConnParams {
+    params: Vec<(String, String)>,
+}
+
+impl ConnParams {
+    pub fn parse(connection_string: &str) -> Result<Self, PgConnectError> {
+        let s = connection_string.trim();
+        if let Some(rest) = s.strip_prefix("postgresql://").or_else(|| s.strip_prefix("postgres://")) {
+            Self::parse_uri(rest)
+        } else {
+            Self::parse_key_value(s)
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.params.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    /// Set a parameter, replacing any previous value.
+    pub fn set(&mut self, key: &str, value: impl Into<String>) {
+        let value = value.into();
+        match self.params.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value,
+            None => self.params.push((key.to_string(), value)),
+        }
+    }
+
+    /// Append `-c name=value` to the startup `options`, so the setting becomes the
+    /// session default and survives `RESET ALL`.
+    pub fn add_option(&mut self, name: &str, value: &str) {
+        let escaped = value.replace('\\', "\\\\").replace(' ', "\\ ");
+        let option = format!("-c {}={}", name, escaped);
+        match self.get("options").filter(|options| !options.trim().is_empty()) {
+            Some(options) => self.set("options", format!("{} {}", options, option)),
+            None => self.set("options", option),
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let i = self.params.iter().position(|(k, _)| k == key)?;
+        Some(self.params.remove(i).1)
+    }
+
+    /// Build the driver config from every parameter `tokio_postgres` understands.
+    pub fn to_pg_config(&self) -> Result<Config, PgConnectError> {
+        let mut forwarded: Vec<String> = Vec::new();
+        for (key, value) in &self.params {
+            if LIBPQ_ONLY_PARAMS.contains(&key.as_str()) {
+                continue;
+            }
+            forwarded.push(format!("{}='{}'", key, value.replace('\\', "\\\\").replace('\'', "\\'")));
+        }
+        if self.get("application_name").is_none() {
+            if let Some(fallback) = self.get("fallback_application_name") {
+                forwarded.push(format!("application_name='{}'", fallback.replace('\\', "\\\\").replace('\'', "\\'")));
+            }
+        }
+
+        forwarded.join(" ").parse::<Config>().map_err(|e| PgConnectError {
+            kind: PgConnectErrorKind::Config,
+            message: format!("Invalid connection string: {}", e),
+        })
+    }
+
+    fn parse_key_value(s: &str) -> Result<Self, PgConnectError> {
+        let mut params = ConnParams::default();
+        let mut chars = s.chars().peekable();
+
+        loop {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            if chars.peek().is_none() {
+                break;
+            }
+
+            let mut key = String::new();
+            while let Some(c) = chars.next_if(|c| *c != '=' && !c.is_whitespace()) {
+                key.push(c);
+            }
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            if chars.next() != Some('=') {
+                return Err(invalid(format!("missing \"=\" after \"{}\"", key)));
+            }
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+            let mut value = String::new();
+            if chars.next_if_eq(&'\'').is_some() {
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some('\\') => value.extend(chars.next()),
+                        Some(c) => value.push(c),
+                        None => return Err(invalid(format!("unterminated quoted value for \"{}\"", key))),
+                    }
+                }
+            } else {
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    if c == '\\' {
+                        value.extend(chars.next());
+                    } else {
+                        value.push(c);
+                    }
+                }
+            }
+            params.set(&key, value);
+        }
+
+        Ok(params)
+    }
+
+    fn parse_uri(s: &str) -> Result<Self, PgConnectError> {
+        let mut params = ConnParams::default();
+
+        let (rest, query) = match s.split_once('?') {
+            Some((rest, query)) => (rest, Some(query)),
+            None => (s, None),
+        };
+        let (authority, dbname) = match rest.split_once('/') {
+            Some((authority, dbname)) => (authority, Some(dbname)),
+            None => (rest, None),
+        };
+        let hostspec = match authority.rsplit_once('@') {
+            Some((userspec, hostspec)) => {
+                let (user, password) = match userspec.split_once(':') {
+                    Some((user, password)) => (user, Some(password)),
+                    None => (userspec, None),
+                };
+                if !user.is_empty() {
+                    params.set("user", decode(user)?);
+                }
+                if let Some(password) = password {
+                    params.set("password", decode(password)?);
+                }
+                hostspec
+            }
+            None => authority,
+        };
+
+        let mut hosts = Vec::new();
+        let mut ports = Vec::new();
+        for spec in hostspec.split(',') {
+            let (host, port) = if let Some(bracketed) = spec.strip_prefix('[') {
+                let (host, after) = bracketed
+                    .split_once(']')
+                    .ok_or_else(|| invalid(format!("unterminated IPv6 host \"{}\"", spec)))?;
+                (host, after.strip_prefix(':').unwrap_or(""))
+            } else {
+                match spec.rsplit_once(':') {
+                    Some((host, port)) => (host, port),
+                    None => (spec, ""),
+                }
+            };
+            hosts.push(decode(host)?);
+            ports.push(decode(port)?);
+        }
+        if hosts.iter().any(|host| !host.is_empty()) {
+            params.set("host", hosts.join(","));
+        }
+        if ports.iter().any(|port| !port.is_empty()) {
+            params.set("port", ports.join(","));
+        }
+
+        if let Some(dbname) = dbname.filter(|dbname| !dbname.is_empty()) {
+            params.set("dbname", decode(dbname)?);
+        }
+
+        for pair in query.unwrap_or("").split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("missing \"=\" in URI parameter \"{}\"", pair)))?;
+            let (key, value) = (decode(key)?, decode(value)?);
+            // JDBC-style `ssl=true` is accepted by libpq as an alias of `sslmode=require`
+            if key == "ssl" && value == "true" {
+                params.set("sslmode", "require");
+            } else {
+                params.set(&key, value);
+            }
+        }
+
+        Ok(params)
+    }
+}
+
+/// Directory where libpq looks for per-user files such as `root.crt`:
+/// `~/.postgresql` on Unix and `%APPDATA%\postgresql` on Windows.
+pub fn libpq_user_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        std::env::var_os("APPDATA").map(|dir| PathBuf::from(dir).join("postgresql"))
+    } else {
+        std::env::var_os("HOME").map(|dir| PathBuf::from(dir).join(".postgresql"))
+    }
+}
+
+fn decode(s: &str) -> Result<String, PgConnectError> {
+    percent_decode_str(s)
+        .decode_utf8()
+        .map(|s| s.into_owned())
+        .map_err(|_| invalid(format!("invalid percent-encoded token \"{}\"", s)))
+}
+
+fn invalid(reason: String) -> PgConnectError {
+    PgConnectError { kind: PgConnectErrorKind::Config, message: format!("Invalid connection string: {}", reason) }
+}
@@ -0,0 +1,536 @@
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use bytes::{Bytes, BytesMut};
+use futures::{SinkExt, TryStreamExt};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_postgres::{Client as PgClient, CopyOutStream};
+
+use crate::error::CommandError;
+
+/// Inline data and files are sent to the server in chunks of this size.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A statement copying data through the client.
+#[derive(Debug)]
+pub enum ClientCopy {
+    /// `COPY ... FROM STDIN`, with the data following it in the script.
+    FromStdin { sql: String },
+    /// `COPY ... TO STDOUT`, returned as a result.
+    ToStdout { sql: String },
+    /// psql's `\copy ... FROM 'file'`, sent as a `COPY ... FROM STDIN`.
+    FromFile { sql: String, path: PathBuf },
+    /// psql's `\copy ... TO 'file'`, sent as a `COPY ... TO STDOUT`.
+    ToFile { sql: String, path: PathBuf },
+}
+
+/// How a statement copies data through the client, `None` for the statements that do
+/// not, including a `COPY` reading or writing a file on the server.
+///
+/// psql meta-commands other than `\copy` are refused.
+pub fn client_copy(sql: &str) -> Result<Option<ClientCopy>, CommandError> {
+    if let Some(meta_command) = sql.strip_prefix('\\') {
+        let name: String = meta_command.chars().take_while(|c| !c.is_whitespace()).collect();
+        if name != "copy" {
+            return Err(CommandError::from(format!(
+                "\\{} is a psql meta-command, only \\copy is supported",
+                name
+            )));
+        }
+        return local_copy(&meta_command[name.len()..]).map(Some);
+    }
+
+    let tokens = tokenize(sql);
+    if !tokens.first().is_some_and(|token| token.is_word("copy")) {
+        return Ok(None);
+    }
+    let Some(target) = target_index(&tokens) else {
+        return Ok(None);
+    };
+    let copy = match (&tokens[target - 1].kind, &tokens[target].kind) {
+        (Kind::Word(direction), Kind::Word(target)) if direction == "from" && target == "stdin" => {
+            ClientCopy::FromStdin { sql: sql.to_string() }
+        }
+        (Kind::Word(direction), Kind::Word(target)) if direction == "to" && target == "stdout" => {
+            ClientCopy::ToStdout { sql: sql.to_string() }
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(copy))
+}
+
+/// Turn the arguments of `\copy` into a `COPY` through the client, like psql does.
+fn local_copy(args: &str) -> Result<ClientCopy, CommandError> {
+    let tokens = tokenize(args);
+    let Some(target) = target_index(&tokens) else {
+        return Err(CommandError::from("\\copy needs FROM or TO followed by a file name"));
+    };
+    let table = args[..tokens[target - 1].start].trim();
+    let from = tokens[target - 1].is_word("from");
+    let (file, rest) = match &tokens[target].kind {
+        Kind::String(file) => (file.clone(), &args[tokens[target].end..]),
+        // Unquoted file names go up to the next space
+        _ => {
+            let start = tokens[target].start;
+            let end = args[start..].find(char::is_whitespace).map_or(args.len(), |end| start + end);
+            (args[start..end].to_string(), &args[end..])
+        }
+    };
+    let options = rest.trim();
+    let sql = |target: &str| format!("COPY {} {} {}", table, target, options).trim_end().to_string();
+
+    let path = match file.to_ascii_lowercase().as_str() {
+        "stdin" | "pstdin" if from => return Ok(ClientCopy::FromStdin { sql: sql("FROM STDIN") }),
+        "stdout" | "pstdout" if !from => return Ok(ClientCopy::ToStdout { sql: sql("TO STDOUT") }),
+        "program" => return Err(CommandError::from("\\copy with a program is not supported")),
+        _ => local_path(&file)?,
+    };
+    Ok(if from {
+        ClientCopy::FromFile { sql: sql("FROM STDIN"), path }
+    } else {
+        ClientCopy::ToFile { sql: sql("TO STDOUT"), path }
+    })
+}
+
+/// The files of `\copy` have no working directory to be relative to, so they must be
+/// absolute or start with `~/`.
+fn local_path(file: &str) -> Result<PathBuf, CommandError> {
+    if let Some(rest) = file.strip_prefix("~/") {
+        let home = std::env::var_os(if cfg!(windows) { "USERPROFILE" } else { "HOME" })
+            .ok_or_else(|| CommandError::from("Could not find the home directory"))?;
+        return Ok(PathBuf::from(home).join(rest));
+    }
+    let path = PathBuf::from(file);
+    if !path.is_absolute() {
+        return Err(CommandError::from(format!("\\copy needs an absolute file path, not {}", file)));
+    }
+    Ok(path)
+}
+
+/// Stream the data following a `COPY ... FROM STDIN` in the script to the server,
+/// returning the number of rows copied.
+pub async fn copy_in_data(client: &PgClient, sql: &str, data: &str) -> Result<u64, CommandError> {
+    let sink = client.copy_in::<_, Bytes>(sql).await?;
+    futures::pin_mut!(sink);
+    for chunk in data.as_bytes().chunks(CHUNK_SIZE) {
+        sink.send(Bytes::copy_from_slice(chunk)).await?;
+    }
+    Ok(sink.finish().await?)
+}
+
+/// Stream a local file to a `COPY ... FROM STDIN`, returning the number of rows copied.
+pub async fn copy_in_file(client: &PgClient, sql: &str, path: &Path) -> Result<u64, CommandError> {
+    let file_error = |e: std::io::Error| CommandError::from(format!("Could not read {}: {}", path.display(), e));
+    let mut file = File::open(path).await.map_err(file_error)?;
+    let sink = client.copy_in::<_, Bytes>(sql).await?;
+    futures::pin_mut!(sink);
+    loop {
+        let mut chunk = BytesMut::with_capacity(CHUNK_SIZE);
+        // Dropping the sink on error aborts the copy
+        if file.read_buf(&mut chunk).await.map_err(file_error)? == 0 {
+            break;
+        }
+        sink.send(chunk.freeze()).await?;
+    }
+    Ok(sink.finish().await?)
+}
+
+/// The rows of a running `COPY ... TO STDOUT`, read as the server sends them.
+pub struct CopyOut {
+    format: CopyFormat,
+    stream: Pin<Box<CopyOutStream>>,
+    /// From the header line, when the statement asked for one.
+    pub header: Option<Vec<String>>,
+    /// The data as the server sent it, to save as a file. Only kept when asked for.
+    pub text: Option<String>,
+}
+
+impl CopyOut {
+    /// The fields of the next row, `None` for nulls.
+    pub async fn next_row(&mut self) -> Result<Option<Vec<Option<String>>>, CommandError> {
+        // The server sends each row in a message of its own
+        let Some(message) = self.stream.try_next().await? else {
+            return Ok(None);
+        };
+        let line = String::from_utf8_lossy(&message);
+        if let Some(text) = &mut self.text {
+            text.push_str(&line);
+        }
+        Ok(Some(self.format.parse_row(&line)))
+    }
+}
+
+/// Start a `COPY ... TO STDOUT` in text or CSV format and read its header, if it has one.
+/// With `keep_text`, what the server sends is also kept to be saved as a file.
+pub async fn copy_out(client: &PgClient, sql: &str, keep_text: bool) -> Result<CopyOut, CommandError> {
+    let format = CopyFormat::parse(sql);
+    if format.kind == FormatKind::Binary {
+        return Err(CommandError::from("Binary COPY output can only be saved to a file, with \\copy ... TO 'file'"));
+    }
+    let stream = Box::pin(client.copy_out(sql).await?);
+    let has_header = format.header;
+    let mut output = CopyOut { format, stream, header: None, text: keep_text.then(String::new) };
+    if has_header {
+        output.header = output
+            .next_row()
+            .await?
+            .map(|fields| fields.into_iter().map(Option::unwrap_or_default).collect());
+    }
+    Ok(output)
+}
+
+/// Write the output of a `COPY ... TO STDOUT` to a local file, returning the number
+/// of rows written.
+pub async fn copy_out_file(client: &PgClient, sql: &str, path: &Path) -> Result<u64, CommandError> {
+    let format = CopyFormat::parse(sql);
+    let file_error = |e: std::io::Error| CommandError::from(format!("Could not write {}: {}", path.display(), e));
+    let mut file = File::create(path).await.map_err(file_error)?;
+    let stream = client.copy_out(sql).await?;
+    futures::pin_mut!(stream);
+
+    let mut messages = 0u64;
+    while let Some(message) = stream.try_next().await? {
+        file.write_all(&message).await.map_err(file_error)?;
+        messages += 1;
+    }
+    file.flush().await.map_err(file_error)?;
+    // The header of the binary format comes with the first row, and its trailer alone
+    Ok(match format.kind {
+        FormatKind::Binary => messages.saturating_sub(1),
+        _ if format.header => messages.saturating_sub(1),
+        _ => messages,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FormatKind {
+    Text,
+    Csv,
+    Binary,
+}
+
+/// The options of a `COPY` needed to read its output. A custom `NULL` or `QUOTE` is
+/// not taken into account.
+struct CopyFormat {
+    kind: FormatKind,
+    delimiter: char,
+    header: bool,
+}
+
+impl CopyFormat {
+    /// Read the options following the target of a `COPY`, in the `WITH (FORMAT csv, HEADER)`
+    /// form or the older `CSV HEADER` one.
+    fn parse(sql: &str) -> Self {
+        let tokens = tokenize(sql);
+        let options = target_index(&tokens).map_or(&[][..], |target| &tokens[target + 1..]);
+
+        let mut kind = FormatKind::Text;
+        let mut delimiter = None;
+        let mut header = false;
+        for (i, token) in options.iter().enumerate() {
+            let next = options.get(i + 1).map(|token| &token.kind);
+            match &token.kind {
+                Kind::Word(word) if word == "csv" => kind = FormatKind::Csv,
+                Kind::Word(word) if word == "binary" => kind = FormatKind::Binary,
+                Kind::Word(word) if word == "header" => {
+                    header = !matches!(next, Some(Kind::Word(value)) if ["false", "off", "0"].contains(&value.as_str()));
+                }
+                Kind::Word(word) if word == "delimiter" => {
+                    if let Some(Kind::String(value)) = next {
+                        delimiter = value.chars().next();
+                    }
+                }
+                _ => {}
+            }
+        }
+        let default_delimiter = if kind == FormatKind::Csv { ',' } else { '\t' };
+        CopyFormat { kind, delimiter: delimiter.unwrap_or(default_delimiter), header }
+    }
+
+    /// Split a row of output into its fields.
+    fn parse_row(&self, line: &str) -> Vec<Option<String>> {
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        match self.kind {
+            FormatKind::Csv => parse_csv_row(line, self.delimiter),
+            _ => line
+                .split(self.delimiter)
+                .map(|field| (field != "\\N").then(|| unescape_text_field(field)))
+                .collect(),
+        }
+    }
+}
+
+/// Unquoted empty fields are nulls, quoted ones are empty strings.
+fn parse_csv_row(line: &str, delimiter: char) -> Vec<Option<String>> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let (mut in_quotes, mut quoted) = (false, false);
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => {
+                in_quotes = !in_quotes;
+                quoted = true;
+            }
+            c if c == delimiter && !in_quotes => {
+                fields.push((quoted || !field.is_empty()).then(|| std::mem::take(&mut field)));
+                quoted = false;
+            }
+            c => field.push(c),
+        }
+    }
+    fields.push((quoted || !field.is_empty()).then_some(field));
+    fields
+}
+
+/// Undo the backslash escapes of the text format, like `\t` or `\x41`.
+fn unescape_text_field(field: &str) -> String {
+    if !field.contains('\\') {
+        return field.to_string();
+    }
+    let mut text = String::with_capacity(field.len());
+    let mut chars = field.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        let Some(escaped) = chars.next() else {
+            text.push('\\');
+            break;
+        };
+        let (radix, max_digits, first) = match escaped {
+            'b' => (0, 0, '\u{8}'),
+            'f' => (0, 0, '\u{c}'),
+            'n' => (0, 0, '\n'),
+            'r' => (0, 0, '\r'),
+            't' => (0, 0, '\t'),
+            'v' => (0, 0, '\u{b}'),
+            '0'..='7' => (8, 2, escaped),
+            'x' if chars.peek().is_some_and(char::is_ascii_hexdigit) => (16, 2, '0'),
+            other => (0, 0, other),
+        };
+        if radix == 0 {
+            text.push(first);
+            continue;
+        }
+        let mut value = first.to_digit(radix).unwrap_or(0);
+        for _ in 0..max_digits {
+            match chars.peek().and_then(|c| c.to_digit(radix)) {
+                Some(digit) => {
+                    value = value * radix + digit;
+                    chars.next();
+                }
+                None => break,
+            }
+        }
+        text.push(char::from_u32(value).unwrap_or(char::REPLACEMENT_CHARACTER));
+    }
+    text
+}
+
+#[derive(Debug)]
+enum Kind {
+    /// A keyword, identifier or number, lowercased.
+    Word(String),
+    /// The content of a `'...'` string.
+    String(String),
+    QuotedIdentifier,
+    Other(char),
+}
+
+#[derive(Debug)]
+struct Token {
+    kind: Kind,
+    /// Byte offsets in the statement.
+    start: usize,
+    end: usize,
+    /// Parentheses the token is in.
+    depth: usize,
+}
+
+impl Token {
+    fn is_word(&self, word: &str) -> bool {
+        matches!(&self.kind, Kind::Word(w) if w == word)
+    }
+}
+
+/// The index of the token after the `FROM` or `TO` of a `COPY`, not counting the
+/// ones in the query of `COPY (query) TO`.
+fn target_index(tokens: &[Token]) -> Option<usize> {
+    let direction = (1..tokens.len())
+        .find(|&i| tokens[i].depth == 0 && (tokens[i].is_word("from") || tokens[i].is_word("to")))?;
+    (direction + 1 < tokens.len()).then_some(direction + 1)
+}
+
+fn tokenize(sql: &str) -> Vec<Token> {
+    let bytes = sql.as_bytes();
+    let mut tokens = Vec::new();
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let kind = match bytes[i] {
+            c if c.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                i = sql[i..].find('\n').map_or(bytes.len(), |end| i + end + 1);
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = sql[i..].find("*/").map_or(bytes.len(), |end| i + end + 2);
+                continue;
+            }
+            quote @ (b'\'' | b'"') => {
+                let mut text = String::new();
+                i += 1;
+                while i < bytes.len() {
+                    let end = sql[i..].find(quote as char).map_or(bytes.len(), |end| i + end);
+                    text.push_str(&sql[i..end]);
+                    i = end + 1;
+                    // A doubled quote stands for itself
+                    if bytes.get(i) != Some(&quote) {
+                        break;
+                    }
+                    text.push(quote as char);
+                    i += 1;
+                }
+                i = i.min(bytes.len());
+                if quote == b'\'' {
+                    Kind::String(text)
+                } else {
+                    Kind::QuotedIdentifier
+                }
+            }
+            c if c == b'_' || c.is_ascii_alphanumeric() || c >= 0x80 => {
+                i = (i..bytes.len())
+                    .find(|&end| !(bytes[end] == b'_' || bytes[end].is_ascii_alphanumeric() || bytes[end] >= 0x80))
+                    .unwrap_or(bytes.len());
+                Kind::Word(sql[start..i].to_ascii_lowercase())
+            }
+            c => {
+                i += 1;
+                Kind::Other(c as char)
+            }
+        };
+        let token_depth = depth;
+        match kind {
+            Kind::Other('(') => depth += 1,
+            Kind::Other(')') => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        // Parentheses are at the depth of what surrounds them
+        let token_depth = token_depth.min(depth);
+        tokens.push(Token { kind, start, end: i, depth: token_depth });
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(values: &[Option<&str>]) -> Vec<Option<String>> {
+        values.iter().map(|value| value.map(String::from)).collect()
+    }
+
+    #[test]
+    fn csv_rows() {
+        assert_eq!(parse_csv_row(r#"a,"b,c",,"""#, ','), fields(&[Some("a"), Some("b,c"), None, Some("")]));
+        assert_eq!(parse_csv_row(r#""say ""hi""",x"#, ','), fields(&[Some(r#"say "hi""#), Some("x")]));
+        assert_eq!(parse_csv_row("1;two words;", ';'), fields(&[Some("1"), Some("two words"), None]));
+        assert_eq!(parse_csv_row("", ','), fields(&[None]));
+    }
+
+    #[test]
+    fn text_fields() {
+        assert_eq!(unescape_text_field("plain"), "plain");
+        assert_eq!(unescape_text_field(r"a\tb\nc\\d"), "a\tb\nc\\d");
+        assert_eq!(unescape_text_field(r"\b\f\r\v"), "\u{8}\u{c}\r\u{b}");
+        assert_eq!(unescape_text_field(r"\x41\x4a2"), "AJ2");
+        assert_eq!(unescape_text_field(r"\101\7\0"), "A\u{7}\0");
+        // Unknown escapes stand for the character itself
+        assert_eq!(unescape_text_field(r"\xZ\q"), "xZq");
+        assert_eq!(unescape_text_field("trailing\\"), "trailing\\");
+    }
+
+    #[test]
+    fn rows_of_each_format() {
+        let text = CopyFormat::parse("copy t to stdout");
+        assert_eq!((text.kind, text.delimiter, text.header), (FormatKind::Text, '\t', false));
+        assert_eq!(text.parse_row("1\t\\N\ta\\tb\r\n"), fields(&[Some("1"), None, Some("a\tb")]));
+        // `\N` only stands for null as a whole field
+        assert_eq!(text.parse_row("\\\\N"), fields(&[Some("\\N")]));
+
+        let csv = CopyFormat::parse("copy (select 1) to stdout with (format csv, header, delimiter ';')");
+        assert_eq!((csv.kind, csv.delimiter, csv.header), (FormatKind::Csv, ';', true));
+        assert_eq!(csv.parse_row("1;;\"\"\n"), fields(&[Some("1"), None, Some("")]));
+
+        let csv = CopyFormat::parse("copy t to stdout csv header");
+        assert_eq!((csv.kind, csv.delimiter, csv.header), (FormatKind::Csv, ',', true));
+        assert!(!CopyFormat::parse("copy t to stdout (format csv, header false)").header);
+        assert_eq!(CopyFormat::parse("copy t to stdout (format binary)").kind, FormatKind::Binary);
+    }
+
+    #[test]
+    fn copies_through_the_client() {
+        assert!(matches!(client_copy("copy t from stdin"), Ok(Some(ClientCopy::FromStdin { .. }))));
+        assert!(matches!(
+            client_copy("copy (select 'to' from t where a = 'from') to stdout with csv"),
+            Ok(Some(ClientCopy::ToStdout { .. }))
+        ));
+        assert!(matches!(client_copy("COPY t TO STDOUT"), Ok(Some(ClientCopy::ToStdout { .. }))));
+        // Files on the server are read and written by the server
+        assert!(matches!(client_copy("copy t to '/tmp/t.csv'"), Ok(None)));
+        assert!(matches!(client_copy("copy t from '/tmp/t.csv'"), Ok(None)));
+        assert!(matches!(client_copy("select 'copy t from stdin'"), Ok(None)));
+        assert_eq!(
+            client_copy("\\dt public.*").unwrap_err().to_string(),
+            "\\dt is a psql meta-command, only \\copy is supported"
+        );
+    }
+
+    #[test]
+    fn local_copies() {
+        let copy = |sql: &str| client_copy(sql).map(Option::unwrap).map_err(|e| e.to_string());
+
+        let Ok(ClientCopy::FromFile { sql, path }) = copy("\\copy t (a, b) from '/tmp/it''s.csv' with csv header")
+        else {
+            panic!("not a copy from a file");
+        };
+        assert_eq!(sql, "COPY t (a, b) FROM STDIN with csv header");
+        assert_eq!(path, PathBuf::from("/tmp/it's.csv"));
+
+        let Ok(ClientCopy::ToFile { sql, path }) = copy("\\copy (select 1 from t) to /tmp/out.txt") else {
+            panic!("not a copy to a file");
+        };
+        assert_eq!(sql, "COPY (select 1 from t) TO STDOUT");
+        assert_eq!(path, PathBuf::from("/tmp/out.txt"));
+
+        let home = std::env::var_os(if cfg!(windows) { "USERPROFILE" } else { "HOME" }).unwrap();
+        let Ok(ClientCopy::ToFile { path, .. }) = copy("\\copy t to '~/t.csv' csv") else {
+            panic!("not a copy to a file");
+        };
+        assert_eq!(path, PathBuf::from(home).join("t.csv"));
+
+        let Ok(ClientCopy::FromStdin { sql }) = copy("\\copy t from stdin") else {
+            panic!("not a copy from stdin");
+        };
+        assert_eq!(sql, "COPY t FROM STDIN");
+        let Ok(ClientCopy::ToStdout { sql }) = copy("\\copy t to pstdout csv") else {
+            panic!("not a copy to stdout");
+        };
+        assert_eq!(sql, "COPY t TO STDOUT csv");
+
+        assert_eq!(copy("\\copy t from program 'ls'").unwrap_err(), "\\copy with a program is not supported");
+        assert_eq!(copy("\\copy t to out.csv").unwrap_err(), "\\copy needs an absolute file path, not out.csv");
+        assert_eq!(copy("\\copy t").unwrap_err(), "\\copy needs FROM or TO followed by a file name");
+    }
+}
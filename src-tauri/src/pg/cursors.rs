@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+
+use crate::error::CommandError;
+use crate::pg::models::PgResultColumn;
+use crate::pg::pg_connect::QueryCanceller;
+use crate::pg::pg_pool::PooledClient;
+use crate::pg::value_codec::{result_columns, row_to_json, text_columns_query};
+
+/// Cursors not fetched from for longer than this are closed by `close_idle`.
+const CURSOR_IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Each cursor has a session of its own, so the name never clashes.
+const CURSOR_NAME: &str = "pgditor_cursor";
+
+/// A page of rows read from a cursor.
+#[derive(Debug, Serialize)]
+pub struct CursorPage {
+    pub cursor_id: String,
+    pub columns: Vec<PgResultColumn>,
+    pub rows: Vec<JsonValue>,
+    /// Rows fetched from the cursor so far, including this page.
+    pub fetched_rows: u64,
+    /// `false` once the result is exhausted, at which point the cursor is closed.
+    pub has_more: bool,
+}
+
+struct Cursor {
+    connection_id: String,
+    canceller: QueryCanceller,
+    state: tokio::sync::Mutex<CursorState>,
+}
+
+struct CursorState {
+    /// Pinned to the cursor until it is closed, with its transaction open.
+    client: PooledClient,
+    columns: Vec<PgResultColumn>,
+    fetched_rows: u64,
+    last_used: Instant,
+}
+
+/// Tauri-managed registry of the server-side cursors opened by `open_cursor`.
+#[derive(Default)]
+pub struct Cursors {
+    cursors: Mutex<HashMap<String, Arc<Cursor>>>,
+}
+
+impl Cursors {
+    /// Declare a cursor for the single statement `sql` in a new transaction on `client`,
+    /// keeping the session for later fetches. The errors can be located in `sql`.
+    pub async fn open(&self, mut client: PooledClient, connection_id: &str, sql: &str) -> Result<String, CommandError> {
+        let columns = async {
+            client.batch_execute("begin").await?;
+            let prepared = client.prepare(sql).await?;
+            let mut columns = result_columns(&client, prepared.columns()).await?;
+            let declared = match text_columns_query(sql, prepared.columns()) {
+                Some(text_sql) => {
+                    columns.iter_mut().for_each(|column| column.binary = false);
+                    text_sql
+                }
+                None => sql.to_string(),
+            };
+            // The extended protocol refuses anything but a single statement
+            let declare = format!("declare {} no scroll cursor for {}", CURSOR_NAME, declared);
+            client.execute(&declare, &[]).await.map_err(|e| CommandError::from(e).sent_in(&declare, sql))?;
+            Ok(columns)
+        }
+        .await;
+        let columns = match columns {
+            Ok(columns) => columns,
+            Err(e) => {
+                client.reset_session().await;
+                return Err(e);
+            }
+        };
+
+        let cursor_id = uuid::Uuid::new_v4().to_string();
+        let cursor = Cursor {
+            connection_id: connection_id.to_string(),
+            canceller: client.canceller(),
+            state: tokio::sync::Mutex::new(CursorState { client, columns, fetched_rows: 0, last_used: Instant::now() }),
+        };
+        self.cursors.lock().unwrap().insert(cursor_id.clone(), Arc::new(cursor));
+        Ok(cursor_id)
+    }
+
+    /// Fetch the next `count` rows. The cursor is closed once exhausted or if the fetch fails.
+    pub async fn fetch(&self, cursor_id: &str, count: u32) -> Result<CursorPage, CommandError> {
+        let cursor = self.get(cursor_id)?;
+        let mut cursor = cursor.state.lock().await;
+        cursor.last_used = Instant::now();
+
+        let fetch = format!("fetch forward {} from {}", count, CURSOR_NAME);
+        let result = async {
+            let rows = cursor.client.query(&fetch, &[]).await?;
+            rows.iter().map(row_to_json).collect::<Result<Vec<_>, CommandError>>()
+        }
+        .await;
+
+        let rows = match result {
+            Ok(rows) => rows,
+            Err(e) => {
+                drop(cursor);
+                self.close(cursor_id).await;
+                return Err(e);
+            }
+        };
+        cursor.fetched_rows += rows.len() as u64;
+        let page = CursorPage {
+            cursor_id: cursor_id.to_string(),
+            columns: cursor.columns.clone(),
+            has_more: rows.len() as u64 == count as u64,
+            rows,
+            fetched_rows: cursor.fetched_rows,
+        };
+        if !page.has_more {
+            drop(cursor);
+            self.close(cursor_id).await;
+        }
+        Ok(page)
+    }
+
+    /// The cancel handle of the session the cursor runs on.
+    pub fn canceller(&self, cursor_id: &str) -> Result<QueryCanceller, CommandError> {
+        Ok(self.get(cursor_id)?.canceller.clone())
+    }
+
+    /// Close the cursor and give its session back to the pool. Returns `false` if it was already closed.
+    pub async fn close(&self, cursor_id: &str) -> bool {
+        let Some(cursor) = self.cursors.lock().unwrap().remove(cursor_id) else {
+            return false;
+        };
+        let mut cursor = cursor.state.lock().await;
+        // Rolling back the transaction also closes the cursor
+        cursor.client.reset_session().await;
+        true
+    }
+
+    /// Close the cursors of a connection, when it is closed.
+    pub async fn close_connection(&self, connection_id: &str) {
+        let cursor_ids: Vec<String> = self
+            .cursors
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, cursor)| cursor.connection_id == connection_id)
+            .map(|(cursor_id, _)| cursor_id.clone())
+            .collect();
+        for cursor_id in cursor_ids {
+            self.close(&cursor_id).await;
+        }
+    }
+
+    /// Close the cursors nobody fetched from recently.
+    pub async fn close_idle(&self) {
+        let idle: Vec<String> = self
+            .cursors
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, cursor)| {
+                cursor.state.try_lock().is_ok_and(|cursor| cursor.last_used.elapsed() >= CURSOR_IDLE_TIMEOUT)
+            })
+            .map(|(cursor_id, _)| cursor_id.clone())
+            .collect();
+        for cursor_id in idle {
+            self.close(&cursor_id).await;
+        }
+    }
+
+    fn get(&self, cursor_id: &str) -> Result<Arc<Cursor>, CommandError> {
+        self.cursors
+            .lock()
+            .unwrap()
+            .get(cursor_id)
+            .cloned()
+            .ok_or_else(|| CommandError::with_code("cursor_closed", "This cursor was closed, run the query again"))
+    }
+}
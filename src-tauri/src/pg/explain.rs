@@ -0,0 +1,452 @@
+use std::collections::HashMap;
+use serde::Serialize;
+use serde_json::{Map, Value as JsonValue};
+
+use crate::error::CommandError;
+
+/// Sequential scans reading at least this many rows are flagged.
+const BIG_TABLE_ROWS: f64 = 100_000.0;
+
+/// Nested loops whose row estimate is off by this factor or more are flagged.
+const MISESTIMATE_FACTOR: f64 = 10.0;
+
+/// Nodes taking at least this share of the time (or cost without ANALYZE) are hot.
+const HOT_NODE_SHARE: f64 = 0.1;
+
+const MAX_HOT_NODES: usize = 3;
+
+/// A parsed `EXPLAIN (FORMAT JSON)` output.
+#[derive(Debug, Serialize)]
+pub struct ExplainResult {
+    pub plan: PlanNode,
+    pub analyzed: bool,
+    pub planning_time_ms: Option<f64>,
+    pub execution_time_ms: Option<f64>,
+    pub triggers: Vec<TriggerTime>,
+    /// Settings affecting the plan that differ from their default.
+    pub settings: Map<String, JsonValue>,
+    /// Ids of the hot nodes, the hottest first.
+    pub hottest: Vec<usize>,
+    /// The output of the server as is, for other plan viewers.
+    pub raw: JsonValue,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlanNode {
+    /// Position of the node in a depth-first walk of the tree, starting at 0 for the root.
+    pub id: usize,
+    pub node_type: String,
+    pub parent_relationship: Option<String>,
+    pub schema: Option<String>,
+    pub relation: Option<String>,
+    pub alias: Option<String>,
+    pub index_name: Option<String>,
+    pub join_type: Option<String>,
+    pub startup_cost: f64,
+    pub total_cost: f64,
+    /// Cost of the node without the cost of its children.
+    pub self_cost: f64,
+    /// Rows the planner expected per loop.
+    pub estimated_rows: f64,
+    /// Rows returned per loop, with ANALYZE.
+    pub actual_rows: Option<f64>,
+    pub loops: Option<f64>,
+    /// Time spent in the node and its children over all loops, in milliseconds.
+    pub total_time_ms: Option<f64>,
+    /// Time spent in the node itself over all loops, in milliseconds.
+    pub self_time_ms: Option<f64>,
+    /// How many times more (or fewer) rows came back than estimated, always `>= 1`.
+    pub misestimate: Option<f64>,
+    /// Blocks used by the node and its children, with BUFFERS.
+    pub buffers: Option<PlanBuffers>,
+    pub hot: bool,
+    pub problems: Vec<PlanProblem>,
+    /// The other properties of the node, like `Filter` or `Sort Key`.
+    pub details: Map<String, JsonValue>,
+    pub children: Vec<PlanNode>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlanBuffers {
+    pub shared_hit: u64,
+    pub shared_read: u64,
+    pub shared_dirtied: u64,
+    pub shared_written: u64,
+    pub local_hit: u64,
+    pub local_read: u64,
+    pub temp_read: u64,
+    pub temp_written: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TriggerTime {
+    pub name: String,
+    pub relation: Option<String>,
+    pub calls: u64,
+    pub time_ms: f64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PlanProblem {
+    /// A sequential scan reading many rows, an index may be missing.
+    SeqScanOnBigTable { rows: f64 },
+    /// A nested loop run on a bad row estimate, which usually means many more loops than planned.
+    NestedLoopMisestimate { factor: f64 },
+    /// A sort that did not fit in `work_mem` and used temporary files.
+    SortSpilled { method: String, space_kb: u64 },
+    /// A hash that did not fit in `work_mem` and was split in batches.
+    HashSpilled { batches: u64 },
+}
+
+impl ExplainResult {
+    /// Parse the single row returned by `EXPLAIN (FORMAT JSON)`.
+    pub fn parse(raw: JsonValue) -> Result<Self, CommandError> {
+        let output = raw
+            .get(0)
+            .and_then(JsonValue::as_object)
+            .ok_or_else(|| CommandError::from("Unexpected EXPLAIN output"))?;
+        let plan = output
+            .get("Plan")
+            .and_then(JsonValue::as_object)
+            .ok_or_else(|| CommandError::from("The EXPLAIN output has no plan"))?;
+
+        let mut next_id = 0;
+        let plan = PlanNode::parse(plan, 1.0, &mut next_id);
+        let triggers = output
+            .get("Triggers")
+            .and_then(JsonValue::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(JsonValue::as_object)
+            .map(|trigger| TriggerTime {
+                name: string(trigger, "Trigger Name").unwrap_or_default(),
+                relation: string(trigger, "Relation"),
+                calls: number(trigger, "Calls").unwrap_or_default() as u64,
+                time_ms: number(trigger, "Time").unwrap_or_default(),
+            })
+            .collect();
+
+        Ok(ExplainResult {
+            analyzed: plan.loops.is_some(),
+            planning_time_ms: output.get("Planning Time").and_then(JsonValue::as_f64),
+            execution_time_ms: output.get("Execution Time").and_then(JsonValue::as_f64),
+            settings: output.get("Settings").and_then(JsonValue::as_object).cloned().unwrap_or_default(),
+            triggers,
+            plan,
+            hottest: Vec::new(),
+            raw,
+        })
+    }
+
+    /// The `(schema, relation)` of every sequential scan, to look up the size of the tables.
+    pub fn seq_scan_relations(&self) -> Vec<(String, String)> {
+        let mut relations = Vec::new();
+        self.plan.walk(&mut |node| {
+            if let (true, Some(schema), Some(relation)) = (node.is_seq_scan(), &node.schema, &node.relation) {
+                relations.push((schema.clone(), relation.clone()));
+            }
+        });
+        relations
+    }
+
+    /// Flag the hot nodes and the problems of the plan. `table_rows` holds the estimated
+    /// row count of the scanned tables, keyed by `(schema, relation)`.
+    pub fn flag_problems(&mut self, table_rows: &HashMap<(String, String), f64>) {
+        self.plan.flag_problems(table_rows);
+
+        let mut nodes = Vec::new();
+        self.plan.walk(&mut |node| {
+            let weight = if self.analyzed { node.self_time_ms.unwrap_or_default() } else { node.self_cost };
+            nodes.push((node.id, weight));
+        });
+        let total: f64 = nodes.iter().map(|(_, weight)| weight).sum();
+        nodes.sort_by(|a, b| b.1.total_cmp(&a.1));
+        self.hottest = nodes
+            .into_iter()
+            .take(MAX_HOT_NODES)
+            .filter(|(_, weight)| total > 0.0 && weight / total >= HOT_NODE_SHARE)
+            .map(|(id, _)| id)
+            .collect();
+
+        let hottest = &self.hottest;
+        self.plan.walk_mut(&mut |node| node.hot = hottest.contains(&node.id));
+    }
+}
+
+impl PlanNode {
+    /// `processes` is the number of processes sharing the loops of the node: the workers of
+    /// a parallel plan each run their own loops, at the same time.
+    fn parse(node: &Map<String, JsonValue>, processes: f64, next_id: &mut usize) -> Self {
+        let id = *next_id;
+        *next_id += 1;
+
+        let field = |key: &str| number(node, key);
+        let node_type = string(node, "Node Type").unwrap_or_default();
+
+        let child_processes = match node_type.as_str() {
+            "Gather" | "Gather Merge" => field("Workers Launched").unwrap_or_default() + 1.0,
+            _ => processes,
+        };
+        let children: Vec<PlanNode> = node
+            .get("Plans")
+            .and_then(JsonValue::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(JsonValue::as_object)
+            .map(|child| PlanNode::parse(child, child_processes, next_id))
+            .collect();
+
+        let total_cost = field("Total Cost").unwrap_or_default();
+        let children_cost: f64 = children.iter().map(|child| child.total_cost).sum();
+        let estimated_rows = field("Plan Rows").unwrap_or_default();
+        let actual_rows = field("Actual Rows");
+        let loops = field("Actual Loops");
+
+        let total_time_ms = field("Actual Total Time").zip(loops).map(|(time, loops)| time * loops / processes);
+        let self_time_ms = total_time_ms.map(|total| {
+            let children_time: f64 = children.iter().filter_map(|child| child.total_time_ms).sum();
+            (total - children_time).max(0.0)
+        });
+        // Nodes that never ran have nothing to compare
+        let misestimate = actual_rows.filter(|_| loops.is_some_and(|loops| loops > 0.0)).map(|actual| {
+            let (actual, estimated) = (actual.max(1.0), estimated_rows.max(1.0));
+            actual.max(estimated) / actual.min(estimated)
+        });
+        let buffers = node.contains_key("Shared Hit Blocks").then(|| PlanBuffers {
+            shared_hit: field("Shared Hit Blocks").unwrap_or_default() as u64,
+            shared_read: field("Shared Read Blocks").unwrap_or_default() as u64,
+            shared_dirtied: field("Shared Dirtied Blocks").unwrap_or_default() as u64,
+            shared_written: field("Shared Written Blocks").unwrap_or_default() as u64,
+            local_hit: field("Local Hit Blocks").unwrap_or_default() as u64,
+            local_read: field("Local Read Blocks").unwrap_or_default() as u64,
+            temp_read: field("Temp Read Blocks").unwrap_or_default() as u64,
+            temp_written: field("Temp Written Blocks").unwrap_or_default() as u64,
+        });
+
+        const PARSED: &[&str] = &[
+            "Node Type", "Parent Relationship", "Schema", "Relation Name", "Alias", "Index Name", "Join Type",
+            "Startup Cost", "Total Cost", "Plan Rows", "Actual Rows", "Actual Loops", "Actual Total Time", "Plans",
+            "Shared Hit Blocks", "Shared Read Blocks", "Shared Dirtied Blocks", "Shared Written Blocks",
+            "Local Hit Blocks", "Local Read Blocks", "Temp Read Blocks", "Temp Written Blocks",
+        ];
+        let details = node
+            .iter()
+            .filter(|(key, _)| !PARSED.contains(&key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+
+        PlanNode {
+            id,
+            parent_relationship: string(node, "Parent Relationship"),
+            schema: string(node, "Schema"),
+            relation: string(node, "Relation Name"),
+            alias: string(node, "Alias"),
+            index_name: string(node, "Index Name"),
+            join_type: string(node, "Join Type"),
+            startup_cost: field("Startup Cost").unwrap_or_default(),
+            total_cost,
+            self_cost: (total_cost - children_cost).max(0.0),
+            estimated_rows,
+            actual_rows,
+            loops,
+            total_time_ms,
+            self_time_ms,
+            misestimate,
+            buffers,
+            hot: false,
+            problems: Vec::new(),
+            details,
+            children,
+            node_type,
+        }
+    }
+
+    fn is_seq_scan(&self) -> bool {
+        matches!(self.node_type.as_str(), "Seq Scan" | "Parallel Seq Scan")
+    }
+
+    fn detail(&self, key: &str) -> Option<&JsonValue> {
+        self.details.get(key)
+    }
+
+    fn flag_problems(&mut self, table_rows: &HashMap<(String, String), f64>) {
+        for child in &mut self.children {
+            child.flag_problems(table_rows);
+        }
+
+        if self.is_seq_scan() {
+            let table = self.schema.clone().zip(self.relation.clone()).and_then(|key| table_rows.get(&key).copied());
+            let removed = self.detail("Rows Removed by Filter").and_then(JsonValue::as_f64).unwrap_or_default();
+            let read = self.actual_rows.zip(self.loops).map(|(rows, loops)| (rows + removed) * loops);
+            let rows = table.unwrap_or_default().max(read.unwrap_or_default());
+            if rows >= BIG_TABLE_ROWS {
+                self.problems.push(PlanProblem::SeqScanOnBigTable { rows });
+            }
+        }
+
+        if self.node_type == "Nested Loop" {
+            // The outer side decides how many times the inner side runs
+            let factor = self
+                .children
+                .first()
+                .and_then(|outer| outer.misestimate)
+                .into_iter()
+                .chain(self.misestimate)
+                .fold(1.0, f64::max);
+            if factor >= MISESTIMATE_FACTOR {
+                self.problems.push(PlanProblem::NestedLoopMisestimate { factor });
+            }
+        }
+
+        let sort_method = self.detail("Sort Method").and_then(JsonValue::as_str).unwrap_or_default().to_string();
+        let sort_on_disk = self.detail("Sort Space Type").and_then(JsonValue::as_str) == Some("Disk");
+        if sort_on_disk || sort_method.starts_with("external") {
+            let space_kb = self.detail("Sort Space Used").and_then(JsonValue::as_u64).unwrap_or_default();
+            self.problems.push(PlanProblem::SortSpilled { method: sort_method, space_kb });
+        }
+
+        let batches = self.detail("Hash Batches").and_then(JsonValue::as_u64).unwrap_or_default();
+        if batches > 1 {
+            self.problems.push(PlanProblem::HashSpilled { batches });
+        }
+    }
+
+    fn walk(&self, visit: &mut impl FnMut(&PlanNode)) {
+        visit(self);
+        for child in &self.children {
+            child.walk(visit);
+        }
+    }
+
+    fn walk_mut(&mut self, visit: &mut impl FnMut(&mut PlanNode)) {
+        visit(self);
+        for child in &mut self.children {
+            child.walk_mut(visit);
+        }
+    }
+}
+
+fn string(object: &Map<String, JsonValue>, key: &str) -> Option<String> {
+    object.get(key).and_then(JsonValue::as_str).map(str::to_string)
+}
+
+fn number(object: &Map<String, JsonValue>, key: &str) -> Option<f64> {
+    object.get(key).and_then(JsonValue::as_f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `EXPLAIN (FORMAT JSON, VERBOSE, SETTINGS, ANALYZE, BUFFERS)` output of Postgres 15
+    const SEQ_SCAN: &str = include_str!("../../tests/fixtures/explain/seq_scan.json");
+    const PARALLEL_SEQ_SCAN: &str = include_str!("../../tests/fixtures/explain/parallel_seq_scan.json");
+    const NESTED_LOOP: &str = include_str!("../../tests/fixtures/explain/nested_loop.json");
+    const SORT_SPILL: &str = include_str!("../../tests/fixtures/explain/sort_spill.json");
+    const HASH_SPILL: &str = include_str!("../../tests/fixtures/explain/hash_spill.json");
+
+    fn explain(output: &str, table_rows: &[(&str, &str, f64)]) -> ExplainResult {
+        let mut result = ExplainResult::parse(serde_json::from_str(output).unwrap()).unwrap();
+        let table_rows = table_rows
+            .iter()
+            .map(|(schema, relation, rows)| ((schema.to_string(), relation.to_string()), *rows))
+            .collect();
+        result.flag_problems(&table_rows);
+        result
+    }
+
+    /// The problems of the plan with the type of their node, in depth-first order.
+    fn problems(result: &ExplainResult) -> Vec<(String, String)> {
+        let mut problems = Vec::new();
+        result.plan.walk(&mut |node| {
+            for problem in &node.problems {
+                problems.push((node.node_type.clone(), format!("{:?}", problem)));
+            }
+        });
+        problems
+    }
+
+    #[test]
+    fn seq_scan_on_big_table() {
+        let result = explain(SEQ_SCAN, &[]);
+        assert!(result.analyzed);
+        assert!(result.planning_time_ms.is_some() && result.execution_time_ms.is_some());
+        assert_eq!(result.settings["max_parallel_workers_per_gather"], "0");
+        assert_eq!(result.seq_scan_relations(), [("fixture".to_string(), "orders".to_string())]);
+        assert!(result.plan.buffers.as_ref().is_some_and(|buffers| buffers.shared_hit > 0));
+        assert_eq!(result.plan.detail("Filter").unwrap(), "(orders.amount = 42)");
+        // The rows removed by the filter were read too
+        assert_eq!(problems(&result), [("Seq Scan".to_string(), "SeqScanOnBigTable { rows: 300000.0 }".to_string())]);
+        assert_eq!(result.hottest, [0]);
+        assert!(result.plan.hot);
+
+        // The statistics of the table are used when they are bigger
+        let result = explain(SEQ_SCAN, &[("fixture", "orders", 1_000_000.0)]);
+        assert!(matches!(result.plan.problems[..], [PlanProblem::SeqScanOnBigTable { rows }] if rows == 1_000_000.0));
+    }
+
+    #[test]
+    fn parallel_seq_scan() {
+        let result = explain(PARALLEL_SEQ_SCAN, &[]);
+        assert_eq!(result.plan.node_type, "Gather");
+        let scan = &result.plan.children[0];
+        assert_eq!(scan.loops, Some(2.0));
+        // Each process reads its share of the rows, at the same time as the other
+        let (scan_time, gather_time) = (scan.total_time_ms.unwrap(), result.plan.total_time_ms.unwrap());
+        assert!(scan_time <= gather_time, "{} > {}", scan_time, gather_time);
+        assert_eq!(problems(&result), [("Seq Scan".to_string(), "SeqScanOnBigTable { rows: 300000.0 }".to_string())]);
+    }
+
+    #[test]
+    fn nested_loop_misestimate() {
+        let result = explain(NESTED_LOOP, &[]);
+        assert_eq!(result.plan.node_type, "Nested Loop");
+        let [PlanProblem::NestedLoopMisestimate { factor }] = result.plan.problems[..] else {
+            panic!("{:?}", result.plan.problems);
+        };
+        // 19 rows were expected from the outer side, 1000 came back
+        assert!((52.0..53.0).contains(&factor), "{}", factor);
+        let inner = &result.plan.children[1];
+        assert_eq!((inner.node_type.as_str(), inner.loops), ("Index Scan", Some(1000.0)));
+        assert_eq!(inner.misestimate, Some(1.0));
+        assert_eq!(problems(&result).len(), 1);
+    }
+
+    #[test]
+    fn sort_spilled() {
+        let result = explain(SORT_SPILL, &[]);
+        assert_eq!(result.settings["work_mem"], "64kB");
+        assert_eq!(
+            problems(&result),
+            [
+                ("Sort".to_string(), r#"SortSpilled { method: "external merge", space_kb: 6504 }"#.to_string()),
+                ("Seq Scan".to_string(), "SeqScanOnBigTable { rows: 300000.0 }".to_string()),
+            ]
+        );
+        let buffers = result.plan.buffers.as_ref().unwrap();
+        assert!(buffers.temp_written > 0 && buffers.temp_read > 0);
+    }
+
+    #[test]
+    fn hash_spilled() {
+        let result = explain(HASH_SPILL, &[]);
+        assert_eq!(result.plan.node_type, "Hash Join");
+        assert_eq!(
+            problems(&result),
+            [
+                ("Seq Scan".to_string(), "SeqScanOnBigTable { rows: 300000.0 }".to_string()),
+                ("Hash".to_string(), "HashSpilled { batches: 256 }".to_string()),
+                ("Seq Scan".to_string(), "SeqScanOnBigTable { rows: 300000.0 }".to_string()),
+            ]
+        );
+        assert_eq!(result.seq_scan_relations().len(), 2);
+        assert!(!result.hottest.is_empty() && result.hottest.len() <= MAX_HOT_NODES);
+    }
+
+    #[test]
+    fn unexpected_output() {
+        assert!(ExplainResult::parse(serde_json::json!({"Plan": {}})).is_err());
+        assert!(ExplainResult::parse(serde_json::json!([{"Planning Time": 0.1}])).is_err());
+    }
+}
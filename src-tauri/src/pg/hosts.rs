@@ -0,0 +1,276 @@
+use crate::pg::conn_params::ConnParams;
+use crate::pg::pg_connect::{PgConnectError, PgConnectErrorKind};
+
+/// The libpq `target_session_attrs` values, see
+/// https://www.postgresql.org/docs/current/libpq-connect.html#LIBPQ-CONNECT-TARGET-SESSION-ATTRS
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TargetSessionAttrs {
+    Any,
+    ReadWrite,
+    ReadOnly,
+    Primary,
+    Standby,
+    PreferStandby,
+}
+
+impl TargetSessionAttrs {
+    pub fn from_params(params: &ConnParams) -> Result<Self, PgConnectError> {
+        match params.get("target_session_attrs").unwrap_or("any") {
+            "any" => Ok(TargetSessionAttrs::Any),
+            "read-write" => Ok(TargetSessionAttrs::ReadWrite),
+            "read-only" => Ok(TargetSessionAttrs::ReadOnly),
+            "primary" => Ok(TargetSessionAttrs::Primary),
+            "standby" => Ok(TargetSessionAttrs::Standby),
+            "prefer-standby" => Ok(TargetSessionAttrs::PreferStandby),
+            value => Err(PgConnectError {
+                kind: PgConnectErrorKind::Config,
+                message: format!("Invalid target_session_attrs value: \"{}\"", value),
+            }),
+        }
+    }
+
+    /// The requirement of each pass over the hosts: `prefer-standby` first looks
+    /// for a standby and then accepts any server.
+    pub fn passes(self) -> Vec<TargetSessionAttrs> {
+        match self {
+            TargetSessionAttrs::PreferStandby => vec![TargetSessionAttrs::Standby, TargetSessionAttrs::Any],
+            attrs => vec![attrs],
+        }
+    }
+
+    /// Whether the session defaults to read-only needs to be known to check the server.
+    pub fn checks_read_only(self, in_hot_standby: bool, read_only_connection: bool) -> bool {
+        matches!(self, TargetSessionAttrs::ReadWrite | TargetSessionAttrs::ReadOnly)
+            && !in_hot_standby
+            && !read_only_connection
+    }
+
+    /// Why a server does not match, if it does not. `default_read_only` only matters when
+    /// [`checks_read_only`](Self::checks_read_only) says so.
+    ///
+    /// Read-only connections make every session read-only, so for them `read-write` looks for
+    /// a server that is not in hot standby and `read-only` accepts any server.
+    pub fn mismatch(
+        self,
+        in_hot_standby: bool,
+        default_read_only: bool,
+        read_only_connection: bool,
+    ) -> Option<&'static str> {
+        let read_only = if in_hot_standby {
+            true
+        } else if read_only_connection {
+            self == TargetSessionAttrs::ReadOnly
+        } else {
+            default_read_only
+        };
+        match self {
+            TargetSessionAttrs::ReadWrite if read_only => Some("session is read-only"),
+            TargetSessionAttrs::ReadOnly if !read_only => Some("session is not read-only"),
+            TargetSessionAttrs::Primary if in_hot_standby => Some("server is in hot standby mode"),
+            TargetSessionAttrs::Standby if !in_hot_standby => Some("server is not in hot standby mode"),
+            _ => None,
+        }
+    }
+}
+
+/// One entry of a multi-host connection string.
+#[derive(Debug, Clone)]
+pub struct HostCandidate {
+    /// `host`, or `hostaddr` when no host name was given.
+    pub host: String,
+    pub port: u16,
+    pub is_unix_socket: bool,
+    /// The connection parameters narrowed to this single host.
+    pub params: ConnParams,
+}
+
+/// Split `host`, `hostaddr` and `port` lists into one candidate per host, in order.
+/// A single port applies to every host, like in libpq.
+pub fn host_candidates(params: &ConnParams) -> Result<Vec<HostCandidate>, PgConnectError> {
+    let split = |key: &str| -> Vec<String> {
+        params.get(key).map(|value| value.split(',').map(|s| s.trim().to_string()).collect()).unwrap_or_default()
+    };
+    let hosts = split("host");
+    let hostaddrs = split("hostaddr");
+    let ports = split("port");
+
+    let count = hosts.len().max(hostaddrs.len());
+    if !hosts.is_empty() && !hostaddrs.is_empty() && hosts.len() != hostaddrs.len() {
+        return Err(config_error(format!(
+            "could not match {} host names to {} hostaddr values",
+            hosts.len(),
+            hostaddrs.len()
+        )));
+    }
+    if ports.len() > 1 && ports.len() != count {
+        return Err(config_error(format!("could not match {} port numbers to {} hosts", ports.len(), count)));
+    }
+
+    let mut candidates = Vec::with_capacity(count);
+    for i in 0..count {
+        let host = hosts.get(i).filter(|host| !host.is_empty());
+        let hostaddr = hostaddrs.get(i).filter(|hostaddr| !hostaddr.is_empty());
+        let port = match ports.get(i).or(ports.first()).filter(|port| !port.is_empty()) {
+            Some(port) => port.parse::<u16>().map_err(|_| config_error(format!("invalid port number \"{}\"", port)))?,
+            None => 5432,
+        };
+
+        let mut host_params = params.clone();
+        host_params.remove("host");
+        host_params.remove("hostaddr");
+        if let Some(host) = host {
+            host_params.set("host", host.as_str());
+        }
+        if let Some(hostaddr) = hostaddr {
+            host_params.set("hostaddr", hostaddr.as_str());
+        }
+        host_params.set("port", port.to_string());
+
+        let name = host.or(hostaddr).cloned().unwrap_or_default();
+        candidates.push(HostCandidate {
+            is_unix_socket: hostaddr.is_none() && (name.starts_with('/') || name.starts_with('@')),
+            host: name,
+            port,
+            params: host_params,
+        });
+    }
+    Ok(candidates)
+}
+
+fn config_error(reason: String) -> PgConnectError {
+    PgConnectError { kind: PgConnectErrorKind::Config, message: format!("Invalid connection string: {}", reason) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates(connection_string: &str) -> Result<Vec<(String, u16, bool)>, String> {
+        let params = ConnParams::parse(connection_string).map_err(|e| e.message)?;
+        let candidates = host_candidates(&params).map_err(|e| e.message)?;
+        Ok(candidates.into_iter().map(|candidate| (candidate.host, candidate.port, candidate.is_unix_socket)).collect())
+    }
+
+    #[test]
+    fn hosts_and_ports() {
+        assert_eq!(
+            candidates("host=a,b port=5433,5434").unwrap(),
+            [("a".to_string(), 5433, false), ("b".to_string(), 5434, false)]
+        );
+        // A single port applies to every host
+        assert_eq!(
+            candidates("host=a,b port=5433").unwrap(),
+            [("a".to_string(), 5433, false), ("b".to_string(), 5433, false)]
+        );
+        // Empty ports are the default one
+        assert_eq!(
+            candidates("host=a,b port=,5434").unwrap(),
+            [("a".to_string(), 5432, false), ("b".to_string(), 5434, false)]
+        );
+        assert_eq!(
+            candidates("postgresql://a:5433,b/db").unwrap(),
+            [("a".to_string(), 5433, false), ("b".to_string(), 5432, false)]
+        );
+        assert!(candidates("dbname=db").unwrap().is_empty());
+    }
+
+    #[test]
+    fn mismatched_counts() {
+        assert_eq!(
+            candidates("host=a,b,c port=5433,5434").unwrap_err(),
+            "Invalid connection string: could not match 2 port numbers to 3 hosts"
+        );
+        assert_eq!(
+            candidates("host=a,b hostaddr=10.0.0.1").unwrap_err(),
+            "Invalid connection string: could not match 2 host names to 1 hostaddr values"
+        );
+        assert_eq!(
+            candidates("host=a port=99999").unwrap_err(),
+            "Invalid connection string: invalid port number \"99999\""
+        );
+        assert!(candidates("host=a,b port=5433,x").is_err());
+    }
+
+    #[test]
+    fn hostaddr() {
+        let params = ConnParams::parse("host=db.example.com,b hostaddr=10.0.0.1, port=5433").unwrap();
+        let candidates = host_candidates(&params).unwrap();
+        // The host name is kept for TLS and the password file
+        assert_eq!(candidates[0].host, "db.example.com");
+        assert_eq!(candidates[0].params.get("hostaddr"), Some("10.0.0.1"));
+        assert_eq!(candidates[1].params.get("hostaddr"), None);
+
+        let params = ConnParams::parse("hostaddr=10.0.0.1,10.0.0.2").unwrap();
+        let candidates = host_candidates(&params).unwrap();
+        assert_eq!(candidates[1].host, "10.0.0.2");
+        assert_eq!(candidates[1].params.get("host"), None);
+        assert_eq!(candidates[1].params.get("port"), Some("5432"));
+    }
+
+    #[test]
+    fn unix_sockets() {
+        assert_eq!(
+            candidates("host=/var/run/postgresql,@abstract,localhost port=5433").unwrap(),
+            [
+                ("/var/run/postgresql".to_string(), 5433, true),
+                ("@abstract".to_string(), 5433, true),
+                ("localhost".to_string(), 5433, false),
+            ]
+        );
+        // A hostaddr means TCP, whatever the host name
+        assert_eq!(candidates("host=/tmp hostaddr=127.0.0.1").unwrap(), [("/tmp".to_string(), 5432, false)]);
+    }
+
+    #[test]
+    fn session_attrs_decision() {
+        use TargetSessionAttrs::*;
+
+        // (attrs, in hot standby, defaults to read-only, read-only connection, mismatch)
+        let cases = [
+            (Any, true, true, false, None),
+            (Any, false, false, false, None),
+            (ReadWrite, false, false, false, None),
+            (ReadWrite, false, true, false, Some("session is read-only")),
+            (ReadWrite, true, false, false, Some("session is read-only")),
+            (ReadWrite, false, true, true, None),
+            (ReadWrite, true, false, true, Some("session is read-only")),
+            (ReadOnly, false, false, false, Some("session is not read-only")),
+            (ReadOnly, false, true, false, None),
+            (ReadOnly, true, false, false, None),
+            (ReadOnly, false, false, true, None),
+            (Primary, false, true, false, None),
+            (Primary, true, false, false, Some("server is in hot standby mode")),
+            (Standby, false, false, false, Some("server is not in hot standby mode")),
+            (Standby, true, false, false, None),
+            (Standby, true, false, true, None),
+        ];
+        for (attrs, in_hot_standby, default_read_only, read_only_connection, mismatch) in cases {
+            assert_eq!(
+                attrs.mismatch(in_hot_standby, default_read_only, read_only_connection),
+                mismatch,
+                "{:?} standby={} default_read_only={} read_only_connection={}",
+                attrs,
+                in_hot_standby,
+                default_read_only,
+                read_only_connection
+            );
+        }
+
+        // The setting is only looked up when it can change the outcome
+        assert!(ReadWrite.checks_read_only(false, false));
+        assert!(ReadOnly.checks_read_only(false, false));
+        assert!(!ReadWrite.checks_read_only(true, false));
+        assert!(!ReadOnly.checks_read_only(false, true));
+        assert!(!Primary.checks_read_only(false, false));
+    }
+
+    #[test]
+    fn session_attrs_passes() {
+        assert_eq!(TargetSessionAttrs::PreferStandby.passes(), [TargetSessionAttrs::Standby, TargetSessionAttrs::Any]);
+        assert_eq!(TargetSessionAttrs::Primary.passes(), [TargetSessionAttrs::Primary]);
+        let params = ConnParams::parse("target_session_attrs=standby").unwrap();
+        assert_eq!(TargetSessionAttrs::from_params(&params).unwrap(), TargetSessionAttrs::Standby);
+        let params = ConnParams::parse("target_session_attrs=replica").unwrap();
+        assert!(TargetSessionAttrs::from_params(&params).is_err());
+    }
+}
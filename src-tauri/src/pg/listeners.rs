@@ -0,0 +1,167 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use tauri::{AppHandle, Emitter};
+use tokio_postgres::{AsyncMessage, Client as PgClient};
+
+use crate::error::CommandError;
+use crate::pg::pg_connect::{pg_connect, PgConnectionConfig};
+use crate::pg::quote_ident::quote_ident;
+
+/// Older notifications are dropped from the recent ones past this many.
+const RECENT_LIMIT: usize = 500;
+
+/// Payload of the `pg-notification` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct PgNotification {
+    pub connection_id: String,
+    pub channel: String,
+    /// The process id of the session that sent the notification.
+    pub pid: i32,
+    pub payload: String,
+    /// The payload indented, when it is a JSON object or array.
+    pub pretty_payload: Option<String>,
+    pub received_at: DateTime<Utc>,
+}
+
+/// Payload of the `listen-closed` event, sent when a listening session drops.
+#[derive(Debug, Clone, Serialize)]
+pub struct ListenClosedEvent {
+    pub connection_id: String,
+    pub channels: Vec<String>,
+    pub reason: String,
+}
+
+/// A session of its own that only listens, so pooled sessions can be reset freely.
+struct ListenSession {
+    /// Tells this session apart from one opened later for the same connection.
+    session_id: String,
+    client: PgClient,
+    channels: BTreeSet<String>,
+}
+
+/// The listening session of a connection, `None` while it has none. Each connection has
+/// a lock of its own, so opening a session, which can go through an SSH tunnel or a
+/// password command, does not hold up the other connections.
+type SessionSlot = Arc<tokio::sync::Mutex<Option<ListenSession>>>;
+
+/// Tauri-managed registry of the channels listened to with `listen`, by connection id.
+pub struct Listeners {
+    sessions: Mutex<HashMap<String, SessionSlot>>,
+    /// Oldest first, across every connection.
+    recent: Arc<Mutex<VecDeque<PgNotification>>>,
+    app: AppHandle,
+}
+
+impl Listeners {
+    pub fn new(app: AppHandle) -> Self {
+        Listeners { sessions: Mutex::default(), recent: Arc::default(), app }
+    }
+
+    /// Listen to `channel`, opening the listening session of the connection if needed.
+    pub async fn listen(&self, connection: &PgConnectionConfig, channel: &str) -> Result<(), CommandError> {
+        let slot = self.slot(&connection.id);
+        let mut session = slot.lock().await;
+        if session.is_none() {
+            *session = Some(self.open(connection, slot.clone()).await?);
+        }
+        let listening = session.as_mut().expect("the session was just opened");
+        if let Err(e) = listening.client.batch_execute(&format!("listen {}", quote_ident(channel))).await {
+            if listening.channels.is_empty() {
+                *session = None;
+            }
+            return Err(e.into());
+        }
+        listening.channels.insert(channel.to_string());
+        Ok(())
+    }
+
+    /// Stop listening to `channel`, closing the listening session after the last one.
+    pub async fn unlisten(&self, connection_id: &str, channel: &str) -> Result<(), CommandError> {
+        let slot = self.slot(connection_id);
+        let mut session = slot.lock().await;
+        let Some(listening) = session.as_mut() else {
+            return Ok(());
+        };
+        listening.client.batch_execute(&format!("unlisten {}", quote_ident(channel))).await?;
+        listening.channels.remove(channel);
+        if listening.channels.is_empty() {
+            *session = None;
+        }
+        Ok(())
+    }
+
+    /// Close the listening session of a connection, if any.
+    pub async fn remove(&self, connection_id: &str) {
+        self.slot(connection_id).lock().await.take();
+    }
+
+    /// The last notifications received, oldest first, for one connection or all of them.
+    pub fn recent(&self, connection_id: Option<&str>) -> Vec<PgNotification> {
+        let recent = self.recent.lock().unwrap();
+        recent
+            .iter()
+            .filter(|notification| connection_id.is_none_or(|id| notification.connection_id == id))
+            .cloned()
+            .collect()
+    }
+
+    fn slot(&self, connection_id: &str) -> SessionSlot {
+        self.sessions.lock().unwrap().entry(connection_id.to_string()).or_default().clone()
+    }
+
+    /// Open a listening session, which empties `slot` when it closes unless it was replaced.
+    async fn open(&self, connection: &PgConnectionConfig, slot: SessionSlot) -> Result<ListenSession, CommandError> {
+        let (client, driver) = pg_connect(connection).await?;
+        let session_id = uuid::Uuid::new_v4().to_string();
+
+        let (connection_id, app, recent) = (connection.id.clone(), self.app.clone(), self.recent.clone());
+        let on_message = move |message| {
+            if let AsyncMessage::Notification(notification) = message {
+                let payload = notification.payload().to_string();
+                let notification = PgNotification {
+                    connection_id: connection_id.clone(),
+                    channel: notification.channel().to_string(),
+                    pid: notification.process_id(),
+                    pretty_payload: pretty_json(&payload),
+                    payload,
+                    received_at: Utc::now(),
+                };
+                let mut recent = recent.lock().unwrap();
+                if recent.len() == RECENT_LIMIT {
+                    recent.pop_front();
+                }
+                recent.push_back(notification.clone());
+                app.emit("pg-notification", notification).ok();
+            }
+        };
+
+        let (app, connection_id) = (self.app.clone(), connection.id.clone());
+        let closed_session_id = session_id.clone();
+        tokio::spawn(async move {
+            let reason = match driver.await_connection(on_message).await {
+                Ok(()) => "The server closed the connection".to_string(),
+                Err(e) => e.to_string(),
+            };
+            // Sessions closed by `unlisten` or `remove` are already gone
+            let mut session = slot.lock().await;
+            if session.as_ref().is_some_and(|session| session.session_id == closed_session_id) {
+                let session = session.take().expect("the session was just found");
+                let channels = session.channels.into_iter().collect();
+                app.emit("listen-closed", ListenClosedEvent { connection_id, channels, reason }).ok();
+            }
+        });
+
+        Ok(ListenSession { session_id, client, channels: BTreeSet::new() })
+    }
+}
+
+fn pretty_json(payload: &str) -> Option<String> {
+    if !payload.trim_start().starts_with(['{', '[']) {
+        return None;
+    }
+    let value: JsonValue = serde_json::from_str(payload).ok()?;
+    serde_json::to_string_pretty(&value).ok()
+}
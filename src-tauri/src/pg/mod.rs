@@ -1,3 +1,25 @@
+pub mod bind_params;
+pub mod conn_params;
+pub mod copy;
+pub mod cursors;
+pub mod explain;
+pub mod hosts;
+pub mod listeners;
+pub mod notices;
 pub mod pg_connect;
+pub mod pg_pool;
+pub mod query_history;
 pub mod quote_ident;
-pub mod models;
\ No newline at end of file
+pub mod session_settings;
+pub mod sql_analysis;
+pub mod sql_split;
+pub mod sql_state;
+pub mod resolve;
+pub mod result_stream;
+pub mod running_queries;
+pub mod models;
+pub mod password_command;
+pub mod ssh_tunnel;
+pub mod tls;
+pub mod transactions;
+pub mod value_codec;
\ No newline at end of file
@@ -39,3 +39,64 @@ pub struct PgTableData {
     pub rows: Vec<JsonValue>,
     pub count: i64,
 }
+
+#[derive(Debug, Serialize)]
+pub struct PgConnectionReport {
+    /// The host the session was opened on, among those of the connection string.
+    pub host: String,
+    pub port: u16,
+    /// Time to open the session, including TLS and authentication.
+    pub connect_ms: f64,
+    /// Round-trip time of an empty query once connected.
+    pub latency_ms: f64,
+    pub server_version: String,
+    pub server_version_num: i32,
+    pub user: String,
+    pub database: String,
+    pub in_hot_standby: bool,
+    pub read_only: bool,
+    /// `None` when the session is not encrypted.
+    pub tls: Option<PgTlsInfo>,
+    pub extensions: Vec<PgExtension>,
+    pub privileges: PgPrivileges,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PgTlsInfo {
+    pub version: String,
+    pub cipher: String,
+    pub bits: i32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PgExtension {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PgPrivileges {
+    pub is_superuser: bool,
+    /// `CREATE` on the current database, needed to create schemas.
+    pub can_create_schema: bool,
+    /// `CREATE` on the first schema of the `search_path`, needed to create tables.
+    pub can_create_table: bool,
+    /// `INSERT`, `UPDATE` or `DELETE` on at least one user table.
+    pub can_modify_data: bool,
+}
+
+/// A column of a query result. Only `name` is known for scripts run with the simple
+/// query protocol, the rest comes from the statement description of prepared statements.
+#[derive(Debug, Clone, Serialize)]
+pub struct PgResultColumn {
+    pub name: String,
+    pub type_oid: Option<u32>,
+    pub type_name: Option<String>,
+    /// `None` when the column is not a table column, like an expression.
+    pub nullable: Option<bool>,
+    pub table_oid: Option<u32>,
+    pub column_id: Option<i16>,
+    /// The values are the hex of the binary format, for types without a conversion to JSON
+    /// that could not be fetched as text.
+    pub binary: bool,
+}
@@ -0,0 +1,76 @@
+use std::sync::Mutex;
+use serde::Serialize;
+use tokio_postgres::error::DbError;
+
+/// A message the server sent while a query ran, like a warning or the output of `RAISE NOTICE`.
+#[derive(Debug, Clone, Serialize)]
+pub struct PgNotice {
+    /// `WARNING`, `NOTICE`, `INFO`, `LOG` or `DEBUG`.
+    pub severity: String,
+    /// The SQLSTATE, `00000` for plain `RAISE NOTICE`.
+    pub code: String,
+    pub message: String,
+    pub detail: Option<String>,
+    pub hint: Option<String>,
+}
+
+impl From<&DbError> for PgNotice {
+    fn from(notice: &DbError) -> Self {
+        PgNotice {
+            // Unlike `severity`, the parsed one is never translated
+            severity: notice
+                .parsed_severity()
+                .map_or_else(|| notice.severity().to_string(), |severity| severity.to_string()),
+            code: notice.code().code().to_string(),
+            message: notice.message().to_string(),
+            detail: notice.detail().map(String::from),
+            hint: notice.hint().map(String::from),
+        }
+    }
+}
+
+/// Payload of the `query-notice` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryNoticeEvent {
+    pub query_id: String,
+    #[serde(flatten)]
+    pub notice: PgNotice,
+}
+
+/// The notices of one session, kept by its connection driver for the query running on it.
+#[derive(Default)]
+pub struct SessionNotices {
+    state: Mutex<NoticeState>,
+}
+
+#[derive(Default)]
+struct NoticeState {
+    query_id: Option<String>,
+    notices: Vec<PgNotice>,
+}
+
+impl SessionNotices {
+    /// Keep the notices sent from now on for `query_id`, until `finish`.
+    pub fn start(&self, query_id: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.query_id = Some(query_id.to_string());
+        state.notices.clear();
+    }
+
+    /// The notices sent since `start`. The driver handles messages in the order the
+    /// server sent them, so once a query returned its notices are all there.
+    pub fn finish(&self) -> Vec<PgNotice> {
+        let mut state = self.state.lock().unwrap();
+        state.query_id = None;
+        std::mem::take(&mut state.notices)
+    }
+
+    /// Keep a notice for the running query and return its id. Notices sent outside of
+    /// a query, like the warnings of a session reset, are dropped.
+    pub fn push(&self, notice: PgNotice) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        let query_id = state.query_id.clone()?;
+        state.notices.push(notice);
+        Some(query_id)
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use tokio::process::Command;
+
+use crate::pg::pg_connect::{PgConnectError, PgConnectErrorKind};
+
+/// Used when the connection does not set `timeoutSecs`.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Cached passwords are dropped this long before their stated expiry, so a session
+/// is never opened with a token about to run out.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(30);
+
+/// A shell command printing the password to use, typically a short-lived token
+/// from a cloud IAM or Vault CLI.
+///
+/// The command prints either the password alone, which is used for a single session,
+/// or a JSON object `{"password": "...", "expiresAt": "<RFC 3339>"}` (or `"expiresIn": <seconds>`)
+/// whose password is reused until it expires.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PasswordCommand {
+    pub command: String,
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CommandOutput {
+    password: String,
+    expires_at: Option<DateTime<Utc>>,
+    expires_in: Option<u64>,
+}
+
+struct CachedPassword {
+    password: String,
+    valid_until: Instant,
+}
+
+/// Passwords with a stated expiry, keyed by command.
+static CACHE: LazyLock<Mutex<HashMap<String, CachedPassword>>> = LazyLock::new(Default::default);
+
+impl PasswordCommand {
+    /// The cached password if it is still valid, or a fresh one from the command.
+    pub async fn password(&self) -> Result<String, PgConnectError> {
+        if let Some(cached) = CACHE.lock().unwrap().get(&self.command) {
+            if Instant::now() < cached.valid_until {
+                return Ok(cached.password.clone());
+            }
+        }
+
+        let stdout = self.run().await?;
+        let Ok(output) = serde_json::from_str::<CommandOutput>(&stdout) else {
+            return Ok(stdout);
+        };
+        if output.password.is_empty() {
+            return Err(command_error("printed JSON with an empty \"password\"".to_string()));
+        }
+
+        let lifetime = match (output.expires_at, output.expires_in) {
+            (Some(expires_at), _) => (expires_at - Utc::now()).to_std().ok(),
+            (None, Some(expires_in)) => Some(Duration::from_secs(expires_in)),
+            (None, None) => None,
+        };
+        let mut cache = CACHE.lock().unwrap();
+        cache.retain(|_, cached| Instant::now() < cached.valid_until);
+        if let Some(lifetime) = lifetime.and_then(|lifetime| lifetime.checked_sub(EXPIRY_MARGIN)) {
+            cache.insert(
+                self.command.clone(),
+                CachedPassword { password: output.password.clone(), valid_until: Instant::now() + lifetime },
+            );
+        }
+        Ok(output.password)
+    }
+
+    /// Run the command through the platform shell and return its trimmed stdout.
+    async fn run(&self) -> Result<String, PgConnectError> {
+        #[cfg(windows)]
+        let mut command = {
+            let mut command = Command::new("cmd");
+            command.arg("/C").arg(&self.command);
+            command
+        };
+        #[cfg(not(windows))]
+        let mut command = {
+            let mut command = Command::new("sh");
+            command.arg("-c").arg(&self.command);
+            command
+        };
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        let timeout = self.timeout_secs.map_or(DEFAULT_TIMEOUT, Duration::from_secs);
+        let output = tokio::time::timeout(timeout, command.output())
+            .await
+            .map_err(|_| command_error(format!("timed out after {} seconds", timeout.as_secs())))?
+            .map_err(|e| command_error(format!("could not be started: {}", e)))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let status = match output.status.code() {
+                Some(code) => format!("exited with status {}", code),
+                None => "was killed".to_string(),
+            };
+            return Err(command_error(match stderr.trim() {
+                "" => status,
+                stderr => format!("{}: {}", status, stderr),
+            }));
+        }
+
+        let stdout = String::from_utf8(output.stdout)
+            .map_err(|_| command_error("printed a password that is not valid UTF-8".to_string()))?;
+        match stdout.trim() {
+            "" => Err(command_error("printed no password".to_string())),
+            password => Ok(password.to_string()),
+        }
+    }
+}
+
+fn command_error(message: String) -> PgConnectError {
+    PgConnectError { kind: PgConnectErrorKind::PasswordCommand, message: format!("Password command {}", message) }
+}
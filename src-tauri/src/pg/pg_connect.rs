@@ -1,67 +1,484 @@
+use std::error::Error as _;
+use std::io;
+use std::future::{poll_fn, Future};
+use std::pin::{pin, Pin};
 use std::sync::Arc;
+use std::task::Poll;
 use futures::lock::Mutex;
-use native_tls::TlsConnector;
-use postgres_native_tls::MakeTlsConnector;
-use tokio_postgres::{Client as PgClient, Connection, Socket};
+use tokio_postgres::config::SslMode as PgSslMode;
+use tokio_postgres::error::SqlState;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_postgres::{AsyncMessage, CancelToken, Client as PgClient, Config, Connection, SimpleQueryMessage, Socket};
 use tokio_postgres::tls::NoTlsStream;
-use postgres_native_tls::TlsStream;
-use serde::Serialize;
+use postgres_native_tls::{MakeTlsConnector, TlsStream};
+use serde::{Deserialize, Serialize};
 
-pub type SharedDb = Arc<Mutex<PgClient>>;
+use crate::pg::conn_params::ConnParams;
+use crate::pg::hosts::{host_candidates, HostCandidate, TargetSessionAttrs};
+use crate::pg::password_command::PasswordCommand;
+use crate::pg::pg_pool::PooledClient;
+use crate::pg::resolve::{default_host, passfile_password, resolve};
+use crate::pg::session_settings::SessionSettings;
+use crate::pg::sql_analysis::SafetyPolicy;
+use crate::pg::ssh_tunnel::{SshTunnel, SshTunnelConfig};
+use crate::pg::tls::{SslMode, TlsSettings};
+
+pub type SharedDb = Arc<Mutex<PooledClient>>;
+
+/// TCP keepalive settings used unless the connection string sets them.
+const TCP_KEEPALIVE_DEFAULTS: &[(&str, &str)] = &[
+    ("keepalives_idle", "30"),
+    ("keepalives_interval", "10"),
+    ("keepalives_retries", "3"),
+];
+
+/// A saved connection as sent by the frontend.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PgConnectionConfig {
+    pub id: String,
+    pub connection_string: String,
+    pub ssh_tunnel: Option<SshTunnelConfig>,
+    pub password_command: Option<PasswordCommand>,
+    /// Sessions start with `default_transaction_read_only = on` and mutations are refused.
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub session_settings: SessionSettings,
+    /// The statements that need a confirmation token before they run.
+    #[serde(default)]
+    pub safety_policy: SafetyPolicy,
+}
+
+/// Why opening a session failed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PgConnectErrorKind {
+    /// The connection string or a file it references is invalid.
+    Config,
+    /// The SSH bastion could not be reached or refused us.
+    SshTunnel,
+    /// The password command failed, timed out or printed nothing.
+    PasswordCommand,
+    /// The host name could not be resolved.
+    Dns,
+    /// Nothing listens on the host and port, or the Unix socket does not exist.
+    ConnectionRefused,
+    /// The server did not answer in time.
+    Timeout,
+    /// The TLS handshake failed or the server does not support TLS.
+    TlsHandshake,
+    /// The server rejected the credentials or has no `pg_hba.conf` entry for us.
+    AuthFailed,
+    /// The requested database does not exist.
+    DatabaseMissing,
+    /// The init SQL of the session settings failed.
+    InitSql,
+    /// Any other error reported by the server or the driver.
+    Database,
+}
+
+impl PgConnectErrorKind {
+    /// Code reported to the frontend, generic database errors have none.
+    pub fn code(self) -> Option<&'static str> {
+        match self {
+            PgConnectErrorKind::Config => Some("config"),
+            PgConnectErrorKind::SshTunnel => Some("ssh_tunnel"),
+            PgConnectErrorKind::PasswordCommand => Some("password_command"),
+            PgConnectErrorKind::Dns => Some("dns"),
+            PgConnectErrorKind::ConnectionRefused => Some("connection_refused"),
+            PgConnectErrorKind::Timeout => Some("timeout"),
+            PgConnectErrorKind::TlsHandshake => Some("tls_handshake"),
+            PgConnectErrorKind::AuthFailed => Some("auth_failed"),
+            PgConnectErrorKind::DatabaseMissing => Some("database_missing"),
+            PgConnectErrorKind::InitSql => Some("init_sql"),
+            PgConnectErrorKind::Database => None,
+        }
+    }
+
+    /// Whether retrying later may succeed, as when the network is flaky.
+    pub fn is_transient(self) -> bool {
+        matches!(
+            self,
+            PgConnectErrorKind::SshTunnel
+                | PgConnectErrorKind::Dns
+                | PgConnectErrorKind::ConnectionRefused
+                | PgConnectErrorKind::Timeout
+                | PgConnectErrorKind::Database
+        )
+    }
+
+    fn classify(e: &tokio_postgres::Error) -> Self {
+        if let Some(db_error) = e.as_db_error() {
+            return match db_error.code() {
+                &SqlState::INVALID_PASSWORD | &SqlState::INVALID_AUTHORIZATION_SPECIFICATION => {
+                    PgConnectErrorKind::AuthFailed
+                }
+                &SqlState::INVALID_CATALOG_NAME => PgConnectErrorKind::DatabaseMissing,
+                _ => PgConnectErrorKind::Database,
+            };
+        }
+
+        // The driver's error kinds are private, only their message tells them apart
+        let message = e.to_string();
+        if message.starts_with("error performing TLS handshake") {
+            return PgConnectErrorKind::TlsHandshake;
+        }
+        if message.starts_with("authentication error") {
+            return PgConnectErrorKind::AuthFailed;
+        }
+        if message.starts_with("timeout waiting for server") {
+            return PgConnectErrorKind::Timeout;
+        }
+        match e.source().and_then(|source| source.downcast_ref::<io::Error>()) {
+            Some(io_error) if is_dns_error(io_error) => PgConnectErrorKind::Dns,
+            Some(io_error) => match io_error.kind() {
+                io::ErrorKind::ConnectionRefused | io::ErrorKind::NotFound => PgConnectErrorKind::ConnectionRefused,
+                io::ErrorKind::TimedOut => PgConnectErrorKind::Timeout,
+                _ => PgConnectErrorKind::Database,
+            },
+            None => PgConnectErrorKind::Database,
+        }
+    }
+}
+
+/// Resolver failures only surface as an `io::Error` with the resolver's message,
+/// or the WSA error code on Windows.
+fn is_dns_error(e: &io::Error) -> bool {
+    if cfg!(windows) {
+        matches!(e.raw_os_error(), Some(11001..=11004))
+    } else {
+        e.to_string().starts_with("failed to lookup address information")
+            || e.to_string() == "could not resolve any addresses"
+    }
+}
 
 #[derive(Debug, Serialize)]
 pub struct PgConnectError {
+    pub kind: PgConnectErrorKind,
     pub message: String,
 }
 
 impl From<native_tls::Error> for PgConnectError {
     fn from(e: native_tls::Error) -> Self {
-        PgConnectError { message: format!("TLS error: {}", e) }
+        PgConnectError { kind: PgConnectErrorKind::Config, message: format!("TLS error: {}", e) }
     }
 }
 
 impl From<tokio_postgres::Error> for PgConnectError {
     fn from(e: tokio_postgres::Error) -> Self {
-        PgConnectError { message: format!("Connection error: {}", e) }
+        // The driver's message is only its error kind, the details are in the source
+        let mut message = format!("Connection error: {}", e);
+        if let Some(source) = e.source() {
+            message.push_str(&format!(": {}", source));
+        }
+        PgConnectError { kind: PgConnectErrorKind::classify(&e), message }
     }
 }
 
-pub enum PgConnection {
+enum Driver {
     NoTls(Connection<Socket, NoTlsStream>),
-    Tls(Connection<Socket, TlsStream<Socket>>),
+    /// Keeps the connector to send cancel requests over TLS too.
+    Tls(Connection<Socket, TlsStream<Socket>>, MakeTlsConnector),
 }
 
-impl PgConnection {
-    pub async fn await_connection(self) -> Result<(), tokio_postgres::Error> {
+impl Driver {
+    /// A parameter reported by the server at startup, like `server_version`.
+    fn parameter(&self, name: &str) -> Option<&str> {
         match self {
-            PgConnection::NoTls(conn) => conn.await,
-            PgConnection::Tls(conn) => conn.await,
+            Driver::NoTls(conn) => conn.parameter(name),
+            Driver::Tls(conn, _) => conn.parameter(name),
         }
     }
+
+    /// Run `future` while polling the connection, before it is handed to a background task.
+    async fn drive<F: Future>(&mut self, future: F) -> Result<F::Output, tokio_postgres::Error> {
+        let mut future = pin!(future);
+        poll_fn(|cx| {
+            if let Poll::Ready(output) = future.as_mut().poll(cx) {
+                return Poll::Ready(Ok(output));
+            }
+            let connection = match self {
+                Driver::NoTls(conn) => Pin::new(conn).poll(cx),
+                Driver::Tls(conn, _) => Pin::new(conn).poll(cx),
+            };
+            match connection {
+                Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+                // The connection is closed, so the future fails with the client's error
+                Poll::Ready(Ok(())) => future.as_mut().poll(cx).map(Ok),
+                Poll::Pending => Poll::Pending,
+            }
+        })
+        .await
+    }
 }
 
-pub async fn pg_connect(connection_string: &str) -> Result<(PgClient, PgConnection), PgConnectError> {
-    let requires_ssl = connection_string.contains("sslmode=require")
-        || connection_string.contains("sslmode=verify-ca")
-        || connection_string.contains("sslmode=verify-full");
+/// The connection future driving a client, to be spawned on the runtime.
+pub struct PgConnection {
+    driver: Driver,
+    /// The host the session was opened on, among those of the connection string.
+    pub host: String,
+    pub port: u16,
+    /// The database the session is connected to.
+    pub database: String,
+    /// Keeps the SSH tunnel open for as long as this session is alive.
+    _tunnel: Option<Arc<SshTunnel>>,
+}
 
-    if requires_ssl {
-        let connector = TlsConnector::builder()
-            .build()
-            .map_err(PgConnectError::from)?;
+impl PgConnection {
+    /// What `cancel_query` needs to stop the queries of `client`, which must be
+    /// the client opened with this connection.
+    pub fn canceller(&self, client: &PgClient) -> QueryCanceller {
+        let tls = match &self.driver {
+            Driver::NoTls(_) => None,
+            Driver::Tls(_, connector) => Some(connector.clone()),
+        };
+        QueryCanceller { token: client.cancel_token(), tls }
+    }
 
-        let tls = MakeTlsConnector::new(connector);
+    /// Drive the session until it is closed, handing the notices and notifications
+    /// the server sends to `on_message`.
+    pub async fn await_connection(self, mut on_message: impl FnMut(AsyncMessage)) -> Result<(), tokio_postgres::Error> {
+        match self.driver {
+            Driver::NoTls(mut conn) => forward_messages(&mut conn, &mut on_message).await,
+            Driver::Tls(mut conn, _) => forward_messages(&mut conn, &mut on_message).await,
+        }
+    }
+}
+
+async fn forward_messages<S, T>(
+    conn: &mut Connection<S, T>,
+    on_message: &mut impl FnMut(AsyncMessage),
+) -> Result<(), tokio_postgres::Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    while let Some(message) = poll_fn(|cx| conn.poll_message(cx)).await {
+        on_message(message?);
+    }
+    Ok(())
+}
 
-        let (client, conn) = tokio_postgres::connect(connection_string, tls)
-            .await
-            .map_err(PgConnectError::from)?;
+/// Sends cancel requests for the queries of one session, over TLS when the session uses it.
+#[derive(Clone)]
+pub struct QueryCanceller {
+    token: CancelToken,
+    tls: Option<MakeTlsConnector>,
+}
 
-        Ok((client, PgConnection::Tls(conn)))
+impl QueryCanceller {
+    pub async fn cancel(&self) -> Result<(), tokio_postgres::Error> {
+        match &self.tls {
+            Some(connector) => self.token.cancel_query(connector.clone()).await,
+            None => self.token.cancel_query(tokio_postgres::NoTls).await,
+        }
+    }
+}
+
+/// Open a session on the first host of the connection string that accepts us and
+/// matches `target_session_attrs`, trying each one in order like libpq.
+pub async fn pg_connect(config: &PgConnectionConfig) -> Result<(PgClient, PgConnection), PgConnectError> {
+    let mut params = ConnParams::parse(&config.connection_string)?;
+    resolve(&mut params)?;
+    if let Some(password_command) = &config.password_command {
+        params.set("password", password_command.password().await?);
+    }
+    if config.read_only {
+        params.add_option("default_transaction_read_only", "on");
+    }
+    config.session_settings.apply(&mut params);
+    // Notice dead connections within about a minute instead of the OS default of hours
+    for (key, value) in TCP_KEEPALIVE_DEFAULTS {
+        if params.get(key).is_none() {
+            params.set(key, *value);
+        }
+    }
+    let tls = TlsSettings::from_params(&params)?;
+    let target_session_attrs = TargetSessionAttrs::from_params(&params)?;
+
+    let tunnel = match &config.ssh_tunnel {
+        Some(ssh_tunnel) => Some(open_tunnel(ssh_tunnel, &mut params).await?),
+        None => {
+            if params.get("host").is_none() && params.get("hostaddr").is_none() {
+                params.set("host", default_host(&params));
+            }
+            None
+        }
+    };
+    let mut candidates = host_candidates(&params)?;
+    // Like libpq, each host has the password of its own entry in the password file
+    for candidate in &mut candidates {
+        if candidate.params.get("password").is_none_or(str::is_empty) {
+            if let Some(password) = passfile_password(&candidate.params, &candidate.host, candidate.port) {
+                candidate.params.set("password", password);
+            }
+        }
+    }
+
+    let mut errors: Vec<(String, PgConnectError)> = Vec::new();
+    for attrs in target_session_attrs.passes() {
+        for candidate in &candidates {
+            match connect_host(candidate, &tls, attrs, config.read_only).await {
+                Ok((client, driver)) => {
+                    // Like libpq, the database defaults to the user name
+                    let database = candidate.params.get("dbname").or(candidate.params.get("user"));
+                    let connection = PgConnection {
+                        driver,
+                        host: candidate.host.clone(),
+                        port: candidate.port,
+                        database: database.unwrap_or_default().to_string(),
+                        _tunnel: tunnel,
+                    };
+                    return Ok((client, connection));
+                }
+                Err(e) => errors.push((format!("{}:{}", candidate.host, candidate.port), e)),
+            }
+        }
+    }
+
+    if errors.len() == 1 {
+        return Err(errors.remove(0).1);
+    }
+    let kind = errors.last().map_or(PgConnectErrorKind::Database, |(_, e)| e.kind);
+    let mut message = "Could not connect to any host:".to_string();
+    for (host, e) in &errors {
+        message.push_str(&format!("\n{}: {}", host, e.message));
+    }
+    Err(PgConnectError { kind, message })
+}
+
+async fn connect_host(
+    candidate: &HostCandidate,
+    tls: &TlsSettings,
+    attrs: TargetSessionAttrs,
+    read_only_connection: bool,
+) -> Result<(PgClient, Driver), PgConnectError> {
+    let pg_config = candidate.params.to_pg_config()?;
+
+    // libpq never uses TLS over Unix sockets, whatever the sslmode
+    let mode = if candidate.is_unix_socket { SslMode::Disable } else { tls.mode };
+    let (client, mut driver) = match mode {
+        SslMode::Disable => connect_no_tls(&pg_config).await?,
+        // Try without TLS first and only retry with it if the server turned us down
+        SslMode::Allow => match connect_no_tls(&pg_config).await {
+            Err(e) if e.as_db_error().is_some() => {
+                connect_tls(pg_config, PgSslMode::Require, tls.connector()?).await?
+            }
+            result => result?,
+        },
+        // The driver goes on without TLS when the server does not support it. Like libpq, a
+        // plain session is also tried when the handshake failed, but not after network errors.
+        SslMode::Prefer => match connect_tls(pg_config.clone(), PgSslMode::Prefer, tls.connector()?).await {
+            Err(e) if matches!(PgConnectErrorKind::classify(&e), PgConnectErrorKind::TlsHandshake) => {
+                connect_no_tls(&pg_config).await?
+            }
+            result => result?,
+        },
+        SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull => {
+            connect_tls(pg_config, PgSslMode::Require, tls.connector()?).await?
+        }
+    };
+
+    check_session_attrs(&client, &mut driver, attrs, read_only_connection).await?;
+    Ok((client, driver))
+}
+
+/// Reject the server if it does not match `attrs`. Postgres 14+ reports the needed
+/// settings at startup, older servers are asked.
+async fn check_session_attrs(
+    client: &PgClient,
+    driver: &mut Driver,
+    attrs: TargetSessionAttrs,
+    read_only_connection: bool,
+) -> Result<(), PgConnectError> {
+    if attrs == TargetSessionAttrs::Any {
+        return Ok(());
+    }
+
+    let in_hot_standby = match driver.parameter("in_hot_standby") {
+        Some(value) => value == "on",
+        None => query_flag(client, driver, "SELECT pg_catalog.pg_is_in_recovery()").await?,
+    };
+    let default_read_only = if attrs.checks_read_only(in_hot_standby, read_only_connection) {
+        match driver.parameter("default_transaction_read_only") {
+            Some(value) => value == "on",
+            None => query_flag(client, driver, "SHOW transaction_read_only").await?,
+        }
     } else {
-        let (client, conn) = tokio_postgres::connect(connection_string, tokio_postgres::NoTls)
-            .await
-            .map_err(PgConnectError::from)?;
+        false
+    };
+
+    match attrs.mismatch(in_hot_standby, default_read_only, read_only_connection) {
+        Some(reason) => Err(PgConnectError {
+            kind: PgConnectErrorKind::Database,
+            message: format!("Connection error: {}", reason),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Run a query returning a single boolean, `t` or `on`.
+async fn query_flag(client: &PgClient, driver: &mut Driver, sql: &str) -> Result<bool, PgConnectError> {
+    let messages = driver.drive(client.simple_query(sql)).await??;
+    let value = messages.iter().find_map(|message| match message {
+        SimpleQueryMessage::Row(row) => row.get(0),
+        _ => None,
+    });
+    Ok(matches!(value, Some("t" | "on")))
+}
+
+/// Run the init SQL of the session settings, once the connection is being driven.
+pub async fn init_session(client: &PgClient, config: &PgConnectionConfig) -> Result<(), PgConnectError> {
+    let Some(init_sql) = config.session_settings.init_sql() else {
+        return Ok(());
+    };
+    client.batch_execute(init_sql).await.map_err(|e| PgConnectError {
+        kind: PgConnectErrorKind::InitSql,
+        message: format!(
+            "Init SQL failed: {}",
+            e.as_db_error().map_or_else(|| e.to_string(), |db_error| db_error.message().to_string())
+        ),
+    })
+}
+
+/// Forward a local port to the database host through the bastion and point the
+/// connection at it. `host` is kept so TLS still verifies the real server name.
+async fn open_tunnel(ssh_tunnel: &SshTunnelConfig, params: &mut ConnParams) -> Result<Arc<SshTunnel>, PgConnectError> {
+    let config_error = |message: &str| PgConnectError { kind: PgConnectErrorKind::Config, message: message.to_string() };
 
-        Ok((client, PgConnection::NoTls(conn)))
+    let host = params.get("host").unwrap_or("localhost").to_string();
+    if host.contains(',') || host.starts_with('/') {
+        return Err(config_error("SSH tunnels need a single TCP host in the connection string"));
+    }
+    let port = match params.get("port").filter(|port| !port.is_empty()) {
+        Some(port) => port.parse::<u16>().map_err(|_| config_error("Invalid port in the connection string"))?,
+        None => 5432,
+    };
+
+    // The password file has entries for the server, not for the local end of the tunnel
+    if params.get("password").is_none_or(str::is_empty) {
+        if let Some(password) = passfile_password(params, &host, port) {
+            params.set("password", password);
+        }
     }
-}
\ No newline at end of file
+    let tunnel = SshTunnel::open(ssh_tunnel, &host, port).await?;
+    params.set("host", host);
+    params.set("hostaddr", "127.0.0.1");
+    params.set("port", tunnel.local_port.to_string());
+    Ok(tunnel)
+}
+
+async fn connect_no_tls(pg_config: &Config) -> Result<(PgClient, Driver), tokio_postgres::Error> {
+    let (client, conn) = pg_config.connect(tokio_postgres::NoTls).await?;
+    Ok((client, Driver::NoTls(conn)))
+}
+
+async fn connect_tls(
+    mut pg_config: Config,
+    ssl_mode: PgSslMode,
+    connector: MakeTlsConnector,
+) -> Result<(PgClient, Driver), tokio_postgres::Error> {
+    let (client, conn) = pg_config.ssl_mode(ssl_mode).connect(connector.clone()).await?;
+    Ok((client, Driver::Tls(conn, connector)))
+}
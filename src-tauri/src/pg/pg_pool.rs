@@ -0,0 +1,483 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_postgres::{AsyncMessage, Client as PgClient};
+
+use crate::error::CommandError;
+use crate::pg::notices::{PgNotice, QueryNoticeEvent, SessionNotices};
+use crate::pg::pg_connect::{
+    init_session, pg_connect, PgConnectError, PgConnectErrorKind, PgConnectionConfig, QueryCanceller,
+};
+
+/// Maximum number of physical sessions opened for a single saved connection.
+const MAX_SIZE: usize = 8;
+
+/// Idle sessions unused for longer than this are closed by `evict_idle`.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Idle sessions not checked for longer than this are pinged before being handed out.
+const HEALTH_CHECK_AFTER: Duration = Duration::from_secs(30);
+
+/// How long a command waits for a free session when the pool is full.
+const ACQUIRE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often the background task calls `evict_idle`.
+pub const EVICTION_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often the background task calls `keepalive`.
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// A keepalive ping not answered within this time counts as a lost connection.
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A keepalive ping slower than this marks the connection as degraded.
+const DEGRADED_LATENCY: Duration = Duration::from_secs(1);
+
+/// Delays between the attempts of a read command whose connection dropped.
+const RECONNECT_BACKOFF: &[Duration] = &[
+    Duration::from_millis(250),
+    Duration::from_secs(1),
+    Duration::from_secs(3),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionStatus {
+    Connected,
+    /// The server answers, but slowly.
+    Degraded,
+    Lost,
+    /// A command is retrying after losing its connection.
+    Reconnecting,
+}
+
+/// Payload of the `connection-status` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionStatusEvent {
+    pub connection_id: String,
+    pub status: ConnectionStatus,
+    pub reason: Option<String>,
+}
+
+struct IdleClient {
+    client: PgClient,
+    canceller: QueryCanceller,
+    database: String,
+    notices: Arc<SessionNotices>,
+    idle_since: Instant,
+    checked_at: Instant,
+}
+
+/// Sessions opened for one saved connection.
+pub struct PgPool {
+    config: PgConnectionConfig,
+    idle: Mutex<Vec<IdleClient>>,
+    permits: Arc<Semaphore>,
+    status: Mutex<Option<ConnectionStatus>>,
+    /// Where events are emitted, `None` in tests.
+    app: Option<AppHandle>,
+}
+
+impl PgPool {
+    fn new(config: PgConnectionConfig, app: Option<AppHandle>) -> Self {
+        PgPool {
+            config,
+            idle: Mutex::new(Vec::new()),
+            permits: Arc::new(Semaphore::new(MAX_SIZE)),
+            status: Mutex::new(None),
+            app,
+        }
+    }
+
+    async fn get(self: &Arc<Self>) -> Result<PooledClient, PgConnectError> {
+        let permit = tokio::time::timeout(ACQUIRE_TIMEOUT, self.permits.clone().acquire_owned())
+            .await
+            .map_err(|_| PgConnectError {
+                kind: PgConnectErrorKind::Database,
+                message: "Timed out waiting for a free database connection".to_string(),
+            })?
+            .expect("pool semaphore is never closed");
+
+        while let Some(idle) = self.take_idle() {
+            if idle.client.is_closed() {
+                continue;
+            }
+            if idle.checked_at.elapsed() >= HEALTH_CHECK_AFTER && idle.client.simple_query("").await.is_err() {
+                continue;
+            }
+            return Ok(PooledClient::new(idle.client, idle.canceller, idle.database, idle.notices, self.clone(), permit));
+        }
+
+        let (client, connection) = match pg_connect(&self.config).await {
+            Ok(connected) => connected,
+            Err(e) => {
+                self.set_status(ConnectionStatus::Lost, Some(e.message.clone()));
+                return Err(e);
+            }
+        };
+
+        let canceller = connection.canceller(&client);
+        let database = connection.database.clone();
+        let notices = Arc::new(SessionNotices::default());
+        let pool = Arc::downgrade(self);
+        let (session_notices, app) = (notices.clone(), self.app.clone());
+        tokio::spawn(async move {
+            let on_message = |message| {
+                if let AsyncMessage::Notice(notice) = message {
+                    let notice = PgNotice::from(&notice);
+                    if let (Some(query_id), Some(app)) = (session_notices.push(notice.clone()), &app) {
+                        app.emit("query-notice", QueryNoticeEvent { query_id, notice }).ok();
+                    }
+                }
+            };
+            if let Err(e) = connection.await_connection(on_message).await {
+                eprintln!("DB connection error: {e}");
+                if let Some(pool) = pool.upgrade() {
+                    pool.set_status(ConnectionStatus::Lost, Some(e.to_string()));
+                }
+            }
+        });
+        init_session(&client, &self.config).await?;
+        self.set_status(ConnectionStatus::Connected, None);
+
+        Ok(PooledClient::new(client, canceller, database, notices, self.clone(), permit))
+    }
+
+    fn take_idle(&self) -> Option<IdleClient> {
+        self.idle.lock().unwrap().pop()
+    }
+
+    fn evict_idle(&self) {
+        self.idle
+            .lock()
+            .unwrap()
+            .retain(|idle| !idle.client.is_closed() && idle.idle_since.elapsed() < IDLE_TIMEOUT);
+    }
+
+    /// Ping every idle session, dropping the dead ones, and report how the server answers.
+    async fn keepalive(&self) {
+        let idle = std::mem::take(&mut *self.idle.lock().unwrap());
+        if idle.is_empty() {
+            return;
+        }
+
+        let pings = idle.into_iter().map(|idle| async move {
+            let started = Instant::now();
+            let result = tokio::time::timeout(PING_TIMEOUT, idle.client.simple_query("")).await;
+            (idle, started.elapsed(), result)
+        });
+        let mut slowest = Duration::ZERO;
+        let mut failure = None;
+        let mut alive = Vec::new();
+        for (mut idle, latency, result) in futures::future::join_all(pings).await {
+            match result {
+                Ok(Ok(_)) => {
+                    slowest = slowest.max(latency);
+                    idle.checked_at = Instant::now();
+                    alive.push(idle);
+                }
+                Ok(Err(e)) => failure = Some(e.to_string()),
+                Err(_) => failure = Some(format!("No answer to keepalive within {} seconds", PING_TIMEOUT.as_secs())),
+            }
+        }
+        let any_alive = !alive.is_empty();
+        self.idle.lock().unwrap().extend(alive);
+
+        match failure {
+            Some(reason) if !any_alive => self.set_status(ConnectionStatus::Lost, Some(reason)),
+            _ if slowest >= DEGRADED_LATENCY => self.set_status(
+                ConnectionStatus::Degraded,
+                Some(format!("The server took {} ms to answer", slowest.as_millis())),
+            ),
+            _ => self.set_status(ConnectionStatus::Connected, None),
+        }
+    }
+
+    /// Emit a `connection-status` event when the status changes. Reconnection
+    /// attempts are always reported.
+    fn set_status(&self, status: ConnectionStatus, reason: Option<String>) {
+        {
+            let mut current = self.status.lock().unwrap();
+            if *current == Some(status) && status != ConnectionStatus::Reconnecting {
+                return;
+            }
+            *current = Some(status);
+        }
+        let event = ConnectionStatusEvent { connection_id: self.config.id.clone(), status, reason };
+        if let Some(app) = &self.app {
+            app.emit("connection-status", event).ok();
+        }
+    }
+}
+
+/// A session borrowed from a `PgPool`, returned to it when dropped if it was left clean.
+pub struct PooledClient {
+    client: Option<PgClient>,
+    canceller: QueryCanceller,
+    database: String,
+    notices: Arc<SessionNotices>,
+    pool: Arc<PgPool>,
+    /// Set by `reset_session` or `mark_clean`. Sessions dropped without it, like when the
+    /// future of a cancelled command is dropped halfway, may be in a transaction and are
+    /// closed instead of going back to the pool.
+    clean: AtomicBool,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl PooledClient {
+    fn new(
+        client: PgClient,
+        canceller: QueryCanceller,
+        database: String,
+        notices: Arc<SessionNotices>,
+        pool: Arc<PgPool>,
+        permit: OwnedSemaphorePermit,
+    ) -> Self {
+        PooledClient {
+            client: Some(client),
+            canceller,
+            database,
+            notices,
+            pool,
+            clean: AtomicBool::new(false),
+            _permit: permit,
+        }
+    }
+
+    /// Cancels the query currently running on this session.
+    pub fn canceller(&self) -> QueryCanceller {
+        self.canceller.clone()
+    }
+
+    /// The database the session is connected to.
+    pub fn database(&self) -> &str {
+        &self.database
+    }
+
+    /// Where the connection driver keeps the notices of the running query.
+    pub fn notices(&self) -> &SessionNotices {
+        &self.notices
+    }
+
+    /// Roll back any transaction left open by user SQL and restore the session
+    /// defaults, so the next command borrowing this session starts clean.
+    ///
+    /// The init SQL is not run again: it only runs when the session is created,
+    /// so it does not have to be idempotent.
+    ///
+    /// If the reset fails the session is closed instead of going back to the pool.
+    pub async fn reset_session(&mut self) {
+        let client = self.client.as_ref().expect("client is only taken on drop");
+        let result = async {
+            client.simple_query("rollback").await.map_err(PgConnectError::from)?;
+            client.batch_execute("reset all; reset role").await.map_err(PgConnectError::from)
+        }
+        .await;
+        *self.clean.get_mut() = result.is_ok();
+    }
+
+    /// Let the session go back to the pool as it is, for commands that only run statements
+    /// outside of transactions and change no setting. Call it once they are done.
+    pub fn mark_clean(&self) {
+        self.clean.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Deref for PooledClient {
+    type Target = PgClient;
+
+    fn deref(&self) -> &PgClient {
+        self.client.as_ref().expect("client is only taken on drop")
+    }
+}
+
+impl Drop for PooledClient {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            if *self.clean.get_mut() && !client.is_closed() {
+                let now = Instant::now();
+                self.pool.idle.lock().unwrap().push(IdleClient {
+                    client,
+                    canceller: self.canceller.clone(),
+                    database: std::mem::take(&mut self.database),
+                    notices: self.notices.clone(),
+                    idle_since: now,
+                    checked_at: now,
+                });
+            }
+        }
+    }
+}
+
+/// Tauri-managed registry of pools, keyed by the saved connection id.
+pub struct PgPools {
+    pools: Mutex<HashMap<String, Arc<PgPool>>>,
+    app: AppHandle,
+}
+
+impl PgPools {
+    pub fn new(app: AppHandle) -> Self {
+        PgPools { pools: Mutex::new(HashMap::new()), app }
+    }
+
+    /// Borrow a session for the given connection, opening the pool on first use.
+    ///
+    /// When a connection is edited its config no longer matches and the old pool is replaced.
+    pub async fn get(&self, config: &PgConnectionConfig) -> Result<PooledClient, PgConnectError> {
+        self.pool(config).get().await
+    }
+
+    /// Run an idempotent read command, retrying with backoff on a fresh session
+    /// when the connection drops or cannot be opened.
+    ///
+    /// A failure is only retried when the session it ran on got closed, so query
+    /// errors reported by the server are returned right away. Commands must not open
+    /// transactions or change settings, their session goes back to the pool as it is.
+    pub async fn read<T, F, Fut>(&self, config: &PgConnectionConfig, command: F) -> Result<T, CommandError>
+    where
+        F: Fn(Arc<PooledClient>) -> Fut,
+        Fut: Future<Output = Result<T, CommandError>>,
+    {
+        let pool = self.pool(config);
+        let mut attempt = 0;
+        loop {
+            let error: CommandError = match pool.get().await {
+                Ok(client) => {
+                    let client = Arc::new(client);
+                    let result = command(client.clone()).await;
+                    client.mark_clean();
+                    match result {
+                        Err(e) if client.is_closed() => e,
+                        result => return result,
+                    }
+                }
+                Err(e) if e.kind.is_transient() => e.into(),
+                Err(e) => return Err(e.into()),
+            };
+
+            let Some(delay) = RECONNECT_BACKOFF.get(attempt) else {
+                return Err(error);
+            };
+            attempt += 1;
+            pool.set_status(
+                ConnectionStatus::Reconnecting,
+                Some(format!("{} (attempt {} of {})", error, attempt, RECONNECT_BACKOFF.len())),
+            );
+            tokio::time::sleep(*delay).await;
+        }
+    }
+
+    fn pool(&self, config: &PgConnectionConfig) -> Arc<PgPool> {
+        let mut pools = self.pools.lock().unwrap();
+        match pools.get(&config.id) {
+            Some(pool) if pool.config == *config => pool.clone(),
+            _ => {
+                let pool = Arc::new(PgPool::new(config.clone(), Some(self.app.clone())));
+                pools.insert(config.id.clone(), pool.clone());
+                pool
+            }
+        }
+    }
+
+    /// Close every idle session of the given connection and forget its pool.
+    pub fn remove(&self, connection_id: &str) {
+        self.pools.lock().unwrap().remove(connection_id);
+    }
+
+    /// Close idle sessions that timed out or whose server side went away.
+    pub fn evict_idle(&self) {
+        for pool in self.all() {
+            pool.evict_idle();
+        }
+    }
+
+    /// Ping the idle sessions of every pool and emit `connection-status` events.
+    pub async fn keepalive(&self) {
+        futures::future::join_all(self.all().iter().map(|pool| pool.keepalive())).await;
+    }
+
+    fn all(&self) -> Vec<Arc<PgPool>> {
+        self.pools.lock().unwrap().values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pg::cursors::Cursors;
+    use tokio_postgres::SimpleQueryMessage;
+
+    /// These tests need a server, given like `PGDITOR_TEST_DATABASE_URL=postgres://postgres@localhost/postgres`,
+    /// and are skipped without one.
+    fn test_pool() -> Option<Arc<PgPool>> {
+        let config = PgConnectionConfig {
+            id: "test".to_string(),
+            connection_string: std::env::var("PGDITOR_TEST_DATABASE_URL").ok()?,
+            ssh_tunnel: None,
+            password_command: None,
+            read_only: false,
+            session_settings: Default::default(),
+            safety_policy: Default::default(),
+        };
+        Some(Arc::new(PgPool::new(config, None)))
+    }
+
+    async fn backend_pid(client: &PgClient) -> i32 {
+        client.query_one("select pg_backend_pid()", &[]).await.unwrap().get(0)
+    }
+
+    #[tokio::test]
+    async fn clean_sessions_are_reused() {
+        let Some(pool) = test_pool() else { return };
+        let client = pool.get().await.unwrap();
+        let pid = backend_pid(&client).await;
+        client.mark_clean();
+        drop(client);
+        assert_eq!(backend_pid(&pool.get().await.unwrap()).await, pid);
+    }
+
+    #[tokio::test]
+    async fn sessions_dropped_in_a_transaction_are_closed() {
+        let Some(pool) = test_pool() else { return };
+        let locker = pool.get().await.unwrap();
+        locker.batch_execute("create table if not exists pgditor_pool_test (id int)").await.unwrap();
+        locker.batch_execute("begin; lock table pgditor_pool_test").await.unwrap();
+
+        // Preparing the query of the cursor waits for the lock, after `open` began its
+        // transaction, until the command is cancelled
+        let client = pool.get().await.unwrap();
+        let pid = backend_pid(&client).await;
+        let cursors = Cursors::default();
+        let open = cursors.open(client, "test", "select * from pgditor_pool_test");
+        assert!(tokio::time::timeout(Duration::from_millis(500), open).await.is_err());
+        locker.batch_execute("rollback").await.unwrap();
+
+        // A simple query starts its own transaction unless one is open
+        let client = pool.get().await.unwrap();
+        let messages = client
+            .simple_query(
+                "select pid, txid_current_if_assigned() is null, xact_start = query_start
+                 from pg_stat_activity where pid = pg_backend_pid()",
+            )
+            .await
+            .unwrap();
+        let row = messages
+            .iter()
+            .find_map(|message| match message {
+                SimpleQueryMessage::Row(row) => Some(row),
+                _ => None,
+            })
+            .expect("the session is in pg_stat_activity");
+        assert_ne!(row.get(0), Some(pid.to_string().as_str()));
+        assert_eq!(row.get(1), Some("t"));
+        assert_eq!(row.get(2), Some("t"), "the session is still in a transaction");
+        drop(client);
+        locker.batch_execute("drop table pgditor_pool_test").await.unwrap();
+    }
+}
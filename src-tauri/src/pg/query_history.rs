@@ -0,0 +1,158 @@
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+use crate::commands::raw_query::{OnError, QueryMode};
+
+/// Older entries are dropped past this many.
+const MAX_ENTRIES: usize = 10_000;
+/// The file is only rewritten once it holds this many dropped entries, to append
+/// most of the time.
+const COMPACT_AFTER: usize = 1_000;
+
+/// A script run on a connection. Only the id of the connection is kept, never its
+/// connection string or credentials.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: String,
+    pub sql: String,
+    pub connection_id: String,
+    pub database: String,
+    pub started_at: DateTime<Utc>,
+    pub duration_ms: u64,
+    /// Rows returned or affected by all the statements, `None` when none has a count.
+    pub rows: Option<u64>,
+    pub status: HistoryStatus,
+    /// The SQLSTATE or pgditor code of the first error.
+    pub error_code: Option<String>,
+    /// How the script was run, to run it again the same way. Older entries have the defaults.
+    #[serde(default)]
+    pub mode: QueryMode,
+    #[serde(default)]
+    pub params: Vec<JsonValue>,
+    #[serde(default)]
+    pub on_error: Option<OnError>,
+    #[serde(default)]
+    pub keep_copy_output: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryStatus {
+    Ok,
+    Error,
+    Cancelled,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct HistoryFilter {
+    /// Matched case-insensitively anywhere in the SQL.
+    pub text: Option<String>,
+    pub connection_id: Option<String>,
+    pub status: Option<HistoryStatus>,
+    pub limit: Option<usize>,
+}
+
+/// A change to the history file.
+enum HistoryWrite {
+    Append(HistoryEntry),
+    Rewrite(Vec<HistoryEntry>),
+}
+
+/// Tauri-managed history of the scripts run, saved as JSON lines in the app data directory.
+pub struct QueryHistory {
+    /// Oldest first, like the file.
+    entries: Mutex<VecDeque<HistoryEntry>>,
+    /// The file is written by a thread of its own, to keep its I/O off the async runtime.
+    writer: Sender<HistoryWrite>,
+}
+
+impl QueryHistory {
+    /// Read the history saved in `path`, skipping the lines that cannot be parsed.
+    pub fn load(path: PathBuf) -> Self {
+        let mut entries: VecDeque<HistoryEntry> = match fs::read_to_string(&path) {
+            Ok(content) => content.lines().filter_map(|line| serde_json::from_str(line).ok()).collect(),
+            Err(_) => VecDeque::new(),
+        };
+        let (writer, writes) = mpsc::channel();
+        if entries.len() > MAX_ENTRIES {
+            entries.drain(..entries.len() - MAX_ENTRIES);
+            let _ = writer.send(HistoryWrite::Rewrite(entries.iter().cloned().collect()));
+        }
+        std::thread::spawn(move || write_history(&path, writes));
+        QueryHistory { entries: Mutex::new(entries), writer }
+    }
+
+    /// Add an entry, saving it in the background.
+    pub fn record(&self, entry: HistoryEntry) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.push_back(entry);
+        let write = if entries.len() > MAX_ENTRIES + COMPACT_AFTER {
+            let dropped = entries.len() - MAX_ENTRIES;
+            entries.drain(..dropped);
+            HistoryWrite::Rewrite(entries.iter().cloned().collect())
+        } else {
+            HistoryWrite::Append(entries.back().expect("an entry was just pushed").clone())
+        };
+        // The writer only stops if the app is shutting down
+        let _ = self.writer.send(write);
+    }
+
+    /// The entries matching `filter`, newest first.
+    pub fn search(&self, filter: &HistoryFilter) -> Vec<HistoryEntry> {
+        let text = filter.text.as_deref().map(str::to_lowercase).filter(|text| !text.is_empty());
+        let entries = self.entries.lock().unwrap();
+        entries
+            .iter()
+            .rev()
+            .filter(|entry| filter.connection_id.as_ref().is_none_or(|id| &entry.connection_id == id))
+            .filter(|entry| filter.status.is_none_or(|status| entry.status == status))
+            .filter(|entry| text.as_ref().is_none_or(|text| entry.sql.to_lowercase().contains(text)))
+            .take(filter.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+
+    pub fn get(&self, entry_id: &str) -> Option<HistoryEntry> {
+        self.entries.lock().unwrap().iter().find(|entry| entry.id == entry_id).cloned()
+    }
+}
+
+/// Apply the writes to the file in order, until the history is dropped.
+fn write_history(path: &Path, writes: Receiver<HistoryWrite>) {
+    for write in writes {
+        let result = match write {
+            HistoryWrite::Append(entry) => append_entry(path, &entry),
+            HistoryWrite::Rewrite(entries) => write_entries(path, &entries),
+        };
+        if let Err(e) = result {
+            eprintln!("Could not save the query history: {e}");
+        }
+    }
+}
+
+fn append_entry(path: &Path, entry: &HistoryEntry) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)
+}
+
+/// Replace the file, through a temporary one so a crash cannot lose the history.
+fn write_entries(path: &Path, entries: &[HistoryEntry]) -> std::io::Result<()> {
+    let mut content = String::new();
+    for entry in entries {
+        content += &serde_json::to_string(entry)?;
+        content.push('\n');
+    }
+    let temporary = path.with_extension("jsonl.tmp");
+    fs::write(&temporary, content)?;
+    fs::rename(temporary, path)
+}
@@ -0,0 +1,233 @@
+use std::path::{Path, PathBuf};
+
+use crate::pg::conn_params::{libpq_user_dir, ConnParams};
+use crate::pg::pg_connect::{PgConnectError, PgConnectErrorKind};
+
+/// Environment variables libpq reads for parameters missing from the connection string, see
+/// https://www.postgresql.org/docs/current/libpq-envars.html
+const ENV_DEFAULTS: &[(&str, &str)] = &[
+    ("host", "PGHOST"),
+    ("hostaddr", "PGHOSTADDR"),
+    ("port", "PGPORT"),
+    ("dbname", "PGDATABASE"),
+    ("user", "PGUSER"),
+    ("password", "PGPASSWORD"),
+    ("passfile", "PGPASSFILE"),
+    ("servicefile", "PGSERVICEFILE"),
+    ("channel_binding", "PGCHANNELBINDING"),
+    ("connect_timeout", "PGCONNECT_TIMEOUT"),
+    ("options", "PGOPTIONS"),
+    ("application_name", "PGAPPNAME"),
+    ("sslmode", "PGSSLMODE"),
+    ("sslnegotiation", "PGSSLNEGOTIATION"),
+    ("sslcompression", "PGSSLCOMPRESSION"),
+    ("sslcert", "PGSSLCERT"),
+    ("sslkey", "PGSSLKEY"),
+    ("sslrootcert", "PGSSLROOTCERT"),
+    ("sslcrl", "PGSSLCRL"),
+    ("sslcrldir", "PGSSLCRLDIR"),
+    ("sslsni", "PGSSLSNI"),
+    ("requirepeer", "PGREQUIREPEER"),
+    ("ssl_min_protocol_version", "PGSSLMINPROTOCOLVERSION"),
+    ("ssl_max_protocol_version", "PGSSLMAXPROTOCOLVERSION"),
+    ("gssencmode", "PGGSSENCMODE"),
+    ("krbsrvname", "PGKRBSRVNAME"),
+    ("gsslib", "PGGSSLIB"),
+    ("target_session_attrs", "PGTARGETSESSIONATTRS"),
+    ("load_balance_hosts", "PGLOADBALANCEHOSTS"),
+];
+
+/// Directories where a local server usually puts its socket.
+const SOCKET_DIRS: &[&str] = if cfg!(unix) { &["/var/run/postgresql", "/tmp"] } else { &[] };
+
+/// Fill in what the connection string leaves out the way libpq does: first from the
+/// `service` definition in `pg_service.conf`, then from the `PG*` environment variables.
+/// The password file is only read for each host when connecting, see `passfile_password`.
+pub fn resolve(params: &mut ConnParams) -> Result<(), PgConnectError> {
+    let service = params.get("service").map(String::from).or_else(|| env_var("PGSERVICE"));
+    if let Some(service) = service {
+        apply_service(params, &service)?;
+    }
+
+    for (key, var) in ENV_DEFAULTS {
+        if params.get(key).is_none() {
+            if let Some(value) = env_var(var) {
+                params.set(key, value);
+            }
+        }
+    }
+    if params.get("user").is_none() {
+        if let Some(user) = env_var(if cfg!(windows) { "USERNAME" } else { "USER" }) {
+            params.set("user", user);
+        }
+    }
+    Ok(())
+}
+
+/// The host libpq uses when none is given: the local server's socket directory on Unix,
+/// `localhost` otherwise.
+pub fn default_host(params: &ConnParams) -> String {
+    let socket = format!(".s.PGSQL.{}", first_port(params));
+    SOCKET_DIRS
+        .iter()
+        .find(|dir| Path::new(dir).join(&socket).exists())
+        .map_or_else(|| "localhost".to_string(), |dir| dir.to_string())
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+fn first_port(params: &ConnParams) -> String {
+    params
+        .get("port")
+        .and_then(|ports| ports.split(',').next())
+        .filter(|port| !port.is_empty())
+        .unwrap_or("5432")
+        .to_string()
+}
+
+/// Copy the parameters of `[service]` that the connection string did not set. Like libpq,
+/// the per-user file is searched first and the system-wide one only if the service is not there.
+fn apply_service(params: &mut ConnParams, service: &str) -> Result<(), PgConnectError> {
+    let mut files = Vec::new();
+    match params.get("servicefile").map(String::from).or_else(|| env_var("PGSERVICEFILE")) {
+        Some(path) => files.push(PathBuf::from(path)),
+        None => files.extend(user_service_file()),
+    }
+    if let Some(dir) = env_var("PGSYSCONFDIR") {
+        files.push(PathBuf::from(dir).join("pg_service.conf"));
+    }
+
+    for file in &files {
+        let Ok(content) = std::fs::read_to_string(file) else {
+            continue;
+        };
+        if let Some(entries) = parse_service_file(&content, service, file)? {
+            for (key, value) in entries {
+                if params.get(&key).is_none() {
+                    params.set(&key, value);
+                }
+            }
+            return Ok(());
+        }
+    }
+
+    Err(PgConnectError {
+        kind: PgConnectErrorKind::Config,
+        message: format!("Definition of service \"{}\" not found", service),
+    })
+}
+
+fn user_service_file() -> Option<PathBuf> {
+    if cfg!(windows) {
+        libpq_user_dir().map(|dir| dir.join(".pg_service.conf"))
+    } else {
+        std::env::var_os("HOME").map(|dir| PathBuf::from(dir).join(".pg_service.conf"))
+    }
+}
+
+/// The `key=value` lines under `[service]`, or `None` if the file does not define it.
+fn parse_service_file(
+    content: &str,
+    service: &str,
+    path: &Path,
+) -> Result<Option<Vec<(String, String)>>, PgConnectError> {
+    let mut entries: Option<Vec<(String, String)>> = None;
+
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            if entries.is_some() {
+                break;
+            }
+            if name == service {
+                entries = Some(Vec::new());
+            }
+            continue;
+        }
+        let Some(entries) = entries.as_mut() else {
+            continue;
+        };
+
+        let invalid = |reason: &str| PgConnectError {
+            kind: PgConnectErrorKind::Config,
+            message: format!("Syntax error in service file \"{}\", line {}: {}", path.display(), i + 1, reason),
+        };
+        let (key, value) = line.split_once('=').ok_or_else(|| invalid("missing \"=\""))?;
+        let key = key.trim();
+        if key == "service" {
+            return Err(invalid("nested service specifications are not supported"));
+        }
+        entries.push((key.to_string(), value.trim().to_string()));
+    }
+
+    Ok(entries)
+}
+
+/// The password of the password file for `host` and `port`, with the user and database of
+/// `params`. `host` is the host name, or the `hostaddr` when there is none.
+pub fn passfile_password(params: &ConnParams, host: &str, port: u16) -> Option<String> {
+    let path = match params.get("passfile") {
+        Some(path) => PathBuf::from(path),
+        None if cfg!(windows) => libpq_user_dir()?.join("pgpass.conf"),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".pgpass"),
+    };
+    let content = read_passfile(&path)?;
+
+    let user = params.get("user").unwrap_or("");
+    let dbname = params.get("dbname").unwrap_or(user);
+    let port = port.to_string();
+    // Sockets in the default directory match `localhost` entries
+    let host = if host.is_empty() || SOCKET_DIRS.contains(&host) { "localhost" } else { host };
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(parse_passfile_line)
+        .find(|entry| {
+            [host, &port, dbname, user]
+                .iter()
+                .zip(entry.iter())
+                .all(|(value, pattern)| pattern == "*" || pattern == value)
+        })
+        .map(|mut entry| entry.remove(4))
+}
+
+/// Read the password file, ignoring it like libpq does when it is not a plain file
+/// or when other users could read it.
+fn read_passfile(path: &Path) -> Option<String> {
+    let metadata = std::fs::metadata(path).ok()?;
+    if !metadata.is_file() {
+        eprintln!("Password file \"{}\" is not a plain file, ignoring it", path.display());
+        return None;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o077 != 0 {
+            eprintln!(
+                "Password file \"{}\" has group or world access; permissions should be u=rw (0600) or less, ignoring it",
+                path.display()
+            );
+            return None;
+        }
+    }
+    std::fs::read_to_string(path).ok()
+}
+
+/// Split `hostname:port:database:username:password`, honouring `\:` and `\\` escapes.
+fn parse_passfile_line(line: &str) -> Option<Vec<String>> {
+    let mut fields = vec![String::new()];
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => fields.last_mut()?.extend(chars.next()),
+            ':' if fields.len() < 5 => fields.push(String::new()),
+            c => fields.last_mut()?.push(c),
+        }
+    }
+    (fields.len() == 5).then_some(fields)
+}
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use tauri::ipc::Channel;
+use tokio::sync::Semaphore;
+
+use crate::error::CommandError;
+use crate::pg::models::PgResultColumn;
+
+/// Rows sent before anything else, so the grid has something to show right away.
+const FIRST_BATCH_SIZE: usize = 100;
+
+const BATCH_SIZE: usize = 2000;
+
+/// Batches sent but not acknowledged with `ack_rows` yet. Once reached, reading
+/// from the server pauses until the frontend catches up.
+const MAX_BATCHES_IN_FLIGHT: usize = 4;
+
+/// A frontend that acknowledges nothing for this long is assumed gone.
+const ACK_TIMEOUT: Duration = Duration::from_secs(2 * 60);
+
+/// Messages sent over the channel of `stream_query` and `stream_table_data`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ResultEvent {
+    /// A statement is about to return rows.
+    Columns { statement: usize, columns: Vec<PgResultColumn> },
+    /// Rows of a statement, to acknowledge with `ack_rows` once rendered. `first`
+    /// is set on the first, smaller batch of the statement.
+    Rows { statement: usize, rows: Vec<JsonValue>, first: bool, total_rows: u64 },
+    StatementComplete {
+        statement: usize,
+        command_tag: String,
+        rows_affected: Option<u64>,
+        duration_ms: u64,
+    },
+    /// A statement failed, when the script goes on after errors.
+    StatementFailed { statement: usize, message: String, duration_ms: u64 },
+}
+
+/// Tauri-managed flow control of the streamed results, keyed by query id.
+#[derive(Default)]
+pub struct ResultStreams {
+    credits: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl ResultStreams {
+    /// Let the given query send one more batch.
+    pub fn ack(&self, query_id: &str) {
+        if let Some(credits) = self.credits.lock().unwrap().get(query_id) {
+            credits.add_permits(1);
+        }
+    }
+}
+
+/// Where a query puts the rows it reads: in memory for commands returning them,
+/// or over a channel in batches.
+pub enum RowSink<'a> {
+    Collect(Vec<JsonValue>),
+    Stream(ResultStream<'a>),
+}
+
+pub struct ResultStream<'a> {
+    streams: &'a ResultStreams,
+    query_id: String,
+    channel: Channel<ResultEvent>,
+    credits: Arc<Semaphore>,
+    statement: usize,
+    pending: Vec<JsonValue>,
+    /// Rows of the current statement already sent.
+    sent_rows: u64,
+}
+
+impl<'a> RowSink<'a> {
+    pub fn collect() -> Self {
+        RowSink::Collect(Vec::new())
+    }
+
+    pub fn stream(streams: &'a ResultStreams, query_id: &str, channel: Channel<ResultEvent>) -> Self {
+        let credits = Arc::new(Semaphore::new(MAX_BATCHES_IN_FLIGHT));
+        streams.credits.lock().unwrap().insert(query_id.to_string(), credits.clone());
+        RowSink::Stream(ResultStream {
+            streams,
+            query_id: query_id.to_string(),
+            channel,
+            credits,
+            statement: 0,
+            pending: Vec::new(),
+            sent_rows: 0,
+        })
+    }
+
+    /// Rows of the current statement pushed so far.
+    pub fn rows_read(&self) -> u64 {
+        match self {
+            RowSink::Collect(rows) => rows.len() as u64,
+            RowSink::Stream(stream) => stream.sent_rows + stream.pending.len() as u64,
+        }
+    }
+
+    pub fn columns(&mut self, columns: Vec<PgResultColumn>) -> Result<(), CommandError> {
+        match self {
+            RowSink::Collect(_) => Ok(()),
+            RowSink::Stream(stream) => stream.send(ResultEvent::Columns { statement: stream.statement, columns }),
+        }
+    }
+
+    pub async fn push(&mut self, row: JsonValue) -> Result<(), CommandError> {
+        match self {
+            RowSink::Collect(rows) => {
+                rows.push(row);
+                Ok(())
+            }
+            RowSink::Stream(stream) => {
+                stream.pending.push(row);
+                let batch_size = if stream.sent_rows == 0 { FIRST_BATCH_SIZE } else { BATCH_SIZE };
+                if stream.pending.len() >= batch_size {
+                    stream.flush().await?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// End the current statement, returning its rows when they are collected.
+    pub async fn finish(
+        &mut self,
+        command_tag: &str,
+        rows_affected: Option<u64>,
+        duration_ms: u64,
+    ) -> Result<Vec<JsonValue>, CommandError> {
+        match self {
+            RowSink::Collect(rows) => Ok(std::mem::take(rows)),
+            RowSink::Stream(stream) => {
+                if !stream.pending.is_empty() {
+                    stream.flush().await?;
+                }
+                stream.send(ResultEvent::StatementComplete {
+                    statement: stream.statement,
+                    command_tag: command_tag.to_string(),
+                    rows_affected,
+                    duration_ms,
+                })?;
+                stream.statement += 1;
+                stream.sent_rows = 0;
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    /// End the current statement on an error, dropping the rows not sent yet.
+    pub fn fail(&mut self, message: &str, duration_ms: u64) -> Result<(), CommandError> {
+        match self {
+            RowSink::Collect(rows) => {
+                rows.clear();
+                Ok(())
+            }
+            RowSink::Stream(stream) => {
+                stream.pending.clear();
+                stream.send(ResultEvent::StatementFailed {
+                    statement: stream.statement,
+                    message: message.to_string(),
+                    duration_ms,
+                })?;
+                stream.statement += 1;
+                stream.sent_rows = 0;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl ResultStream<'_> {
+    /// Send the pending rows, first waiting for the frontend if too many batches are in flight.
+    async fn flush(&mut self) -> Result<(), CommandError> {
+        tokio::time::timeout(ACK_TIMEOUT, self.credits.acquire())
+            .await
+            .map_err(|_| CommandError::from("The results were not read by the application in time"))?
+            .expect("stream semaphore is never closed")
+            .forget();
+
+        let rows = std::mem::take(&mut self.pending);
+        let first = self.sent_rows == 0;
+        self.sent_rows += rows.len() as u64;
+        self.send(ResultEvent::Rows { statement: self.statement, rows, first, total_rows: self.sent_rows })
+    }
+
+    fn send(&self, event: ResultEvent) -> Result<(), CommandError> {
+        self.channel
+            .send(event)
+            .map_err(|e| CommandError::from(format!("Could not send results to the application: {}", e)))
+    }
+}
+
+impl Drop for ResultStream<'_> {
+    fn drop(&mut self) {
+        self.streams.credits.lock().unwrap().remove(&self.query_id);
+    }
+}
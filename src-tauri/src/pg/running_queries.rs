@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+use crate::error::CommandError;
+use crate::pg::pg_connect::QueryCanceller;
+
+struct RunningQuery {
+    canceller: QueryCanceller,
+    cancelled: Arc<AtomicBool>,
+    /// Wakes up a query waiting on something other than the server, like the
+    /// frontend reading streamed rows.
+    interrupt: Arc<Notify>,
+}
+
+/// Tauri-managed registry of the queries being executed, keyed by the query id
+/// chosen by the frontend, so `cancel_query` can stop them.
+#[derive(Default)]
+pub struct RunningQueries {
+    queries: Mutex<HashMap<String, RunningQuery>>,
+}
+
+impl RunningQueries {
+    /// Run `query` under the given id. If it is cancelled with `cancel`, its error is
+    /// replaced by a `query_cancelled` one.
+    pub async fn run<T, E>(
+        &self,
+        query_id: &str,
+        canceller: QueryCanceller,
+        query: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, CommandError>
+    where
+        E: Into<CommandError>,
+    {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let interrupt = Arc::new(Notify::new());
+        self.queries.lock().unwrap().insert(
+            query_id.to_string(),
+            RunningQuery { canceller, cancelled: cancelled.clone(), interrupt: interrupt.clone() },
+        );
+        let _guard = Unregister { queries: self, query_id };
+
+        tokio::select! {
+            result = query => result.map_err(|e| {
+                if cancelled.load(Ordering::SeqCst) {
+                    cancelled_error()
+                } else {
+                    e.into()
+                }
+            }),
+            _ = interrupt.notified() => Err(cancelled_error()),
+        }
+    }
+
+    /// Ask the server to cancel the query. Returns `false` if it already finished.
+    pub async fn cancel(&self, query_id: &str) -> Result<bool, CommandError> {
+        let (canceller, interrupt) = match self.queries.lock().unwrap().get(query_id) {
+            Some(query) => {
+                query.cancelled.store(true, Ordering::SeqCst);
+                (query.canceller.clone(), query.interrupt.clone())
+            }
+            None => return Ok(false),
+        };
+        // Stop the server first, so the session is idle again when the command gives up on it
+        let result = canceller.cancel().await;
+        interrupt.notify_one();
+        result.map_err(CommandError::from)?;
+        Ok(true)
+    }
+}
+
+fn cancelled_error() -> CommandError {
+    CommandError::with_code("query_cancelled", "Query cancelled")
+}
+
+/// Forgets the query when it finishes, or when the command is dropped.
+struct Unregister<'a> {
+    queries: &'a RunningQueries,
+    query_id: &'a str,
+}
+
+impl Drop for Unregister<'_> {
+    fn drop(&mut self) {
+        self.queries.queries.lock().unwrap().remove(self.query_id);
+    }
+}
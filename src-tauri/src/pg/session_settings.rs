@@ -0,0 +1,59 @@
+use serde::Deserialize;
+
+use crate::pg::conn_params::ConnParams;
+
+/// Used when neither the settings nor the connection string name the application.
+const DEFAULT_APPLICATION_NAME: &str = "pgditor";
+
+/// Settings applied to every session of a connection.
+///
+/// They are sent as startup options rather than `SET` commands, so they become the
+/// session defaults and `RESET ALL` between commands brings them back.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionSettings {
+    pub search_path: Option<String>,
+    /// Any duration Postgres accepts, like `30s` or `5min`. Bare numbers are milliseconds.
+    pub statement_timeout: Option<String>,
+    pub lock_timeout: Option<String>,
+    pub idle_in_transaction_session_timeout: Option<String>,
+    /// Role to switch to after logging in, as with `SET ROLE`.
+    pub role: Option<String>,
+    pub time_zone: Option<String>,
+    pub application_name: Option<String>,
+    /// SQL run once when a session is opened, like `create temp table`. The reset between
+    /// commands undoes any `SET` it issues, so session defaults belong in the fields above.
+    pub init_sql: Option<String>,
+}
+
+impl SessionSettings {
+    pub fn apply(&self, params: &mut ConnParams) {
+        let options = [
+            ("search_path", &self.search_path),
+            ("statement_timeout", &self.statement_timeout),
+            ("lock_timeout", &self.lock_timeout),
+            ("idle_in_transaction_session_timeout", &self.idle_in_transaction_session_timeout),
+            ("role", &self.role),
+            ("TimeZone", &self.time_zone),
+        ];
+        for (name, value) in options {
+            if let Some(value) = value.as_deref().filter(|value| !value.trim().is_empty()) {
+                params.add_option(name, value.trim());
+            }
+        }
+
+        match self.application_name.as_deref().filter(|name| !name.trim().is_empty()) {
+            Some(name) => params.set("application_name", name.trim()),
+            None => {
+                if params.get("fallback_application_name").is_none() {
+                    params.set("fallback_application_name", DEFAULT_APPLICATION_NAME);
+                }
+            }
+        }
+    }
+
+    /// The init SQL, if there is anything to run.
+    pub fn init_sql(&self) -> Option<&str> {
+        self.init_sql.as_deref().filter(|sql| !sql.trim().is_empty())
+    }
+}
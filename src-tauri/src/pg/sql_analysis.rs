@@ -0,0 +1,452 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::OnceLock;
+use serde::{Deserialize, Serialize};
+use sqlparser::ast::{Query, SetExpr, Statement, UtilityOption};
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::keywords::Keyword;
+use sqlparser::parser::Parser;
+use sqlparser::tokenizer::{Token, Tokenizer};
+
+use crate::error::CommandError;
+use crate::pg::copy::{client_copy, ClientCopy};
+use crate::pg::sql_split::{script_position, split_statements, SqlStatement};
+
+/// What a statement does, declared from the least to the most destructive, `Unknown` aside.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatementKind {
+    /// Queries, `SHOW`, `EXPLAIN` without `ANALYZE` and `COPY ... TO`.
+    ReadOnly,
+    /// `BEGIN`, `COMMIT`, `ROLLBACK`, savepoints and `SET TRANSACTION`.
+    TransactionControl,
+    /// Statements that add rows or objects or change settings, like `INSERT`, `CREATE`,
+    /// `COPY ... FROM` or `SET`.
+    Other,
+    /// `UPDATE` or `DELETE` limited by a `WHERE`, and `MERGE`.
+    DmlWithWhere,
+    /// `UPDATE` or `DELETE` of every row of a table.
+    DmlWithoutWhere,
+    Truncate,
+    /// `DROP` or `ALTER` of any object.
+    DropOrAlter,
+    /// The parser does not support the statement, or it runs code that cannot be looked
+    /// into, like `CALL` or `EXECUTE`.
+    Unknown,
+}
+
+impl StatementKind {
+    fn description(self) -> &'static str {
+        match self {
+            StatementKind::ReadOnly => "only reads data",
+            StatementKind::TransactionControl => "controls the transaction",
+            StatementKind::Other => "changes the database",
+            StatementKind::DmlWithWhere => "updates or deletes rows",
+            StatementKind::DmlWithoutWhere => "updates or deletes every row of a table",
+            StatementKind::Truncate => "empties tables",
+            StatementKind::DropOrAlter => "drops or alters objects",
+            StatementKind::Unknown => "could not be analyzed",
+        }
+    }
+}
+
+/// Which statements of a connection need a confirmation before they run.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SafetyPolicy {
+    pub confirm: Vec<StatementKind>,
+}
+
+impl Default for SafetyPolicy {
+    fn default() -> Self {
+        SafetyPolicy {
+            confirm: vec![StatementKind::DmlWithoutWhere, StatementKind::Truncate, StatementKind::DropOrAlter],
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatementAnalysis {
+    #[serde(flatten)]
+    pub statement: SqlStatement,
+    pub kind: StatementKind,
+    /// The policy of the connection requires a confirmation for this statement.
+    pub needs_confirmation: bool,
+    /// Why the statement could not be parsed, its kind is then guessed from its tokens.
+    pub parse_error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SqlAnalysis {
+    pub statements: Vec<StatementAnalysis>,
+    /// To pass as `confirmation` to run the script, set when a statement needs one. It
+    /// only confirms this exact script on this connection.
+    pub confirmation_token: Option<String>,
+}
+
+/// Classify each statement of a script and tell which ones `policy` wants confirmed.
+pub fn analyze_sql(connection_id: &str, policy: &SafetyPolicy, sql: &str) -> SqlAnalysis {
+    let statements: Vec<StatementAnalysis> = split_statements(sql)
+        .into_iter()
+        .map(|statement| {
+            let (kind, parse_error) = statement_kind(&statement.sql);
+            StatementAnalysis { needs_confirmation: policy.confirm.contains(&kind), statement, kind, parse_error }
+        })
+        .collect();
+    let confirmation_token =
+        statements.iter().any(|statement| statement.needs_confirmation).then(|| confirmation_token(connection_id, sql));
+    SqlAnalysis { statements, confirmation_token }
+}
+
+/// Refuse scripts with statements `policy` wants confirmed, unless `confirmation` is the
+/// token `analyze_sql` returned for them.
+pub fn check_confirmation(
+    connection_id: &str,
+    policy: &SafetyPolicy,
+    sql: &str,
+    confirmation: Option<&str>,
+) -> Result<(), CommandError> {
+    if policy.confirm.is_empty() {
+        return Ok(());
+    }
+    let analysis = analyze_sql(connection_id, policy, sql);
+    let (Some(token), Some(statement)) =
+        (&analysis.confirmation_token, analysis.statements.iter().find(|statement| statement.needs_confirmation))
+    else {
+        return Ok(());
+    };
+    if confirmation == Some(token.as_str()) {
+        return Ok(());
+    }
+    let line = script_position(sql, &statement.statement, 1).line;
+    Err(CommandError::with_code(
+        "confirmation_required",
+        format!("The statement on line {} {}, confirm it to run the script", line, statement.kind.description()),
+    ))
+}
+
+/// Whether every statement of `sql` only reads data, so that it can run on a read-only
+/// connection. `set_config` can only change settings other than the read-only ones, named
+/// by a literal.
+///
+/// The server still has the last word: the statements run in a read-only transaction that
+/// also refuses the writes of the functions they call.
+pub fn is_read_only(sql: &str) -> bool {
+    split_statements(sql).iter().all(|statement| statement_kind(&statement.sql).0 == StatementKind::ReadOnly)
+        && !sets_read_only_mode(sql)
+}
+
+/// Whether `sql` is a single statement that can be the body of a `WITH`: a query that
+/// does not change data, or an `INSERT`, `UPDATE` or `DELETE`.
+pub fn nests_in_with(sql: &str) -> bool {
+    let statement = sql.trim_start_matches(|c: char| c == '(' || c.is_whitespace());
+    let keyword: String = statement.chars().take_while(|c| c.is_ascii_alphabetic()).collect();
+    match keyword.to_ascii_lowercase().as_str() {
+        "select" | "values" | "table" | "insert" | "update" | "delete" => true,
+        // A `WITH` changing data has to stay at the top level
+        "with" => matches!(
+            Parser::parse_sql(&PostgreSqlDialect {}, sql).as_deref(),
+            Ok([Statement::Query(query)]) if classify_query(query) == StatementKind::ReadOnly
+        ),
+        _ => false,
+    }
+}
+
+/// Tokens are keyed per run of the app, so they cannot be made up or reused after a restart.
+fn confirmation_token(connection_id: &str, sql: &str) -> String {
+    static KEY: OnceLock<RandomState> = OnceLock::new();
+    format!("{:016x}", KEY.get_or_init(RandomState::new).hash_one((connection_id, sql)))
+}
+
+fn statement_kind(sql: &str) -> (StatementKind, Option<String>) {
+    // `\copy` and `COPY ... STDIN` are handled by the client, not the parser
+    match client_copy(sql) {
+        Ok(Some(ClientCopy::ToStdout { .. } | ClientCopy::ToFile { .. })) => return (StatementKind::ReadOnly, None),
+        Ok(Some(ClientCopy::FromStdin { .. } | ClientCopy::FromFile { .. })) => return (StatementKind::Other, None),
+        Err(e) => return (StatementKind::Unknown, Some(e.to_string())),
+        Ok(None) => {}
+    }
+    match Parser::parse_sql(&PostgreSqlDialect {}, sql) {
+        Ok(statements) => {
+            let kind = statements.iter().map(classify).max().unwrap_or(StatementKind::ReadOnly);
+            (kind, None)
+        }
+        Err(e) => (unparsed_kind(sql), Some(e.to_string())),
+    }
+}
+
+fn classify(statement: &Statement) -> StatementKind {
+    match statement {
+        Statement::Query(query) => classify_query(query),
+        Statement::Update { selection, .. } => dml_kind(selection.is_some()),
+        Statement::Delete(delete) => dml_kind(delete.selection.is_some()),
+        Statement::Merge { .. } => StatementKind::DmlWithWhere,
+        Statement::Truncate { .. } => StatementKind::Truncate,
+        Statement::Drop { .. }
+        | Statement::DropFunction { .. }
+        | Statement::DropProcedure { .. }
+        | Statement::DropTrigger { .. }
+        | Statement::DropPolicy { .. }
+        | Statement::DropSecret { .. }
+        | Statement::AlterTable { .. }
+        | Statement::AlterIndex { .. }
+        | Statement::AlterView { .. }
+        | Statement::AlterRole { .. }
+        | Statement::AlterPolicy { .. } => StatementKind::DropOrAlter,
+        Statement::StartTransaction { .. }
+        | Statement::SetTransaction { .. }
+        | Statement::Commit { .. }
+        | Statement::Rollback { .. }
+        | Statement::Savepoint { .. }
+        | Statement::ReleaseSavepoint { .. } => StatementKind::TransactionControl,
+        // Only `EXPLAIN ANALYZE` runs the statement
+        Statement::Explain { analyze, options, statement, .. } if *analyze || analyzes(options) => {
+            classify(statement)
+        }
+        Statement::Explain { .. }
+        | Statement::ExplainTable { .. }
+        | Statement::ShowVariable { .. }
+        | Statement::ShowVariables { .. }
+        | Statement::ShowTables { .. }
+        | Statement::ShowColumns { .. }
+        | Statement::ShowFunctions { .. }
+        | Statement::ShowCollation { .. }
+        | Statement::Declare { .. }
+        | Statement::Fetch { .. }
+        | Statement::Close { .. } => StatementKind::ReadOnly,
+        Statement::Copy { to: true, .. } => StatementKind::ReadOnly,
+        Statement::Call(_) | Statement::Execute { .. } => StatementKind::Unknown,
+        _ => StatementKind::Other,
+    }
+}
+
+/// Queries can change data through the `INSERT` or `UPDATE` of a `WITH`, or create a
+/// table with `SELECT INTO`.
+fn classify_query(query: &Query) -> StatementKind {
+    let ctes = query.with.iter().flat_map(|with| &with.cte_tables).map(|cte| classify_query(&cte.query));
+    ctes.chain([classify_set_expr(&query.body)]).max().unwrap_or(StatementKind::ReadOnly)
+}
+
+fn classify_set_expr(body: &SetExpr) -> StatementKind {
+    match body {
+        SetExpr::Select(select) if select.into.is_some() => StatementKind::Other,
+        SetExpr::Select(_) | SetExpr::Values(_) | SetExpr::Table(_) => StatementKind::ReadOnly,
+        SetExpr::Query(query) => classify_query(query),
+        SetExpr::SetOperation { left, right, .. } => classify_set_expr(left).max(classify_set_expr(right)),
+        SetExpr::Insert(statement) | SetExpr::Update(statement) => classify(statement),
+    }
+}
+
+fn dml_kind(has_where: bool) -> StatementKind {
+    if has_where {
+        StatementKind::DmlWithWhere
+    } else {
+        StatementKind::DmlWithoutWhere
+    }
+}
+
+/// Whether the options of `EXPLAIN (ANALYZE, ...)` turn `ANALYZE` on.
+fn analyzes(options: &Option<Vec<UtilityOption>>) -> bool {
+    options.iter().flatten().any(|option| {
+        option.name.value.eq_ignore_ascii_case("analyze")
+            && option.arg.as_ref().is_none_or(|arg| {
+                let arg = arg.to_string().trim_matches('\'').to_ascii_lowercase();
+                !matches!(arg.as_str(), "false" | "off" | "no" | "0")
+            })
+    })
+}
+
+/// The parser misses some of the Postgres syntax, like data-modifying `WITH` queries, `DO`
+/// blocks or `DROP` of some object types. Their destructive commands are still found among
+/// their tokens, erring on the side of asking for a confirmation.
+fn unparsed_kind(sql: &str) -> StatementKind {
+    if is_table_command(sql) {
+        return StatementKind::ReadOnly;
+    }
+    destructive_commands(sql).into_iter().max().unwrap_or(StatementKind::Unknown)
+}
+
+/// Whether `sql` is `TABLE name`, short for `SELECT * FROM name`.
+fn is_table_command(sql: &str) -> bool {
+    let dialect = PostgreSqlDialect {};
+    let Ok(tokens) = Tokenizer::new(&dialect, sql).tokenize() else {
+        return false;
+    };
+    let mut tokens = tokens.iter().filter(|token| !matches!(token, Token::Whitespace(_) | Token::SemiColon));
+    matches!(tokens.next(), Some(Token::Word(word)) if word.keyword == Keyword::TABLE)
+        && tokens.enumerate().all(|(i, token)| match token {
+            Token::Word(_) => i % 2 == 0,
+            Token::Period => i % 2 == 1,
+            _ => false,
+        })
+}
+
+fn destructive_commands(sql: &str) -> Vec<StatementKind> {
+    let dialect = PostgreSqlDialect {};
+    let Ok(tokens) = Tokenizer::new(&dialect, sql).tokenize() else {
+        return Vec::new();
+    };
+    let tokens: Vec<&Token> = tokens.iter().filter(|token| !matches!(token, Token::Whitespace(_))).collect();
+    let mut kinds = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            // The body of a `DO` block or of a function
+            Token::DollarQuotedString(body) => kinds.extend(destructive_commands(&body.value)),
+            Token::Word(word) if starts_command(i.checked_sub(1).map(|i| tokens[i])) => match word.keyword {
+                Keyword::DELETE | Keyword::UPDATE => kinds.push(dml_kind(has_where(&tokens[i + 1..]))),
+                Keyword::TRUNCATE => kinds.push(StatementKind::Truncate),
+                Keyword::DROP | Keyword::ALTER => kinds.push(StatementKind::DropOrAlter),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    kinds
+}
+
+/// Whether `sql` calls `set_config` on `transaction_read_only` or
+/// `default_transaction_read_only`, or on a setting it computes.
+fn sets_read_only_mode(sql: &str) -> bool {
+    const READ_ONLY_SETTINGS: &[&str] = &["transaction_read_only", "default_transaction_read_only"];
+
+    let dialect = PostgreSqlDialect {};
+    let Ok(tokens) = Tokenizer::new(&dialect, sql).tokenize() else {
+        return true;
+    };
+    let tokens: Vec<&Token> = tokens.iter().filter(|token| !matches!(token, Token::Whitespace(_))).collect();
+    tokens.iter().enumerate().any(|(i, token)| match token {
+        Token::DollarQuotedString(body) => sets_read_only_mode(&body.value),
+        Token::Word(word) if word.value.eq_ignore_ascii_case("set_config") => {
+            match (tokens.get(i + 1), tokens.get(i + 2), tokens.get(i + 3)) {
+                (Some(Token::LParen), Some(Token::SingleQuotedString(name)), Some(Token::Comma)) => {
+                    READ_ONLY_SETTINGS.contains(&name.trim().to_ascii_lowercase().as_str())
+                }
+                // A computed name could be any setting
+                _ => true,
+            }
+        }
+        _ => false,
+    })
+}
+
+/// Whether a word after `previous` starts a command, so the `UPDATE` of `FOR UPDATE` or the
+/// `DELETE` of `ON DELETE CASCADE` are not taken for one.
+fn starts_command(previous: Option<&Token>) -> bool {
+    match previous {
+        None | Some(Token::LParen | Token::SemiColon) => true,
+        Some(Token::Word(word)) => {
+            matches!(word.keyword, Keyword::BEGIN | Keyword::THEN | Keyword::ELSE)
+                || word.value.eq_ignore_ascii_case("loop")
+        }
+        _ => false,
+    }
+}
+
+/// Whether the command the tokens follow has a `WHERE` of its own, before it ends with a
+/// `;` or the parenthesis around it.
+fn has_where(tokens: &[&Token]) -> bool {
+    let mut depth = 0;
+    for token in tokens {
+        match token {
+            Token::LParen => depth += 1,
+            Token::RParen if depth == 0 => return false,
+            Token::RParen => depth -= 1,
+            Token::SemiColon if depth == 0 => return false,
+            Token::Word(word) if depth == 0 && word.keyword == Keyword::WHERE => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind(sql: &str) -> StatementKind {
+        statement_kind(sql).0
+    }
+
+    #[test]
+    fn dml_without_where() {
+        assert_eq!(kind("delete from orders"), StatementKind::DmlWithoutWhere);
+        assert_eq!(kind("update orders set paid = true"), StatementKind::DmlWithoutWhere);
+        assert_eq!(kind("delete from orders where id = 1"), StatementKind::DmlWithWhere);
+        assert_eq!(kind("update orders set paid = true where id = 1"), StatementKind::DmlWithWhere);
+    }
+
+    #[test]
+    fn explain_analyze_runs_the_statement() {
+        assert_eq!(kind("explain delete from orders"), StatementKind::ReadOnly);
+        assert_eq!(kind("explain analyze delete from orders"), StatementKind::DmlWithoutWhere);
+        assert_eq!(kind("explain (analyze) delete from orders"), StatementKind::DmlWithoutWhere);
+        assert_eq!(kind("explain (analyze true, buffers) delete from orders"), StatementKind::DmlWithoutWhere);
+        assert_eq!(kind("explain (analyze off) delete from orders"), StatementKind::ReadOnly);
+        assert_eq!(kind("explain (costs off) delete from orders"), StatementKind::ReadOnly);
+    }
+
+    #[test]
+    fn data_modifying_with() {
+        let with = |dml: &str| kind(&format!("with d as ({dml} returning *) select * from d"));
+        assert_eq!(with("update orders set paid = true"), StatementKind::DmlWithoutWhere);
+        assert_eq!(with("delete from orders"), StatementKind::DmlWithoutWhere);
+        assert_eq!(with("delete from orders where id = 1"), StatementKind::DmlWithWhere);
+    }
+
+    #[test]
+    fn do_blocks() {
+        assert_eq!(kind("do $$ begin delete from orders; end $$"), StatementKind::DmlWithoutWhere);
+        assert_eq!(kind("do $$ begin delete from orders where id = 1; end $$"), StatementKind::DmlWithWhere);
+        assert_eq!(kind("do $body$ begin if true then truncate orders; end if; end $body$"), StatementKind::Truncate);
+        assert_eq!(kind("do $$ begin raise notice 'hello'; end $$"), StatementKind::Unknown);
+    }
+
+    #[test]
+    fn unparsed_statements() {
+        assert_eq!(kind("table orders"), StatementKind::ReadOnly);
+        assert_eq!(kind("TABLE public.orders;"), StatementKind::ReadOnly);
+        assert_eq!(kind("alter type mood add value 'meh'"), StatementKind::DropOrAlter);
+        assert_eq!(kind("select * from orders for update"), StatementKind::ReadOnly);
+    }
+
+    #[test]
+    fn read_only_scripts() {
+        assert!(is_read_only("select 1; show search_path; explain delete from orders; table orders"));
+        assert!(is_read_only("select set_config('search_path', 'public', false)"));
+        assert!(is_read_only("\\copy orders to '/tmp/orders.csv'"));
+        assert!(!is_read_only("select 1; delete from orders where id = 1"));
+        assert!(!is_read_only("with d as (delete from orders returning *) select * from d"));
+        assert!(!is_read_only("set default_transaction_read_only = off"));
+        assert!(!is_read_only("begin read write"));
+        assert!(!is_read_only("commit"));
+        assert!(!is_read_only("select set_config('default_transaction_read_only', 'off', false)"));
+        assert!(!is_read_only("select pg_catalog.set_config('Transaction_Read_Only ', 'off', true)"));
+        assert!(!is_read_only("select set_config('default_transaction_' || 'read_only', 'off', false)"));
+        assert!(!is_read_only("select set_config($$default_transaction_read_only$$, 'off', false)"));
+    }
+
+    #[test]
+    fn confirmation() {
+        let policy = SafetyPolicy::default();
+        let sql = "select 1;\ndelete from orders;";
+        let analysis = analyze_sql("a", &policy, sql);
+        let token = analysis.confirmation_token.as_deref();
+        assert!(token.is_some());
+        assert!(check_confirmation("a", &policy, sql, None).is_err());
+        assert!(check_confirmation("a", &policy, sql, token).is_ok());
+        assert!(check_confirmation("b", &policy, sql, token).is_err());
+        assert!(check_confirmation("a", &policy, "select 1", None).is_ok());
+        assert!(analyze_sql("a", &policy, "select 1").confirmation_token.is_none());
+    }
+
+    #[test]
+    fn nesting_in_with() {
+        assert!(nests_in_with("select box '(1,2),(3,4)'"));
+        assert!(nests_in_with("(select 1) union (select 2)"));
+        assert!(nests_in_with("update orders set total = 0 returning *"));
+        assert!(nests_in_with("with recent as (select * from orders) select * from recent"));
+        assert!(!nests_in_with("with gone as (delete from orders returning *) select * from gone"));
+        assert!(!nests_in_with("fetch forward 10 from c"));
+        assert!(!nests_in_with("explain select 1"));
+    }
+}
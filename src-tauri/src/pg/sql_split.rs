@@ -0,0 +1,506 @@
+use serde::Serialize;
+
+/// One statement of a script, as sent to the server.
+#[derive(Debug, Clone, Serialize)]
+pub struct SqlStatement {
+    /// The statement text, without the trailing `;`.
+    pub sql: String,
+    /// Offsets of the statement in the script, in UTF-16 code units like JavaScript strings.
+    pub start: usize,
+    pub end: usize,
+    /// The rows following a `COPY ... FROM STDIN`, up to the `\.` line ending them.
+    #[serde(skip)]
+    pub copy_data: Option<String>,
+}
+
+/// A position in a script, as shown by editors.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScriptPosition {
+    /// 1-based
+    pub line: usize,
+    /// 1-based, in UTF-16 code units
+    pub column: usize,
+    /// In UTF-16 code units from the start of the script
+    pub offset: usize,
+}
+
+/// Split a script on the `;` that end statements, skipping the ones inside strings,
+/// quoted identifiers, dollar-quoted bodies, comments, parentheses and the
+/// `BEGIN ATOMIC ... END` bodies of functions and procedures.
+///
+/// Statements made only of whitespace and comments are left out, since the server
+/// does not report anything for them. Like in psql, the lines after a
+/// `COPY ... FROM STDIN;` are its data, up to a line made of `\.`, and
+/// meta-commands like `\copy` take the rest of their line.
+pub fn split_statements(sql: &str) -> Vec<SqlStatement> {
+    let bytes = sql.as_bytes();
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut has_tokens = false;
+    let mut depth = 0usize;
+    // The first words of the statement, to tell `CREATE FUNCTION` and `CREATE PROCEDURE` apart
+    let mut leading_words: Vec<String> = Vec::new();
+    let mut begin_depth = 0usize;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let next = match bytes[i] {
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                i = sql[i..].find('\n').map_or(bytes.len(), |end| i + end + 1);
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = skip_block_comment(bytes, i);
+                continue;
+            }
+            c if c.is_ascii_whitespace() => i + 1,
+            b'\'' => {
+                let escapes = i > 0 && matches!(bytes[i - 1], b'e' | b'E') && !is_ident_byte(bytes, i.wrapping_sub(2));
+                skip_quoted(bytes, i, b'\'', escapes)
+            }
+            b'"' => skip_quoted(bytes, i, b'"', false),
+            b'$' => match dollar_tag(sql, i) {
+                Some(tag) => sql[i + tag.len()..].find(tag).map_or(bytes.len(), |end| i + tag.len() + end + tag.len()),
+                None => i + 1,
+            },
+            // psql meta-commands like `\copy` end with their line
+            b'\\' if !has_tokens => {
+                let line_end = sql[i..].find('\n').map_or(bytes.len(), |end| i + end);
+                let line = sql[i..line_end].trim_end();
+                let mut statement = statement(sql, i, i + line.strip_suffix(';').unwrap_or(line).len());
+                i = line_end;
+                if is_copy_from_stdin(&statement.sql[1..]) {
+                    let (data, end) = copy_data(sql, line_end);
+                    statement.copy_data = Some(data.to_string());
+                    i = end;
+                }
+                statements.push(statement);
+                start = i;
+                continue;
+            }
+            b'(' => {
+                depth += 1;
+                i + 1
+            }
+            b')' => {
+                depth = depth.saturating_sub(1);
+                i + 1
+            }
+            c if (c.is_ascii_alphabetic() || c == b'_' || c >= 0x80) && !is_ident_byte(bytes, i.wrapping_sub(1)) => {
+                let end = (i..bytes.len()).find(|&end| !is_ident_byte(bytes, end)).unwrap_or(bytes.len());
+                let word = sql[i..end].to_ascii_lowercase();
+                if leading_words.len() < 4 {
+                    leading_words.push(word.clone());
+                }
+                // Like psql, `CASE ... END` is only counted inside a `BEGIN`
+                if depth == 0 && is_create_routine(&leading_words) {
+                    match word.as_str() {
+                        "begin" => begin_depth += 1,
+                        "case" if begin_depth > 0 => begin_depth += 1,
+                        "end" => begin_depth = begin_depth.saturating_sub(1),
+                        _ => {}
+                    }
+                }
+                end
+            }
+            b';' if depth == 0 && begin_depth == 0 => {
+                i += 1;
+                if has_tokens {
+                    let mut statement = statement(sql, start, i - 1);
+                    if is_copy_from_stdin(&statement.sql) {
+                        let (data, end) = copy_data(sql, i);
+                        statement.copy_data = Some(data.to_string());
+                        i = end;
+                    }
+                    statements.push(statement);
+                }
+                start = i;
+                has_tokens = false;
+                leading_words.clear();
+                continue;
+            }
+            _ => i + 1,
+        };
+        if !bytes[i].is_ascii_whitespace() {
+            has_tokens = true;
+        }
+        i = next;
+    }
+    if has_tokens {
+        statements.push(statement(sql, start, bytes.len()));
+    }
+    statements
+}
+
+/// Whether a statement starting with these words is a `CREATE [OR REPLACE] FUNCTION` or
+/// `PROCEDURE`, whose `BEGIN ATOMIC` body holds statements of its own.
+fn is_create_routine(words: &[String]) -> bool {
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+    matches!(
+        words.as_slice(),
+        ["create", "function" | "procedure", ..] | ["create", "or", "replace", "function" | "procedure", ..]
+    )
+}
+
+/// The statement between two byte offsets, trimmed of surrounding whitespace and comments.
+fn statement(sql: &str, start: usize, end: usize) -> SqlStatement {
+    let text = &sql[start..end];
+    let leading = text.len() - strip_leading_comments(text).len();
+    let text = text[leading..].trim_end();
+    let start = start + leading;
+    SqlStatement {
+        sql: text.to_string(),
+        start: utf16_len(&sql[..start]),
+        end: utf16_len(&sql[..start + text.len()]),
+        copy_data: None,
+    }
+}
+
+fn is_copy_from_stdin(sql: &str) -> bool {
+    let words = top_level_words(sql);
+    words.first().is_some_and(|w| w == "copy")
+        && words.windows(2).any(|w| w[0] == "from" && (w[1] == "stdin" || w[1] == "pstdin"))
+}
+
+/// The data of a `COPY ... FROM STDIN` whose `;` is right before `i`, and the offset after it.
+/// The data starts on the next line and ends before the `\.` line, or at the end of the script.
+fn copy_data(sql: &str, i: usize) -> (&str, usize) {
+    let Some(newline) = sql[i..].find('\n') else {
+        return ("", sql.len());
+    };
+    let start = i + newline + 1;
+    let mut line_start = start;
+    while line_start < sql.len() {
+        let line_end = sql[line_start..].find('\n').map_or(sql.len(), |end| line_start + end + 1);
+        if sql[line_start..line_end].trim_end_matches(['\n', '\r']) == "\\." {
+            return (&sql[start..line_start], line_end);
+        }
+        line_start = line_end;
+    }
+    (&sql[start..], sql.len())
+}
+
+/// Where the 1-based character `position` of a statement, as reported by the server
+/// in errors, is in the script.
+pub fn script_position(script: &str, statement: &SqlStatement, position: u32) -> ScriptPosition {
+    let in_statement: usize =
+        statement.sql.chars().take(position.saturating_sub(1) as usize).map(char::len_utf16).sum();
+    let offset = statement.start + in_statement;
+
+    let (mut line, mut column, mut units) = (1, 1, 0);
+    for c in script.chars() {
+        if units >= offset {
+            break;
+        }
+        units += c.len_utf16();
+        if c == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += c.len_utf16();
+        }
+    }
+    ScriptPosition { line, column, offset }
+}
+
+fn utf16_len(s: &str) -> usize {
+    s.chars().map(char::len_utf16).sum()
+}
+
+/// Skip whitespace, `--` and `/* */` comments at the start of `sql`.
+pub fn strip_leading_comments(sql: &str) -> &str {
+    let bytes = sql.as_bytes();
+    let mut i = 0;
+    loop {
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if sql[i..].starts_with("--") {
+            i = sql[i..].find('\n').map_or(bytes.len(), |end| i + end + 1);
+        } else if sql[i..].starts_with("/*") {
+            i = skip_block_comment(bytes, i);
+        } else {
+            return &sql[i..];
+        }
+    }
+}
+
+/// The offset after a `/* */` comment starting at `i`. Block comments nest in Postgres.
+fn skip_block_comment(bytes: &[u8], mut i: usize) -> usize {
+    let mut depth = 0;
+    while i < bytes.len() {
+        if bytes[i..].starts_with(b"/*") {
+            depth += 1;
+            i += 2;
+        } else if bytes[i..].starts_with(b"*/") {
+            depth -= 1;
+            i += 2;
+            if depth == 0 {
+                return i;
+            }
+        } else {
+            i += 1;
+        }
+    }
+    bytes.len()
+}
+
+/// The offset after a quoted string or identifier starting at `i`. A doubled quote
+/// stands for itself, and in `E''` strings so does a backslash-escaped one.
+fn skip_quoted(bytes: &[u8], mut i: usize, quote: u8, escapes: bool) -> usize {
+    i += 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if escapes => i += 2,
+            c if c == quote => {
+                if bytes.get(i + 1) == Some(&quote) {
+                    i += 2;
+                } else {
+                    return i + 1;
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+/// The `$tag$` opening a dollar-quoted string at `i`, if any. A `$` following an
+/// identifier character is part of the identifier, and `$1` is a parameter.
+fn dollar_tag(sql: &str, i: usize) -> Option<&str> {
+    let bytes = sql.as_bytes();
+    if i > 0 && is_ident_byte(bytes, i - 1) {
+        return None;
+    }
+    let end = sql[i + 1..].find('$')? + i + 1;
+    let tag = &sql[i + 1..end];
+    let valid = tag.chars().enumerate().all(|(n, c)| c == '_' || c.is_alphabetic() || (n > 0 && c.is_ascii_digit()));
+    valid.then(|| &sql[i..=end])
+}
+
+fn is_ident_byte(bytes: &[u8], i: usize) -> bool {
+    bytes.get(i).is_some_and(|&c| c == b'_' || c == b'$' || c.is_ascii_alphanumeric() || c >= 0x80)
+}
+
+/// Rebuild the command tag the server sent for a statement, like `UPDATE 3` or
+/// `CREATE TABLE`, along with its row count when the tag has one.
+///
+/// tokio-postgres only exposes the row count of `CommandComplete`, so the rest of
+/// the tag is derived from the statement's leading keywords.
+pub fn command_tag(sql: &str, rows: u64) -> (String, Option<u64>) {
+    let words = top_level_words(sql);
+    let word = |n: usize| words.get(n).map(String::as_str).unwrap_or("");
+
+    let verb = match word(0) {
+        // The main statement follows the common table expressions
+        "with" => words
+            .iter()
+            .map(String::as_str)
+            .find(|w| ["select", "insert", "update", "delete", "merge", "values", "table"].contains(w))
+            .unwrap_or("select"),
+        verb => verb,
+    };
+    let tag = match verb {
+        "insert" => return (format!("INSERT 0 {}", rows), Some(rows)),
+        "select" | "values" | "table" => return (format!("SELECT {}", rows), Some(rows)),
+        "update" | "delete" | "merge" | "copy" | "fetch" | "move" => {
+            return (format!("{} {}", verb.to_uppercase(), rows), Some(rows))
+        }
+        "create" | "alter" | "drop" => {
+            let object = object_type(&words[1..]);
+            // `CREATE TABLE ... AS` reports the rows it inserted
+            let creates_from_query = ["table", "materialized view"].contains(&object.as_str());
+            if verb == "create" && creates_from_query && words.iter().any(|w| w == "as") {
+                return (format!("SELECT {}", rows), Some(rows));
+            }
+            format!("{} {}", verb, object)
+        }
+        "start" => "start transaction".to_string(),
+        "end" => "commit".to_string(),
+        "abort" => "rollback".to_string(),
+        "truncate" | "lock" => format!("{} table", verb),
+        "refresh" => "refresh materialized view".to_string(),
+        "prepare" if word(1) == "transaction" => "prepare transaction".to_string(),
+        "commit" | "rollback" if word(1) == "prepared" => format!("{} prepared", verb),
+        "import" => "import foreign schema".to_string(),
+        "security" => "security label".to_string(),
+        "reassign" => "reassign owned".to_string(),
+        "declare" => "declare cursor".to_string(),
+        "close" if word(1) == "all" => "close cursor all".to_string(),
+        "close" => "close cursor".to_string(),
+        "discard" => format!("discard {}", word(1)),
+        verb => verb.to_string(),
+    };
+    (tag.to_uppercase(), None)
+}
+
+/// Whether the statement ends the current transaction, like `COMMIT` or `ROLLBACK`
+/// but not `ROLLBACK TO SAVEPOINT`.
+pub fn ends_transaction(sql: &str) -> bool {
+    let words = top_level_words(sql);
+    let word = |n: usize| words.get(n).map(String::as_str).unwrap_or("");
+    match word(0) {
+        "commit" | "end" | "abort" => word(1) != "prepared",
+        "rollback" => word(1) != "prepared" && !words.iter().any(|w| w == "to"),
+        "prepare" => word(1) == "transaction",
+        _ => false,
+    }
+}
+
+/// Whether the statement is `SAVEPOINT`, `RELEASE` or `ROLLBACK TO SAVEPOINT`.
+pub fn uses_savepoints(sql: &str) -> bool {
+    let words = top_level_words(sql);
+    match words.first().map(String::as_str) {
+        Some("savepoint" | "release") => true,
+        Some("rollback") => words.iter().any(|w| w == "to"),
+        _ => false,
+    }
+}
+
+/// The object type of a `CREATE`, `ALTER` or `DROP`, as it appears in the command tag.
+fn object_type(words: &[String]) -> String {
+    const MODIFIERS: &[&str] = &[
+        "or", "replace", "unique", "temp", "temporary", "unlogged", "global", "local", "recursive", "trusted",
+        "procedural", "default", "constraint",
+    ];
+    const MULTI_WORD: &[&str] = &[
+        "materialized view",
+        "foreign table",
+        "foreign data wrapper",
+        "event trigger",
+        "user mapping",
+        "access method",
+        "operator class",
+        "operator family",
+        "text search configuration",
+        "text search dictionary",
+        "text search parser",
+        "text search template",
+        "default privileges",
+    ];
+
+    let words: Vec<&str> = words.iter().map(String::as_str).skip_while(|w| MODIFIERS.contains(w)).collect();
+    let joined = words.join(" ") + " ";
+    if let Some(object) = MULTI_WORD.iter().find(|object| joined.starts_with(&format!("{} ", object))) {
+        return object.to_string();
+    }
+    match words.first().copied().unwrap_or("") {
+        // Users and groups are roles
+        "user" | "group" => "role".to_string(),
+        object => object.to_string(),
+    }
+}
+
+/// The lowercased keywords and identifiers outside parentheses, strings and comments.
+fn top_level_words(sql: &str) -> Vec<String> {
+    let bytes = sql.as_bytes();
+    let mut words = Vec::new();
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < bytes.len() {
+        i = match bytes[i] {
+            b'-' if bytes.get(i + 1) == Some(&b'-') => sql[i..].find('\n').map_or(bytes.len(), |end| i + end + 1),
+            b'/' if bytes.get(i + 1) == Some(&b'*') => skip_block_comment(bytes, i),
+            b'\'' => skip_quoted(bytes, i, b'\'', false),
+            b'"' => skip_quoted(bytes, i, b'"', false),
+            b'$' => match dollar_tag(sql, i) {
+                Some(tag) => sql[i + tag.len()..].find(tag).map_or(bytes.len(), |end| i + tag.len() + end + tag.len()),
+                None => i + 1,
+            },
+            b'(' => {
+                depth += 1;
+                i + 1
+            }
+            b')' => {
+                depth = depth.saturating_sub(1);
+                i + 1
+            }
+            c if c.is_ascii_alphabetic() || c == b'_' => {
+                let end = (i..bytes.len()).find(|&end| !is_ident_byte(bytes, end)).unwrap_or(bytes.len());
+                if depth == 0 {
+                    words.push(sql[i..end].to_ascii_lowercase());
+                }
+                end
+            }
+            _ => i + 1,
+        };
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(sql: &str) -> Vec<String> {
+        split_statements(sql).into_iter().map(|statement| statement.sql).collect()
+    }
+
+    #[test]
+    fn quoted_semicolons() {
+        assert_eq!(split("select 'a;b'; select 2"), ["select 'a;b'", "select 2"]);
+        assert_eq!(split("select 'it''s;'; select 2"), ["select 'it''s;'", "select 2"]);
+        assert_eq!(split(r"select E'\';'; select 2"), [r"select E'\';'", "select 2"]);
+        assert_eq!(split(r"select 'a\'; select 2"), [r"select 'a\'", "select 2"]);
+        assert_eq!(split(r#"select 1 as "a;""b"; select 2"#), [r#"select 1 as "a;""b""#, "select 2"]);
+    }
+
+    #[test]
+    fn dollar_quotes() {
+        let function = "create function f() returns int language plpgsql as $body$ begin return 1; end $body$";
+        assert_eq!(split(&format!("{function}; select 2")), [function, "select 2"]);
+        assert_eq!(split("select $$a;b$$; select $1"), ["select $$a;b$$", "select $1"]);
+        assert_eq!(split("select a$b; select 2"), ["select a$b", "select 2"]);
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(split("select 1 -- a;b\n; select 2"), ["select 1 -- a;b", "select 2"]);
+        assert_eq!(split("select /* a /* b; */ c; */ 1; select 2"), ["select /* a /* b; */ c; */ 1", "select 2"]);
+        assert_eq!(split("-- only a comment;\n/* and; another */"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn begin_atomic() {
+        let function = "create or replace function f(a int) returns int language sql begin atomic \
+            select case when a > 0 then 1 else 0 end; select a; end";
+        assert_eq!(split(&format!("{function}; select 2")), [function, "select 2"]);
+        let procedure = "create procedure p() begin atomic insert into t values (1); end";
+        assert_eq!(split(&format!("{procedure};\nselect 2")), [procedure, "select 2"]);
+        assert_eq!(split("begin; select 1; end;"), ["begin", "select 1", "end"]);
+        let case = "select case when true then 1 end";
+        assert_eq!(split(&format!("{case}; select 2")), [case, "select 2"]);
+    }
+
+    #[test]
+    fn copy_data() {
+        let statements = split_statements("copy t from stdin;\n1\ta;b\n\\.\nselect 2");
+        assert_eq!(statements.len(), 2);
+        assert_eq!(statements[0].sql, "copy t from stdin");
+        assert_eq!(statements[0].copy_data.as_deref(), Some("1\ta;b\n"));
+        assert_eq!(statements[1].sql, "select 2");
+
+        let statements = split_statements("\\copy t from stdin\n1;2\n\\.\nselect 2;");
+        assert_eq!(statements.len(), 2);
+        assert_eq!(statements[0].sql, "\\copy t from stdin");
+        assert_eq!(statements[0].copy_data.as_deref(), Some("1;2\n"));
+    }
+
+    #[test]
+    fn transaction_control() {
+        assert!(ends_transaction("commit"));
+        assert!(ends_transaction("rollback"));
+        assert!(!ends_transaction("rollback to savepoint a"));
+        assert!(uses_savepoints("savepoint a"));
+        assert!(uses_savepoints("release savepoint a"));
+        assert!(uses_savepoints("ROLLBACK TO a"));
+        assert!(!uses_savepoints("rollback"));
+        assert!(!uses_savepoints("select 'savepoint'"));
+    }
+
+    #[test]
+    fn offsets_in_utf16() {
+        let statements = split_statements("select 'é'; select 2");
+        assert_eq!((statements[1].start, statements[1].end), (12, 20));
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock, Mutex, Weak};
+use std::time::Duration;
+use russh::client::{self, AuthResult, Handle};
+use russh::keys::agent::client::AgentClient;
+use russh::keys::agent::AgentIdentity;
+use russh::keys::{check_known_hosts_path, load_secret_key, PrivateKeyWithHashAlg, PublicKey};
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+use crate::pg::pg_connect::{PgConnectError, PgConnectErrorKind};
+
+/// How to authenticate against the bastion host.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(tag = "method", rename_all = "camelCase")]
+pub enum SshAuth {
+    Password { password: String },
+    PrivateKey { path: String, passphrase: Option<String> },
+    Agent,
+}
+
+/// SSH bastion used to reach the database, like `ssh -L` would.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SshTunnelConfig {
+    pub host: String,
+    pub port: Option<u16>,
+    pub user: String,
+    pub auth: SshAuth,
+    /// Defaults to `~/.ssh/known_hosts`.
+    pub known_hosts_path: Option<String>,
+}
+
+/// Tunnels currently open, so every session of a pool goes through the same SSH connection.
+static TUNNELS: LazyLock<Mutex<HashMap<TunnelKey, Weak<SshTunnel>>>> = LazyLock::new(Default::default);
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct TunnelKey {
+    config: SshTunnelConfig,
+    target_host: String,
+    target_port: u16,
+}
+
+/// A local port forwarded to `target_host:target_port` through the bastion.
+///
+/// The tunnel stays open as long as a database session holds it.
+pub struct SshTunnel {
+    pub local_port: u16,
+    handle: Arc<Handle<HostKeyCheck>>,
+    listener: JoinHandle<()>,
+}
+
+impl Drop for SshTunnel {
+    fn drop(&mut self) {
+        self.listener.abort();
+    }
+}
+
+impl SshTunnel {
+    /// Reuse the open tunnel to the given target, or open a new one.
+    pub async fn open(
+        config: &SshTunnelConfig,
+        target_host: &str,
+        target_port: u16,
+    ) -> Result<Arc<SshTunnel>, PgConnectError> {
+        let key = TunnelKey {
+            config: config.clone(),
+            target_host: target_host.to_string(),
+            target_port,
+        };
+        if let Some(tunnel) = TUNNELS.lock().unwrap().get(&key).and_then(Weak::upgrade) {
+            if !tunnel.handle.is_closed() {
+                return Ok(tunnel);
+            }
+        }
+
+        let tunnel = Arc::new(Self::connect(config, target_host, target_port).await?);
+        let mut tunnels = TUNNELS.lock().unwrap();
+        tunnels.retain(|_, tunnel| tunnel.strong_count() > 0);
+        tunnels.insert(key, Arc::downgrade(&tunnel));
+        Ok(tunnel)
+    }
+
+    async fn connect(
+        config: &SshTunnelConfig,
+        target_host: &str,
+        target_port: u16,
+    ) -> Result<SshTunnel, PgConnectError> {
+        let port = config.port.unwrap_or(22);
+        let known_hosts_path = match &config.known_hosts_path {
+            Some(path) => expand_home(path),
+            None => expand_home("~/.ssh/known_hosts"),
+        };
+        let ssh_config = Arc::new(client::Config {
+            keepalive_interval: Some(Duration::from_secs(30)),
+            keepalive_max: 3,
+            ..Default::default()
+        });
+        let check = HostKeyCheck { host: config.host.clone(), port, known_hosts_path };
+
+        let mut handle = client::connect(ssh_config, (config.host.as_str(), port), check)
+            .await
+            .map_err(|e| tunnel_error(format!("Could not connect to {}:{}: {}", config.host, port, e)))?;
+
+        let authenticated = match &config.auth {
+            SshAuth::Password { password } => handle
+                .authenticate_password(&config.user, password)
+                .await
+                .map_err(|e| tunnel_error(e.to_string()))?
+                .success(),
+            SshAuth::PrivateKey { path, passphrase } => {
+                let key = load_secret_key(expand_home(path), passphrase.as_deref())
+                    .map_err(|e| tunnel_error(format!("Could not load private key \"{}\": {}", path, e)))?;
+                let hash_alg = handle
+                    .best_supported_rsa_hash()
+                    .await
+                    .map_err(|e| tunnel_error(e.to_string()))?
+                    .flatten();
+                handle
+                    .authenticate_publickey(&config.user, PrivateKeyWithHashAlg::new(Arc::new(key), hash_alg))
+                    .await
+                    .map_err(|e| tunnel_error(e.to_string()))?
+                    .success()
+            }
+            SshAuth::Agent => authenticate_with_agent(&mut handle, &config.user).await?,
+        };
+        if !authenticated {
+            return Err(tunnel_error(format!(
+                "Authentication as \"{}\" on {} was rejected",
+                config.user, config.host
+            )));
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .map_err(|e| tunnel_error(format!("Could not open a local port: {}", e)))?;
+        let local_port = listener
+            .local_addr()
+            .map_err(|e| tunnel_error(e.to_string()))?
+            .port();
+
+        let handle = Arc::new(handle);
+        let forward_handle = handle.clone();
+        let target_host = target_host.to_string();
+        let listener = tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let handle = forward_handle.clone();
+                let target_host = target_host.clone();
+                tokio::spawn(async move {
+                    let channel = match handle
+                        .channel_open_direct_tcpip(target_host, target_port as u32, "127.0.0.1", local_port as u32)
+                        .await
+                    {
+                        Ok(channel) => channel,
+                        Err(e) => {
+                            eprintln!("SSH tunnel error: {e}");
+                            return;
+                        }
+                    };
+                    let mut stream = channel.into_stream();
+                    let _ = tokio::io::copy_bidirectional(&mut socket, &mut stream).await;
+                });
+            }
+        });
+
+        Ok(SshTunnel { local_port, handle, listener })
+    }
+}
+
+/// Try every identity of the running ssh-agent until the server accepts one.
+async fn authenticate_with_agent(handle: &mut Handle<HostKeyCheck>, user: &str) -> Result<bool, PgConnectError> {
+    #[cfg(unix)]
+    let agent = AgentClient::connect_env().await;
+    #[cfg(windows)]
+    let agent = AgentClient::connect_named_pipe(r"\\.\pipe\openssh-ssh-agent").await;
+
+    let agent = agent.map_err(|e| tunnel_error(format!("Could not reach the ssh-agent: {}", e)))?;
+    try_agent_identities(handle, user, agent).await
+}
+
+async fn try_agent_identities<R>(
+    handle: &mut Handle<HostKeyCheck>,
+    user: &str,
+    mut agent: AgentClient<R>,
+) -> Result<bool, PgConnectError>
+where
+    R: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let identities = agent
+        .request_identities()
+        .await
+        .map_err(|e| tunnel_error(format!("Could not list ssh-agent keys: {}", e)))?;
+    let hash_alg = handle
+        .best_supported_rsa_hash()
+        .await
+        .map_err(|e| tunnel_error(e.to_string()))?
+        .flatten();
+
+    for identity in identities {
+        let AgentIdentity::PublicKey { key, .. } = identity else {
+            continue;
+        };
+        let result = handle
+            .authenticate_publickey_with(user, key, hash_alg, &mut agent)
+            .await
+            .map_err(|e| tunnel_error(e.to_string()))?;
+        if let AuthResult::Success = result {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Only accept bastions whose key is already trusted in `known_hosts`.
+struct HostKeyCheck {
+    host: String,
+    port: u16,
+    known_hosts_path: PathBuf,
+}
+
+#[derive(Debug)]
+enum SshError {
+    Ssh(russh::Error),
+    HostKey(String),
+}
+
+impl From<russh::Error> for SshError {
+    fn from(e: russh::Error) -> Self {
+        SshError::Ssh(e)
+    }
+}
+
+impl std::fmt::Display for SshError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SshError::Ssh(e) => write!(f, "{}", e),
+            SshError::HostKey(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl client::Handler for HostKeyCheck {
+    type Error = SshError;
+
+    async fn check_server_key(&mut self, key: &PublicKey) -> Result<bool, SshError> {
+        match check_known_hosts_path(&self.host, self.port, key, &self.known_hosts_path) {
+            Ok(true) => Ok(true),
+            Ok(false) => Err(SshError::HostKey(format!(
+                "Host key {} of {} is not in {}, connect once with ssh to trust it",
+                key.fingerprint(Default::default()),
+                self.host,
+                self.known_hosts_path.display()
+            ))),
+            Err(russh::keys::Error::KeyChanged { line }) => Err(SshError::HostKey(format!(
+                "Host key of {} does not match line {} of {}, it may have been tampered with",
+                self.host,
+                line,
+                self.known_hosts_path.display()
+            ))),
+            Err(e) => Err(SshError::HostKey(format!(
+                "Could not read {}: {}",
+                self.known_hosts_path.display(),
+                e
+            ))),
+        }
+    }
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+fn tunnel_error(message: String) -> PgConnectError {
+    PgConnectError { kind: PgConnectErrorKind::SshTunnel, message: format!("SSH tunnel: {}", message) }
+}
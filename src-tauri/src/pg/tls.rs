@@ -0,0 +1,232 @@
+use std::path::{Path, PathBuf};
+use native_tls::{Certificate, Identity, TlsConnector};
+use pkcs8::der::pem::LineEnding;
+use pkcs8::der::Decode;
+use pkcs8::{AlgorithmIdentifierRef, EncryptedPrivateKeyInfo, PrivateKeyInfo, SecretDocument};
+use postgres_native_tls::MakeTlsConnector;
+
+use crate::pg::conn_params::{libpq_user_dir, ConnParams};
+use crate::pg::pg_connect::{PgConnectError, PgConnectErrorKind};
+
+/// The libpq `sslmode` values, see
+/// https://www.postgresql.org/docs/current/libpq-ssl.html#LIBPQ-SSL-PROTECTION
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SslMode {
+    Disable,
+    Allow,
+    Prefer,
+    Require,
+    VerifyCa,
+    VerifyFull,
+}
+
+impl SslMode {
+    fn parse(value: &str) -> Result<Self, PgConnectError> {
+        match value {
+            "disable" => Ok(SslMode::Disable),
+            "allow" => Ok(SslMode::Allow),
+            "prefer" => Ok(SslMode::Prefer),
+            "require" => Ok(SslMode::Require),
+            "verify-ca" => Ok(SslMode::VerifyCa),
+            "verify-full" => Ok(SslMode::VerifyFull),
+            _ => Err(PgConnectError { kind: PgConnectErrorKind::Config, message: format!("Invalid sslmode value: \"{}\"", value) }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum RootCert {
+    /// No CA file: the server certificate chain is not verified.
+    None,
+    /// Only trust the CAs from this file.
+    File(PathBuf),
+    /// `sslrootcert=system`: trust the operating system's CA store.
+    System,
+}
+
+/// TLS settings resolved from the `ssl*` connection parameters, falling back to
+/// libpq's default files in `~/.postgresql` like `psql` does.
+#[derive(Debug, Clone)]
+pub struct TlsSettings {
+    pub mode: SslMode,
+    root_cert: RootCert,
+    client_cert: Option<(PathBuf, PathBuf)>,
+    key_password: Option<String>,
+}
+
+impl TlsSettings {
+    pub fn from_params(params: &ConnParams) -> Result<Self, PgConnectError> {
+        let explicit_mode = match params.get("sslmode") {
+            Some(value) => Some(SslMode::parse(value)?),
+            None if params.get("requiressl") == Some("1") => Some(SslMode::Require),
+            None => None,
+        };
+        let user_dir = libpq_user_dir();
+
+        let mut root_cert = match params.get("sslrootcert") {
+            Some("system") => RootCert::System,
+            Some(path) => RootCert::File(PathBuf::from(path)),
+            None => match user_dir.as_ref().map(|dir| dir.join("root.crt")) {
+                Some(path) if path.exists() => RootCert::File(path),
+                _ => RootCert::None,
+            },
+        };
+
+        let mode = match (&root_cert, explicit_mode) {
+            (RootCert::System, None) => SslMode::VerifyFull,
+            (RootCert::System, Some(mode)) if mode != SslMode::VerifyFull => {
+                return Err(PgConnectError {
+                    kind: PgConnectErrorKind::Config,
+                    message: "sslrootcert=system can only be used with sslmode=verify-full".to_string(),
+                });
+            }
+            (_, mode) => mode.unwrap_or(SslMode::Prefer),
+        };
+
+        if let RootCert::File(path) = &root_cert {
+            if !path.exists() {
+                if matches!(mode, SslMode::VerifyCa | SslMode::VerifyFull) {
+                    return Err(PgConnectError {
+                        kind: PgConnectErrorKind::Config,
+                        message: format!("Root certificate file \"{}\" does not exist", path.display()),
+                    });
+                }
+                // Like libpq, the other modes only verify the chain when the file exists
+                root_cert = RootCert::None;
+            }
+        }
+        if matches!(mode, SslMode::VerifyCa | SslMode::VerifyFull) && root_cert == RootCert::None {
+            return Err(PgConnectError {
+                kind: PgConnectErrorKind::Config,
+                message: format!(
+                    "sslmode={} needs a root certificate: set sslrootcert to a CA file, use sslrootcert=system, or create ~/.postgresql/root.crt",
+                    if mode == SslMode::VerifyCa { "verify-ca" } else { "verify-full" }
+                ),
+            });
+        }
+
+        // Like libpq, a missing certificate file simply means no client certificate is sent
+        let cert_path = params
+            .get("sslcert")
+            .map(PathBuf::from)
+            .or_else(|| user_dir.as_ref().map(|dir| dir.join("postgresql.crt")));
+        let key_path = params
+            .get("sslkey")
+            .map(PathBuf::from)
+            .or_else(|| user_dir.as_ref().map(|dir| dir.join("postgresql.key")));
+        let client_cert = match (cert_path, key_path) {
+            (Some(cert), Some(key)) if cert.exists() => {
+                if !key.exists() {
+                    return Err(PgConnectError {
+                        kind: PgConnectErrorKind::Config,
+                        message: format!(
+                            "Certificate present, but not private key file \"{}\"",
+                            key.display()
+                        ),
+                    });
+                }
+                Some((cert, key))
+            }
+            _ => None,
+        };
+
+        Ok(TlsSettings {
+            mode,
+            root_cert,
+            client_cert,
+            key_password: params.get("sslpassword").map(String::from),
+        })
+    }
+
+    /// Build a connector that verifies the chain only when a root certificate is
+    /// configured and checks the host name only in `verify-full`.
+    pub fn connector(&self) -> Result<MakeTlsConnector, PgConnectError> {
+        let mut builder = TlsConnector::builder();
+
+        if let RootCert::File(path) = &self.root_cert {
+            for cert in read_certificates(path)? {
+                builder.add_root_certificate(cert);
+            }
+            builder.disable_built_in_roots(true);
+        }
+        // libpq verifies the chain in every mode as soon as a root certificate exists
+        builder.danger_accept_invalid_certs(self.root_cert == RootCert::None);
+        builder.danger_accept_invalid_hostnames(self.mode != SslMode::VerifyFull);
+
+        if let Some((cert_path, key_path)) = &self.client_cert {
+            let cert = read_file(cert_path, "client certificate")?;
+            let key = read_private_key(key_path, self.key_password.as_deref())?;
+            builder.identity(Identity::from_pkcs8(&cert, key.as_bytes()).map_err(PgConnectError::from)?);
+        }
+
+        let connector = builder.build().map_err(PgConnectError::from)?;
+        Ok(MakeTlsConnector::new(connector))
+    }
+}
+
+fn read_file(path: &Path, what: &str) -> Result<Vec<u8>, PgConnectError> {
+    std::fs::read(path).map_err(|e| PgConnectError {
+        kind: PgConnectErrorKind::Config,
+        message: format!("Could not read {} \"{}\": {}", what, path.display(), e),
+    })
+}
+
+/// Read every certificate of a CA bundle, in PEM or DER format.
+fn read_certificates(path: &Path) -> Result<Vec<Certificate>, PgConnectError> {
+    let bytes = read_file(path, "root certificate file")?;
+    let certs = if bytes.windows(11).any(|w| w == b"-----BEGIN ") {
+        Certificate::stack_from_pem(&bytes)
+    } else {
+        Certificate::from_der(&bytes).map(|cert| vec![cert])
+    };
+    certs.map_err(|e| PgConnectError {
+        kind: PgConnectErrorKind::Config,
+        message: format!("Invalid root certificate file \"{}\": {}", path.display(), e),
+    })
+}
+
+/// Read a client key as an unencrypted PKCS#8 PEM, which is the only format
+/// native-tls accepts. Encrypted PKCS#8, PKCS#1 (RSA) and SEC1 (EC) keys are converted.
+fn read_private_key(path: &Path, password: Option<&str>) -> Result<String, PgConnectError> {
+    let invalid = |reason: String| PgConnectError {
+        kind: PgConnectErrorKind::Config,
+        message: format!("Invalid private key file \"{}\": {}", path.display(), reason),
+    };
+
+    let pem = String::from_utf8(read_file(path, "private key file")?)
+        .map_err(|_| invalid("expected a PEM file".to_string()))?;
+    if pem.contains("Proc-Type: 4,ENCRYPTED") {
+        return Err(invalid(
+            "legacy encrypted PEM keys are not supported, convert it with `openssl pkcs8 -topk8`".to_string(),
+        ));
+    }
+    let (label, der) = pkcs8::der::pem::decode_vec(pem.as_bytes()).map_err(|e| invalid(e.to_string()))?;
+
+    let document = match label {
+        "PRIVATE KEY" => SecretDocument::try_from(der.as_slice()).map_err(|e| invalid(e.to_string()))?,
+        "ENCRYPTED PRIVATE KEY" => {
+            let password = password.ok_or_else(|| invalid("the key is encrypted but no sslpassword was given".to_string()))?;
+            EncryptedPrivateKeyInfo::from_der(&der)
+                .map_err(|e| invalid(e.to_string()))?
+                .decrypt(password)
+                .map_err(|e| invalid(format!("could not decrypt the key: {}", e)))?
+        }
+        "RSA PRIVATE KEY" => SecretDocument::try_from(PrivateKeyInfo::new(pkcs1::ALGORITHM_ID, &der))
+            .map_err(|e| invalid(e.to_string()))?,
+        "EC PRIVATE KEY" => {
+            let curve = sec1::EcPrivateKey::from_der(&der)
+                .map_err(|e| invalid(e.to_string()))?
+                .parameters
+                .and_then(|parameters| parameters.named_curve())
+                .ok_or_else(|| invalid("missing named curve".to_string()))?;
+            let algorithm = AlgorithmIdentifierRef { oid: sec1::ALGORITHM_OID, parameters: Some((&curve).into()) };
+            SecretDocument::try_from(PrivateKeyInfo::new(algorithm, &der)).map_err(|e| invalid(e.to_string()))?
+        }
+        label => return Err(invalid(format!("unsupported PEM label \"{}\"", label))),
+    };
+
+    document
+        .to_pem("PRIVATE KEY", LineEnding::LF)
+        .map(|pem| pem.to_string())
+        .map_err(|e| invalid(e.to_string()))
+}
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter};
+use tokio::sync::OwnedMutexGuard;
+use tokio_postgres::Client as PgClient;
+
+use crate::error::CommandError;
+use crate::pg::notices::SessionNotices;
+use crate::pg::pg_connect::{PgConnectionConfig, QueryCanceller};
+use crate::pg::pg_pool::PooledClient;
+use crate::pg::quote_ident::quote_ident;
+use crate::pg::sql_analysis::SafetyPolicy;
+
+/// Transactions left idle for this long trigger a `transaction-idle` event, since
+/// they hold locks and keep vacuum from cleaning up behind them.
+const IDLE_WARNING_AFTER: Duration = Duration::from_secs(2 * 60);
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IsolationLevel {
+    ReadCommitted,
+    RepeatableRead,
+    Serializable,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct TransactionOptions {
+    /// The default of the session when unset, usually read committed.
+    pub isolation_level: Option<IsolationLevel>,
+    #[serde(default)]
+    pub read_only: bool,
+    /// Only applies to serializable read-only transactions.
+    #[serde(default)]
+    pub deferrable: bool,
+}
+
+/// Payload of the `transaction-idle` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct TransactionIdleEvent {
+    pub transaction_id: String,
+    pub connection_id: String,
+    pub idle_seconds: u64,
+}
+
+struct Transaction {
+    connection_id: String,
+    database: String,
+    read_only_connection: bool,
+    safety_policy: SafetyPolicy,
+    canceller: QueryCanceller,
+    state: Arc<tokio::sync::Mutex<TransactionState>>,
+}
+
+struct TransactionState {
+    /// Pinned to the transaction until it ends.
+    client: PooledClient,
+    last_used: Instant,
+    /// Set once the idle event was sent, until the transaction is used again.
+    warned: bool,
+}
+
+/// A transaction's session, locked so its statements run one at a time.
+pub struct TransactionSession {
+    state: OwnedMutexGuard<TransactionState>,
+    /// The connection is read-only, scripts are checked like in `raw_query`.
+    pub read_only_connection: bool,
+    /// The safety policy of the connection, scripts are confirmed like in `raw_query`.
+    pub safety_policy: SafetyPolicy,
+    pub connection_id: String,
+    pub database: String,
+}
+
+impl TransactionSession {
+    /// Where the connection driver keeps the notices of the running query.
+    pub fn notices(&self) -> &SessionNotices {
+        self.state.client.notices()
+    }
+}
+
+impl Deref for TransactionSession {
+    type Target = PgClient;
+
+    fn deref(&self) -> &PgClient {
+        &self.state.client
+    }
+}
+
+impl Drop for TransactionSession {
+    fn drop(&mut self) {
+        self.state.last_used = Instant::now();
+        self.state.warned = false;
+    }
+}
+
+/// Tauri-managed registry of the transactions opened by `begin_transaction`.
+pub struct Transactions {
+    transactions: Mutex<HashMap<String, Arc<Transaction>>>,
+    app: AppHandle,
+}
+
+impl Transactions {
+    pub fn new(app: AppHandle) -> Self {
+        Transactions { transactions: Mutex::new(HashMap::new()), app }
+    }
+
+    /// Start a transaction on `client`, keeping the session until `commit` or `rollback`.
+    pub async fn begin(
+        &self,
+        mut client: PooledClient,
+        connection: &PgConnectionConfig,
+        options: &TransactionOptions,
+    ) -> Result<String, CommandError> {
+        let mut begin = "begin".to_string();
+        if let Some(isolation_level) = options.isolation_level {
+            begin += match isolation_level {
+                IsolationLevel::ReadCommitted => " isolation level read committed",
+                IsolationLevel::RepeatableRead => " isolation level repeatable read",
+                IsolationLevel::Serializable => " isolation level serializable",
+            };
+        }
+        // Rather than relying on the default of the session, which `set_config` can change
+        if options.read_only || connection.read_only {
+            begin += " read only";
+        }
+        if options.deferrable {
+            begin += " deferrable";
+        }
+        if let Err(e) = client.batch_execute(&begin).await {
+            client.reset_session().await;
+            return Err(e.into());
+        }
+
+        let transaction_id = uuid::Uuid::new_v4().to_string();
+        let transaction = Transaction {
+            connection_id: connection.id.clone(),
+            database: client.database().to_string(),
+            read_only_connection: connection.read_only,
+            safety_policy: connection.safety_policy.clone(),
+            canceller: client.canceller(),
+            state: Arc::new(tokio::sync::Mutex::new(TransactionState {
+                client,
+                last_used: Instant::now(),
+                warned: false,
+            })),
+        };
+        self.transactions.lock().unwrap().insert(transaction_id.clone(), Arc::new(transaction));
+        Ok(transaction_id)
+    }
+
+    /// Wait for the session of the transaction to be free and lock it.
+    pub async fn session(&self, transaction_id: &str) -> Result<TransactionSession, CommandError> {
+        let transaction = self.get(transaction_id)?;
+        Ok(TransactionSession {
+            state: transaction.state.clone().lock_owned().await,
+            read_only_connection: transaction.read_only_connection,
+            safety_policy: transaction.safety_policy.clone(),
+            connection_id: transaction.connection_id.clone(),
+            database: transaction.database.clone(),
+        })
+    }
+
+    /// The cancel handle of the session the transaction runs on.
+    pub fn canceller(&self, transaction_id: &str) -> Result<QueryCanceller, CommandError> {
+        Ok(self.get(transaction_id)?.canceller.clone())
+    }
+
+    pub async fn savepoint(&self, transaction_id: &str, name: &str) -> Result<(), CommandError> {
+        let session = self.session(transaction_id).await?;
+        session.batch_execute(&format!("savepoint {}", quote_ident(name))).await?;
+        Ok(())
+    }
+
+    /// Commit the transaction and give its session back to the pool. The transaction
+    /// ends even if the commit fails, the server rolls it back then.
+    pub async fn commit(&self, transaction_id: &str) -> Result<(), CommandError> {
+        let transaction = self.remove(transaction_id)?;
+        let mut state = transaction.state.lock().await;
+        let result = state.client.batch_execute("commit").await;
+        state.client.reset_session().await;
+        result.map_err(CommandError::from)
+    }
+
+    /// Roll back to `savepoint`, keeping the transaction open, or roll back the whole
+    /// transaction and give its session back to the pool.
+    pub async fn rollback(&self, transaction_id: &str, savepoint: Option<&str>) -> Result<(), CommandError> {
+        if let Some(savepoint) = savepoint {
+            let session = self.session(transaction_id).await?;
+            session.batch_execute(&format!("rollback to savepoint {}", quote_ident(savepoint))).await?;
+            return Ok(());
+        }
+        let transaction = self.remove(transaction_id)?;
+        let mut state = transaction.state.lock().await;
+        // Also restores the session settings changed by the transaction
+        state.client.reset_session().await;
+        Ok(())
+    }
+
+    /// Roll back the transactions of a connection, when it is closed.
+    pub async fn rollback_connection(&self, connection_id: &str) {
+        let transaction_ids: Vec<String> = self
+            .transactions
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, transaction)| transaction.connection_id == connection_id)
+            .map(|(transaction_id, _)| transaction_id.clone())
+            .collect();
+        for transaction_id in transaction_ids {
+            self.rollback(&transaction_id, None).await.ok();
+        }
+    }
+
+    /// Warn about the transactions nobody used recently, once per idle period.
+    pub fn warn_idle(&self) {
+        for (transaction_id, transaction) in self.transactions.lock().unwrap().iter() {
+            let Ok(mut state) = transaction.state.try_lock() else {
+                continue;
+            };
+            let idle = state.last_used.elapsed();
+            if state.warned || idle < IDLE_WARNING_AFTER {
+                continue;
+            }
+            state.warned = true;
+            let event = TransactionIdleEvent {
+                transaction_id: transaction_id.clone(),
+                connection_id: transaction.connection_id.clone(),
+                idle_seconds: idle.as_secs(),
+            };
+            self.app.emit("transaction-idle", event).ok();
+        }
+    }
+
+    fn get(&self, transaction_id: &str) -> Result<Arc<Transaction>, CommandError> {
+        self.transactions.lock().unwrap().get(transaction_id).cloned().ok_or_else(transaction_closed)
+    }
+
+    fn remove(&self, transaction_id: &str) -> Result<Arc<Transaction>, CommandError> {
+        self.transactions.lock().unwrap().remove(transaction_id).ok_or_else(transaction_closed)
+    }
+}
+
+fn transaction_closed() -> CommandError {
+    CommandError::with_code("transaction_closed", "This transaction already ended")
+}
@@ -0,0 +1,647 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Write;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, TimeDelta};
+use fallible_iterator::FallibleIterator;
+use postgres_protocol::types;
+use serde_json::{Map, Value as JsonValue};
+use tokio_postgres::types::{FromSql, Kind, Type};
+use tokio_postgres::{Client as PgClient, Column, Row, Statement};
+
+use crate::error::CommandError;
+use crate::pg::models::PgResultColumn;
+use crate::pg::sql_analysis::nests_in_with;
+
+type DecodeError = Box<dyn Error + Sync + Send>;
+
+/// The binary value of a column, whatever its type.
+struct RawValue<'a>(&'a [u8]);
+
+impl<'a> FromSql<'a> for RawValue<'a> {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> Result<Self, DecodeError> {
+        Ok(RawValue(raw))
+    }
+
+    fn accepts(_: &Type) -> bool {
+        true
+    }
+}
+
+/// Convert a row returned by the extended protocol to a JSON object keyed by column name.
+pub fn row_to_json(row: &Row) -> Result<JsonValue, CommandError> {
+    let mut map = Map::new();
+    for (i, column) in row.columns().iter().enumerate() {
+        let value = row
+            .try_get::<_, Option<RawValue>>(i)
+            .map_err(CommandError::from)
+            .and_then(|raw| {
+                value_to_json(column.type_(), raw.map(|raw| raw.0)).map_err(|e| {
+                    CommandError::from(format!(
+                        "Could not decode column \"{}\" of type {}: {}",
+                        column.name(),
+                        column.type_().name(),
+                        e
+                    ))
+                })
+            })?;
+        map.insert(column.name().to_string(), value);
+    }
+    Ok(JsonValue::Object(map))
+}
+
+/// Describe the result columns, looking up `NOT NULL` for those coming straight from a table.
+pub async fn result_columns(client: &PgClient, columns: &[Column]) -> Result<Vec<PgResultColumn>, CommandError> {
+    let (table_oids, column_ids): (Vec<u32>, Vec<i16>) =
+        columns.iter().filter_map(|column| Some((column.table_oid()?, column.column_id()?))).unzip();
+    let not_null: HashMap<(u32, i16), bool> = if table_oids.is_empty() {
+        HashMap::new()
+    } else {
+        client
+            .query(
+                "select attrelid, attnum, attnotnull from pg_attribute
+                 where (attrelid, attnum) in (select * from unnest($1::oid[], $2::int2[]))",
+                &[&table_oids, &column_ids],
+            )
+            .await?
+            .iter()
+            .map(|row| ((row.get(0), row.get(1)), row.get(2)))
+            .collect()
+    };
+
+    Ok(columns
+        .iter()
+        .map(|column| {
+            let source = column.table_oid().zip(column.column_id());
+            PgResultColumn {
+                name: column.name().to_string(),
+                type_oid: Some(column.type_().oid()),
+                type_name: Some(column.type_().name().to_string()),
+                nullable: source.and_then(|source| not_null.get(&source)).map(|not_null| !not_null),
+                table_oid: column.table_oid(),
+                column_id: column.column_id(),
+                binary: !decodes(column.type_()),
+            }
+        })
+        .collect())
+}
+
+/// Prepare `sql` and describe its result columns. When some columns have a type without
+/// a conversion to JSON, the statement is wrapped to fetch them as text, if it can be.
+/// The query that was prepared is returned too, to locate the errors of its execution.
+pub async fn prepare_decodable(
+    client: &PgClient,
+    sql: &str,
+) -> Result<(Statement, Vec<PgResultColumn>, String), CommandError> {
+    let prepared = client.prepare(sql).await?;
+    let mut columns = result_columns(client, prepared.columns()).await?;
+    let Some(text_sql) = text_columns_query(sql, prepared.columns()) else {
+        return Ok((prepared, columns, sql.to_string()));
+    };
+    let prepared = client.prepare(&text_sql).await.map_err(|e| CommandError::from(e).sent_in(&text_sql, sql))?;
+    for column in &mut columns {
+        column.binary = false;
+    }
+    Ok((prepared, columns, text_sql))
+}
+
+/// `sql` wrapped in a query casting the columns `decodes` has no conversion for to text,
+/// so they come back as the server prints them. `None` when every column can be decoded,
+/// or when the statement cannot be put in a `WITH`, like `FETCH`.
+pub fn text_columns_query(sql: &str, columns: &[Column]) -> Option<String> {
+    if columns.iter().all(|column| decodes(column.type_())) || !nests_in_with(sql) {
+        return None;
+    }
+    let names: Vec<String> = (1..=columns.len()).map(|i| format!("column{}", i)).collect();
+    let select: Vec<String> = columns
+        .iter()
+        .zip(&names)
+        .map(|(column, name)| {
+            let cast = if decodes(column.type_()) { "" } else { "::text" };
+            format!("{}{} as \"{}\"", name, cast, column.name().replace('"', "\"\""))
+        })
+        .collect();
+    // On lines of their own, so a comment ending the statement does not hide the parenthesis
+    Some(format!(
+        "with pgditor_result ({}) as (\n{}\n) select {} from pgditor_result",
+        names.join(", "),
+        sql,
+        select.join(", ")
+    ))
+}
+
+/// Whether `value_to_json` has a conversion for values of this type. Keep it in sync with
+/// the types matched there.
+pub fn decodes(ty: &Type) -> bool {
+    match ty.kind() {
+        Kind::Array(element) | Kind::Domain(element) | Kind::Range(element) => decodes(element),
+        Kind::Composite(fields) => fields.iter().all(|field| decodes(field.type_())),
+        Kind::Enum(_) => true,
+        _ => {
+            matches!(
+                *ty,
+                Type::BOOL
+                    | Type::INT2
+                    | Type::INT4
+                    | Type::OID
+                    | Type::XID
+                    | Type::CID
+                    | Type::REGPROC
+                    | Type::REGCLASS
+                    | Type::REGTYPE
+                    | Type::REGNAMESPACE
+                    | Type::INT8
+                    | Type::FLOAT4
+                    | Type::FLOAT8
+                    | Type::NUMERIC
+                    | Type::MONEY
+                    | Type::TEXT
+                    | Type::VARCHAR
+                    | Type::BPCHAR
+                    | Type::NAME
+                    | Type::UNKNOWN
+                    | Type::XML
+                    | Type::CHAR
+                    | Type::JSON
+                    | Type::JSONB
+                    | Type::JSONPATH
+                    | Type::UUID
+                    | Type::BYTEA
+                    | Type::DATE
+                    | Type::TIMESTAMP
+                    | Type::TIMESTAMPTZ
+                    | Type::TIME
+                    | Type::TIMETZ
+                    | Type::INTERVAL
+                    | Type::INET
+                    | Type::CIDR
+                    | Type::MACADDR
+                    | Type::BIT
+                    | Type::VARBIT
+                    | Type::PG_LSN
+                    | Type::POINT
+                    | Type::TS_VECTOR
+            ) || matches!(ty.name(), "hstore" | "ltree" | "lquery" | "ltxtquery" | "citext")
+        }
+    }
+}
+
+/// Convert a value in Postgres binary format to JSON.
+///
+/// Numbers JSON cannot hold exactly (`int8`, `numeric`, `money`) become strings, as do
+/// dates and times (ISO 8601) and `bytea` (`\x` hex). Values of types without a
+/// conversion, which `prepare_decodable` could not fetch as text, come back as hex too.
+pub fn value_to_json(ty: &Type, raw: Option<&[u8]>) -> Result<JsonValue, DecodeError> {
+    let Some(raw) = raw else {
+        return Ok(JsonValue::Null);
+    };
+
+    match ty.kind() {
+        Kind::Array(element) => return array_to_json(element, raw),
+        Kind::Domain(base) => return value_to_json(base, Some(raw)),
+        Kind::Range(element) => return range_to_json(element, raw).map(JsonValue::String),
+        Kind::Composite(fields) => return composite_to_json(fields, raw),
+        Kind::Enum(_) => return Ok(JsonValue::from(types::text_from_sql(raw)?)),
+        _ => {}
+    }
+
+    let value = match *ty {
+        Type::BOOL => JsonValue::from(types::bool_from_sql(raw)?),
+        Type::INT2 => JsonValue::from(types::int2_from_sql(raw)?),
+        Type::INT4 => JsonValue::from(types::int4_from_sql(raw)?),
+        Type::OID | Type::XID | Type::CID | Type::REGPROC | Type::REGCLASS | Type::REGTYPE | Type::REGNAMESPACE => {
+            JsonValue::from(types::oid_from_sql(raw)?)
+        }
+        Type::INT8 => JsonValue::String(types::int8_from_sql(raw)?.to_string()),
+        // Going through the shortest decimal form keeps 0.1 from turning into 0.10000000149011612
+        Type::FLOAT4 => float_to_json(types::float4_from_sql(raw)?.to_string().parse()?),
+        Type::FLOAT8 => float_to_json(types::float8_from_sql(raw)?),
+        Type::NUMERIC => JsonValue::String(numeric_to_string(raw)?),
+        Type::MONEY => {
+            let cents = types::int8_from_sql(raw)?;
+            let sign = if cents < 0 { "-" } else { "" };
+            JsonValue::String(format!("{}{}.{:02}", sign, (cents / 100).abs(), (cents % 100).abs()))
+        }
+        Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME | Type::UNKNOWN | Type::XML => {
+            JsonValue::from(types::text_from_sql(raw)?)
+        }
+        Type::CHAR => JsonValue::String((types::char_from_sql(raw)? as u8 as char).to_string()),
+        Type::JSON => serde_json::from_slice(raw)?,
+        Type::JSONB => match raw.split_first() {
+            Some((1, json)) => serde_json::from_slice(json)?,
+            _ => return Err("unsupported jsonb version".into()),
+        },
+        Type::JSONPATH => match raw.split_first() {
+            Some((1, path)) => JsonValue::from(std::str::from_utf8(path)?),
+            _ => return Err("unsupported jsonpath version".into()),
+        },
+        Type::UUID => JsonValue::String(uuid::Uuid::from_bytes(types::uuid_from_sql(raw)?).to_string()),
+        Type::BYTEA => JsonValue::String(hex(types::bytea_from_sql(raw))),
+        Type::DATE => JsonValue::String(date_to_string(types::date_from_sql(raw)?)),
+        Type::TIMESTAMP => JsonValue::String(timestamp_to_string(types::timestamp_from_sql(raw)?, "")),
+        Type::TIMESTAMPTZ => JsonValue::String(timestamp_to_string(types::timestamp_from_sql(raw)?, "Z")),
+        Type::TIME => JsonValue::String(time_to_string(types::time_from_sql(raw)?)),
+        Type::TIMETZ => {
+            let (time, offset) = raw.split_at_checked(8).ok_or("invalid timetz length")?;
+            // The offset is stored in seconds west of UTC
+            let offset = -types::int4_from_sql(offset)?;
+            let sign = if offset < 0 { '-' } else { '+' };
+            let offset = offset.abs();
+            JsonValue::String(format!(
+                "{}{}{:02}:{:02}",
+                time_to_string(types::time_from_sql(time)?),
+                sign,
+                offset / 3600,
+                offset % 3600 / 60
+            ))
+        }
+        Type::INTERVAL => JsonValue::String(interval_to_string(raw)?),
+        Type::INET | Type::CIDR => {
+            let inet = types::inet_from_sql(raw)?;
+            let full = if inet.addr().is_ipv4() { 32 } else { 128 };
+            if *ty == Type::INET && inet.netmask() == full {
+                JsonValue::String(inet.addr().to_string())
+            } else {
+                JsonValue::String(format!("{}/{}", inet.addr(), inet.netmask()))
+            }
+        }
+        Type::MACADDR => JsonValue::String(
+            types::macaddr_from_sql(raw)?.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":"),
+        ),
+        Type::BIT | Type::VARBIT => {
+            let bits = types::varbit_from_sql(raw)?;
+            let string = (0..bits.len()).map(|i| if bits.bytes()[i / 8] & (0x80 >> (i % 8)) != 0 { '1' } else { '0' });
+            JsonValue::String(string.collect())
+        }
+        Type::PG_LSN => {
+            let lsn = types::lsn_from_sql(raw)?;
+            JsonValue::String(format!("{:X}/{:X}", lsn >> 32, lsn & 0xFFFF_FFFF))
+        }
+        Type::POINT => {
+            let point = types::point_from_sql(raw)?;
+            serde_json::json!({ "x": point.x(), "y": point.y() })
+        }
+        Type::TS_VECTOR => JsonValue::String(tsvector_to_string(raw)?),
+        _ => match ty.name() {
+            "hstore" => {
+                let mut map = Map::new();
+                let mut entries = types::hstore_from_sql(raw)?;
+                while let Some((key, value)) = entries.next()? {
+                    map.insert(key.to_string(), value.map_or(JsonValue::Null, JsonValue::from));
+                }
+                JsonValue::Object(map)
+            }
+            "ltree" => JsonValue::from(types::ltree_from_sql(raw)?),
+            "lquery" => JsonValue::from(types::lquery_from_sql(raw)?),
+            "ltxtquery" => JsonValue::from(types::ltxtquery_from_sql(raw)?),
+            "citext" => JsonValue::from(types::text_from_sql(raw)?),
+            // The binary format of other types is their own, like the WKB of PostGIS
+            _ => JsonValue::String(hex(raw)),
+        },
+    };
+    Ok(value)
+}
+
+/// `NaN` and infinities are not valid JSON numbers.
+fn float_to_json(value: f64) -> JsonValue {
+    if value.is_finite() {
+        JsonValue::from(value)
+    } else if value.is_nan() {
+        JsonValue::from("NaN")
+    } else if value > 0.0 {
+        JsonValue::from("Infinity")
+    } else {
+        JsonValue::from("-Infinity")
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut string = String::with_capacity(2 + bytes.len() * 2);
+    string.push_str("\\x");
+    for byte in bytes {
+        write!(string, "{:02x}", byte).unwrap();
+    }
+    string
+}
+
+fn array_to_json(element: &Type, raw: &[u8]) -> Result<JsonValue, DecodeError> {
+    let array = types::array_from_sql(raw)?;
+    let dimensions: Vec<usize> = array.dimensions().map(|dimension| Ok(dimension.len as usize)).collect()?;
+    let values: Vec<JsonValue> = array.values().map(|value| value_to_json(element, value)).collect()?;
+    if dimensions.is_empty() {
+        return Ok(JsonValue::Array(Vec::new()));
+    }
+
+    fn nest(values: &mut impl Iterator<Item = JsonValue>, dimensions: &[usize]) -> JsonValue {
+        match dimensions.split_first() {
+            Some((len, inner)) => JsonValue::Array((0..*len).map(|_| nest(values, inner)).collect()),
+            None => values.next().unwrap_or(JsonValue::Null),
+        }
+    }
+    Ok(nest(&mut values.into_iter(), &dimensions))
+}
+
+/// A range in its Postgres text form, like `[1,10)`.
+fn range_to_json(element: &Type, raw: &[u8]) -> Result<String, DecodeError> {
+    let (lower, upper) = match types::range_from_sql(raw)? {
+        types::Range::Empty => return Ok("empty".to_string()),
+        types::Range::Nonempty(lower, upper) => (lower, upper),
+    };
+    let bound = |value: Option<&[u8]>| -> Result<String, DecodeError> {
+        let text = match value_to_json(element, value)? {
+            JsonValue::String(text) => text,
+            JsonValue::Null => return Ok(String::new()),
+            value => value.to_string(),
+        };
+        if text.is_empty() || text.contains(|c: char| "()[],\"\\ ".contains(c)) {
+            Ok(format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\"")))
+        } else {
+            Ok(text)
+        }
+    };
+
+    let (open, lower) = match lower {
+        types::RangeBound::Inclusive(value) => ('[', bound(value)?),
+        types::RangeBound::Exclusive(value) => ('(', bound(value)?),
+        types::RangeBound::Unbounded => ('(', String::new()),
+    };
+    let (close, upper) = match upper {
+        types::RangeBound::Inclusive(value) => (']', bound(value)?),
+        types::RangeBound::Exclusive(value) => (')', bound(value)?),
+        types::RangeBound::Unbounded => (')', String::new()),
+    };
+    Ok(format!("{}{},{}{}", open, lower, upper, close))
+}
+
+fn composite_to_json(fields: &[tokio_postgres::types::Field], mut raw: &[u8]) -> Result<JsonValue, DecodeError> {
+    let count = types::int4_from_sql(take(&mut raw, 4)?)?;
+    if count as usize != fields.len() {
+        return Err(format!("expected {} fields, got {}", fields.len(), count).into());
+    }
+    let mut map = Map::new();
+    for field in fields {
+        take(&mut raw, 4)?; // field type oid
+        let len = types::int4_from_sql(take(&mut raw, 4)?)?;
+        let value = if len < 0 { None } else { Some(take(&mut raw, len as usize)?) };
+        map.insert(field.name().to_string(), value_to_json(field.type_(), value)?);
+    }
+    Ok(JsonValue::Object(map))
+}
+
+fn take<'a>(raw: &mut &'a [u8], len: usize) -> Result<&'a [u8], DecodeError> {
+    let (head, tail) = raw.split_at_checked(len).ok_or("invalid value length")?;
+    *raw = tail;
+    Ok(head)
+}
+
+/// Decode the base-10000 digits of a `numeric` without losing precision.
+fn numeric_to_string(mut raw: &[u8]) -> Result<String, DecodeError> {
+    let ndigits = types::int2_from_sql(take(&mut raw, 2)?)?;
+    let weight = types::int2_from_sql(take(&mut raw, 2)?)? as i32;
+    let sign = types::int2_from_sql(take(&mut raw, 2)?)? as u16;
+    let dscale = types::int2_from_sql(take(&mut raw, 2)?)? as usize;
+    let digits: Vec<i16> = (0..ndigits).map(|_| types::int2_from_sql(take(&mut raw, 2)?)).collect::<Result<_, _>>()?;
+
+    match sign {
+        0x0000 | 0x4000 => {}
+        0xC000 => return Ok("NaN".to_string()),
+        0xD000 => return Ok("Infinity".to_string()),
+        0xF000 => return Ok("-Infinity".to_string()),
+        _ => return Err("invalid numeric sign".into()),
+    }
+    let digit = |i: i32| if i >= 0 { digits.get(i as usize).copied().unwrap_or(0) } else { 0 };
+
+    let mut string = String::new();
+    if sign == 0x4000 {
+        string.push('-');
+    }
+    if weight < 0 {
+        string.push('0');
+    } else {
+        write!(string, "{}", digit(0))?;
+        for i in 1..=weight {
+            write!(string, "{:04}", digit(i))?;
+        }
+    }
+    if dscale > 0 {
+        let mut fraction = String::new();
+        let mut i = weight + 1;
+        while fraction.len() < dscale {
+            write!(fraction, "{:04}", digit(i))?;
+            i += 1;
+        }
+        fraction.truncate(dscale);
+        string.push('.');
+        string.push_str(&fraction);
+    }
+    Ok(string)
+}
+
+fn epoch() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(2000, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap()
+}
+
+fn date_to_string(days: i32) -> String {
+    match days {
+        i32::MAX => "infinity".to_string(),
+        i32::MIN => "-infinity".to_string(),
+        days => (epoch().date() + TimeDelta::days(days as i64)).format("%Y-%m-%d").to_string(),
+    }
+}
+
+fn timestamp_to_string(micros: i64, suffix: &str) -> String {
+    match micros {
+        i64::MAX => "infinity".to_string(),
+        i64::MIN => "-infinity".to_string(),
+        micros => format!("{}{}", (epoch() + TimeDelta::microseconds(micros)).format("%Y-%m-%dT%H:%M:%S%.f"), suffix),
+    }
+}
+
+fn time_to_string(micros: i64) -> String {
+    // 24:00:00 is a valid Postgres time but not a chrono one
+    if micros == 86_400_000_000 {
+        return "24:00:00".to_string();
+    }
+    (NaiveTime::MIN + TimeDelta::microseconds(micros)).format("%H:%M:%S%.f").to_string()
+}
+
+/// An interval in ISO 8601 form, like `P1Y2M3DT4H5M6.5S`, the way Postgres prints it
+/// with `intervalstyle = iso_8601`.
+fn interval_to_string(mut raw: &[u8]) -> Result<String, DecodeError> {
+    let micros = types::int8_from_sql(take(&mut raw, 8)?)?;
+    let days = types::int4_from_sql(take(&mut raw, 4)?)?;
+    let months = types::int4_from_sql(take(&mut raw, 4)?)?;
+    if micros == 0 && days == 0 && months == 0 {
+        return Ok("PT0S".to_string());
+    }
+
+    let mut string = "P".to_string();
+    for (value, unit) in [(months / 12, 'Y'), (months % 12, 'M'), (days, 'D')] {
+        if value != 0 {
+            write!(string, "{}{}", value, unit)?;
+        }
+    }
+    if micros != 0 {
+        string.push('T');
+        let hours = micros / 3_600_000_000;
+        let minutes = micros % 3_600_000_000 / 60_000_000;
+        let seconds = micros % 60_000_000;
+        for (value, unit) in [(hours, 'H'), (minutes, 'M')] {
+            if value != 0 {
+                write!(string, "{}{}", value, unit)?;
+            }
+        }
+        if seconds != 0 {
+            let sign = if seconds < 0 { "-" } else { "" };
+            let seconds = seconds.abs();
+            write!(string, "{}{}", sign, seconds / 1_000_000)?;
+            if seconds % 1_000_000 != 0 {
+                let fraction = format!("{:06}", seconds % 1_000_000);
+                write!(string, ".{}", fraction.trim_end_matches('0'))?;
+            }
+            string.push('S');
+        }
+    }
+    Ok(string)
+}
+
+/// A `tsvector` in its text form, like `'cat':3A 'fat':2,4`.
+fn tsvector_to_string(mut raw: &[u8]) -> Result<String, DecodeError> {
+    let count = types::int4_from_sql(take(&mut raw, 4)?)?;
+    let mut lexemes = Vec::with_capacity(count.max(0) as usize);
+    for _ in 0..count {
+        let end = raw.iter().position(|&b| b == 0).ok_or("unterminated tsvector lexeme")?;
+        let lexeme = std::str::from_utf8(take(&mut raw, end)?)?;
+        take(&mut raw, 1)?;
+        let positions = types::int2_from_sql(take(&mut raw, 2)?)? as u16;
+
+        let mut string = format!("'{}'", lexeme.replace('\'', "''"));
+        for n in 0..positions {
+            let position = types::int2_from_sql(take(&mut raw, 2)?)? as u16;
+            string.push(if n == 0 { ':' } else { ',' });
+            write!(string, "{}", position & 0x3FFF)?;
+            match position >> 14 {
+                3 => string.push('A'),
+                2 => string.push('B'),
+                1 => string.push('C'),
+                _ => {}
+            }
+        }
+        lexemes.push(string);
+    }
+    Ok(lexemes.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numeric(weight: i16, sign: u16, dscale: i16, digits: &[i16]) -> Vec<u8> {
+        let header = [digits.len() as i16, weight, sign as i16, dscale];
+        header.iter().chain(digits).flat_map(|value| value.to_be_bytes()).collect()
+    }
+
+    fn interval(micros: i64, days: i32, months: i32) -> Vec<u8> {
+        [&micros.to_be_bytes()[..], &days.to_be_bytes(), &months.to_be_bytes()].concat()
+    }
+
+    fn decoded(ty: Type, raw: &[u8]) -> JsonValue {
+        value_to_json(&ty, Some(raw)).unwrap()
+    }
+
+    #[test]
+    fn numerics() {
+        assert_eq!(numeric_to_string(&numeric(1, 0, 3, &[1, 2345, 6780])).unwrap(), "12345.678");
+        assert_eq!(numeric_to_string(&numeric(0, 0, 2, &[1, 5000])).unwrap(), "1.50");
+        assert_eq!(numeric_to_string(&numeric(2, 0, 0, &[1])).unwrap(), "100000000");
+        assert_eq!(numeric_to_string(&numeric(-1, 0, 4, &[12])).unwrap(), "0.0012");
+        assert_eq!(numeric_to_string(&numeric(-2, 0, 8, &[7])).unwrap(), "0.00000007");
+        assert_eq!(numeric_to_string(&numeric(0, 0x4000, 0, &[5])).unwrap(), "-5");
+        assert_eq!(numeric_to_string(&numeric(0, 0, 0, &[])).unwrap(), "0");
+        assert_eq!(numeric_to_string(&numeric(0, 0, 2, &[])).unwrap(), "0.00");
+        // Trailing groups of zeros are not sent
+        assert_eq!(numeric_to_string(&numeric(7, 0, 0, &[1])).unwrap(), format!("1{}", "0".repeat(28)));
+
+        assert_eq!(numeric_to_string(&numeric(0, 0xC000, 0, &[])).unwrap(), "NaN");
+        assert_eq!(numeric_to_string(&numeric(0, 0xD000, 0, &[])).unwrap(), "Infinity");
+        assert_eq!(numeric_to_string(&numeric(0, 0xF000, 0, &[])).unwrap(), "-Infinity");
+        assert!(numeric_to_string(&numeric(0, 0x1234, 0, &[1])).is_err());
+        assert!(numeric_to_string(&numeric(0, 0, 0, &[1])[..9]).is_err());
+        assert_eq!(decoded(Type::NUMERIC, &numeric(0, 0x4000, 1, &[3, 1400])), "-3.1");
+    }
+
+    #[test]
+    fn intervals() {
+        assert_eq!(interval_to_string(&interval(0, 0, 0)).unwrap(), "PT0S");
+        let micros = 4 * 3_600_000_000 + 5 * 60_000_000 + 6_500_000;
+        assert_eq!(interval_to_string(&interval(micros, 3, 14)).unwrap(), "P1Y2M3DT4H5M6.5S");
+        assert_eq!(interval_to_string(&interval(0, 0, 12)).unwrap(), "P1Y");
+        assert_eq!(interval_to_string(&interval(0, -1, -14)).unwrap(), "P-1Y-2M-1D");
+        assert_eq!(interval_to_string(&interval(-5_400_000_000, 0, 0)).unwrap(), "PT-1H-30M");
+        assert_eq!(interval_to_string(&interval(-1_500_000, 0, 0)).unwrap(), "PT-1.5S");
+        assert_eq!(interval_to_string(&interval(1, 0, 0)).unwrap(), "PT0.000001S");
+        assert!(interval_to_string(&interval(0, 0, 0)[..15]).is_err());
+    }
+
+    #[test]
+    fn dates_and_times() {
+        let micros = |value: i64| value.to_be_bytes();
+        assert_eq!(decoded(Type::TIMESTAMP, &micros(0)), "2000-01-01T00:00:00");
+        assert_eq!(decoded(Type::TIMESTAMP, &micros(1_500_000)), "2000-01-01T00:00:01.500");
+        assert_eq!(decoded(Type::TIMESTAMP, &micros(-86_400_000_001)), "1999-12-30T23:59:59.999999");
+        assert_eq!(decoded(Type::TIMESTAMPTZ, &micros(0)), "2000-01-01T00:00:00Z");
+        assert_eq!(decoded(Type::TIMESTAMP, &micros(i64::MAX)), "infinity");
+        assert_eq!(decoded(Type::TIMESTAMPTZ, &micros(i64::MIN)), "-infinity");
+
+        assert_eq!(decoded(Type::DATE, &0i32.to_be_bytes()), "2000-01-01");
+        assert_eq!(decoded(Type::DATE, &(-1i32).to_be_bytes()), "1999-12-31");
+        assert_eq!(decoded(Type::DATE, &i32::MAX.to_be_bytes()), "infinity");
+
+        assert_eq!(decoded(Type::TIME, &micros(45_296_000_000)), "12:34:56");
+        assert_eq!(decoded(Type::TIME, &micros(86_400_000_000)), "24:00:00");
+        // Seconds west of UTC
+        let timetz = [&micros(43_200_000_000)[..], &(-7200i32).to_be_bytes()].concat();
+        assert_eq!(decoded(Type::TIMETZ, &timetz), "12:00:00+02:00");
+        let timetz = [&micros(0)[..], &16_200i32.to_be_bytes()].concat();
+        assert_eq!(decoded(Type::TIMETZ, &timetz), "00:00:00-04:30");
+    }
+
+    /// A client of the database of `PGDITOR_TEST_DATABASE_URL`, the tests needing one are
+    /// skipped without it.
+    async fn test_client() -> Option<PgClient> {
+        let url = std::env::var("PGDITOR_TEST_DATABASE_URL").ok()?;
+        let (client, connection) = tokio_postgres::connect(&url, tokio_postgres::NoTls).await.unwrap();
+        tokio::spawn(connection);
+        Some(client)
+    }
+
+    #[tokio::test]
+    async fn text_columns_queries() {
+        let Some(client) = test_client().await else {
+            return;
+        };
+
+        let sql = "select 1 as a, box(point(1, 2), point(3, 4)) as \"b\"\"c\" -- comment";
+        let text_sql = text_columns_query(sql, client.prepare(sql).await.unwrap().columns()).unwrap();
+        assert_eq!(
+            text_sql,
+            format!(
+                "with pgditor_result (column1, column2) as (\n{}\n) \
+                select column1 as \"a\", column2::text as \"b\"\"c\" from pgditor_result",
+                sql
+            )
+        );
+        let row = client.query_one(&text_sql, &[]).await.unwrap();
+        assert_eq!(row.get::<_, i32>("a"), 1);
+        assert_eq!(row.get::<_, String>("b\"c"), "(3,4),(1,2)");
+
+        let sql = "select 1 as a, now() as b";
+        assert_eq!(text_columns_query(sql, client.prepare(sql).await.unwrap().columns()), None);
+
+        // A `WITH` changing data cannot be nested in another one
+        client.batch_execute("create temporary table pgditor_codec_test (b box)").await.unwrap();
+        let sql = "with i as (insert into pgditor_codec_test values (null) returning b) select b from i";
+        assert_eq!(text_columns_query(sql, client.prepare(sql).await.unwrap().columns()), None);
+        let sql = "insert into pgditor_codec_test values (null) returning b";
+        assert!(text_columns_query(sql, client.prepare(sql).await.unwrap().columns()).is_some());
+    }
+}
@@ -1,13 +1,80 @@
 import {catch_error} from "@les3dev/catch_error";
 import {StoreContext} from "$lib/helpers/StoreContext";
-import type {PgTable} from "$lib/table/pg_context.svelte";
+import type {PgTable, StatementKind} from "$lib/table/pg_context.svelte";
 import {invoke} from "@tauri-apps/api/core";
+import {listen} from "@tauri-apps/api/event";
 import {getContext, setContext} from "svelte";
 
+export type SshAuth =
+    | {method: "password"; password: string}
+    | {method: "privateKey"; path: string; passphrase?: string}
+    | {method: "agent"};
+
+export type SshTunnel = {
+    host: string;
+    port?: number;
+    user: string;
+    auth: SshAuth;
+    knownHostsPath?: string;
+};
+
+export type PasswordCommand = {
+    command: string;
+    timeoutSecs?: number;
+};
+
+export type SessionSettings = {
+    searchPath?: string;
+    statementTimeout?: string;
+    lockTimeout?: string;
+    idleInTransactionSessionTimeout?: string;
+    role?: string;
+    timeZone?: string;
+    applicationName?: string;
+    initSql?: string;
+};
+
+/** The statements that need a confirmation before they run, `dml_without_where`, `truncate` and `drop_or_alter` by default. */
+export type SafetyPolicy = {
+    confirm: StatementKind[];
+};
+
 export type Connection = {
     id: string;
     name: string;
     connectionString: string;
+    sshTunnel?: SshTunnel;
+    passwordCommand?: PasswordCommand;
+    readOnly?: boolean;
+    sessionSettings?: SessionSettings;
+    safetyPolicy?: SafetyPolicy;
+};
+
+export type ConnectionReport = {
+    host: string;
+    port: number;
+    connect_ms: number;
+    latency_ms: number;
+    server_version: string;
+    server_version_num: number;
+    user: string;
+    database: string;
+    in_hot_standby: boolean;
+    read_only: boolean;
+    tls: {version: string; cipher: string; bits: number} | null;
+    extensions: {name: string; version: string}[];
+    privileges: {
+        is_superuser: boolean;
+        can_create_schema: boolean;
+        can_create_table: boolean;
+        can_modify_data: boolean;
+    };
+};
+
+export type ConnectionStatus = {
+    connection_id: string;
+    status: "connected" | "degraded" | "lost" | "reconnecting";
+    reason: string | null;
 };
 
 const storePath = "connections.json";
@@ -15,11 +82,15 @@ const storePath = "connections.json";
 class ConnectionsContext extends StoreContext {
     list = $state<Connection[]>([]);
     current_id = $state<string>();
+    statuses = $state<Record<string, ConnectionStatus>>({});
 
     /**
-     * Load all saved connections from _connections.json_.
+     * Load all saved connections from _connections.json_ and start tracking their health.
      */
     load = async () => {
+        await listen<ConnectionStatus>("connection-status", ({payload}) => {
+            this.statuses[payload.connection_id] = payload;
+        });
         this.list = (await this.get_from_store<Connection[]>("connections")) ?? [];
         if (this.list.length) {
             const selectedId = await this.get_from_store<string>("selected_connection_id");
@@ -69,6 +140,7 @@ class ConnectionsContext extends StoreContext {
      * @param id connection id
      */
     remove = async (id: string) => {
+        await catch_error(() => invoke("close_connection", {connectionId: id}));
         this.list = this.list.filter((connection) => connection.id !== id);
         await this.set_to_store("connections", this.list);
         await this.save_store();
@@ -120,7 +192,8 @@ class ConnectionsContext extends StoreContext {
     /**
      * Check all possible errors in the connection data.
      */
-    #check_errors = async ({name, connectionString}: Connection, index?: number) => {
+    #check_errors = async (connection: Connection, index?: number) => {
+        const {name, connectionString} = connection;
         if (name === "") {
             return "Name is required";
         }
@@ -133,12 +206,9 @@ class ConnectionsContext extends StoreContext {
         if (connectionString === "") {
             return "Connection string is required";
         }
-        const ok = await catch_error(() => invoke<boolean>("test_connection", {connectionString}));
-        if (ok instanceof Error) {
-            return ok.message;
-        }
-        if (!ok) {
-            return "Connection string is invalid";
+        const report = await catch_error(() => invoke<ConnectionReport>("test_connection", {connection}));
+        if (report instanceof Error) {
+            return report.message;
         }
     };
 }
@@ -130,8 +130,8 @@ class QueryGeneratorContext extends StoreContext {
         this.query_prompt = "";
         this.current_chat.history.push({type: "user", text: prompt});
 
-        const connectionString = this.#connections.current?.connectionString;
-        if (connectionString === undefined) {
+        const connection = this.#connections.current;
+        if (connection === undefined) {
             this.is_generating = false;
             return;
         }
@@ -178,7 +178,7 @@ class QueryGeneratorContext extends StoreContext {
         const result = await catch_error(() =>
             invoke<string | null>("generate_query", {
                 apiKey: this.#api_key,
-                connectionString,
+                connection,
                 model: this.model,
                 reasoning: this.reasoning,
                 prompt,
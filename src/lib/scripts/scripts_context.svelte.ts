@@ -1,14 +1,34 @@
 import {getContext, setContext} from "svelte";
 import {get_toast_context} from "$lib/widgets/Toaster.svelte";
-import {get_pg_context} from "$lib/table/pg_context.svelte";
+import {
+    get_pg_context,
+    type ExplainResult,
+    type OnError,
+    type PgValue,
+    type QueryMode,
+    type ScriptResult,
+    type SqlAnalysis,
+    type StatementKind,
+} from "$lib/table/pg_context.svelte";
 import {catch_error} from "@les3dev/catch_error";
 import {StoreContext} from "$lib/helpers/StoreContext";
 import {get_connections_context} from "$lib/connection/connections_context.svelte";
 import {readFile, writeTextFile} from "@tauri-apps/plugin-fs";
-import {save} from "@tauri-apps/plugin-dialog";
+import {ask, save} from "@tauri-apps/plugin-dialog";
 
 const store_path = "scripts.json";
 
+const statement_kind_labels: Record<StatementKind, string> = {
+    read_only: "reads data",
+    transaction_control: "controls the transaction",
+    other: "changes the database",
+    dml_with_where: "updates or deletes rows",
+    dml_without_where: "updates or deletes every row of a table",
+    truncate: "empties tables",
+    drop_or_alter: "drops or alters objects",
+    unknown: "could not be analyzed",
+};
+
 export type ScriptFile = {path: string; updated_at: string};
 class ScriptsContext extends StoreContext {
     #connections = get_connections_context();
@@ -27,8 +47,19 @@ class ScriptsContext extends StoreContext {
 
     current_value = $state("");
     current_selection = $state("");
-    last_result = $state<Record<string, string | null>[]>();
+    last_result = $state<ScriptResult>();
+    last_plan = $state<ExplainResult>();
+    mode = $state<QueryMode>("simple");
+    /** Empty, the whole script fails on the first error. */
+    on_error = $state<OnError | "">("");
+    /** Keep what `COPY ... TO STDOUT` sends, so it can be downloaded as a file. */
+    keep_copy_output = $state(false);
+    /** JSON array of the values bound to `$1..$n`, like `[42, "2024-01-01"]`. */
+    params = $state("");
     error_message = $state("");
+    /** What the statements of the script do, refreshed while it is typed. */
+    analysis = $state<SqlAnalysis>();
+    #analyze_timer: ReturnType<typeof setTimeout> | undefined;
 
     constructor(store_path: string) {
         super(store_path);
@@ -38,6 +69,18 @@ class ScriptsContext extends StoreContext {
                 this.load_all();
             }
         });
+
+        $effect(() => {
+            const sql = this.current_selection ? this.current_selection : this.current_value;
+            if (!this.#connections.current) {
+                return;
+            }
+            clearTimeout(this.#analyze_timer);
+            this.#analyze_timer = setTimeout(async () => {
+                const analysis = await catch_error(() => this.#pg.analyze_sql(sql));
+                this.analysis = analysis instanceof Error ? undefined : analysis;
+            }, 300);
+        });
     }
 
     load_all = async () => {
@@ -119,21 +162,137 @@ class ScriptsContext extends StoreContext {
         this.#toaster.toast(`File saved to ${path}`, {kind: "success"});
     };
 
-    run = async () => {
+    explain = async (analyze: boolean) => {
         this.error_message = "";
+        this.last_result = undefined;
         const result = await catch_error(() =>
-            this.#pg.raw_query(this.current_selection ? this.current_selection : this.current_value),
+            this.#pg.explain_query(this.current_selection ? this.current_selection : this.current_value, {analyze}),
         );
         if (result instanceof Error) {
             this.error_message = result.message;
         } else {
-            this.last_result = result;
-            if (this.last_result !== undefined) {
-                for (let i = 0; i < this.last_result.length; i++) {
-                    this.last_result[i].__index = i.toString(); // used to prevent re-render table rows
-                }
+            this.last_plan = result;
+        }
+    };
+
+    run = async () => {
+        this.error_message = "";
+        this.last_plan = undefined;
+        let params: PgValue[] = [];
+        if (this.params.trim() !== "") {
+            const parsed = catch_error(() => JSON.parse(this.params) as unknown);
+            if (!Array.isArray(parsed)) {
+                this.error_message = "Parameters must be a JSON array, like [42, \"2024-01-01\"]";
+                return;
+            }
+            params = parsed;
+        }
+        const sql = this.current_selection ? this.current_selection : this.current_value;
+        const confirmation = await catch_error(() => this.#confirm(sql));
+        if (confirmation instanceof Error) {
+            this.error_message = confirmation.message;
+            return;
+        }
+        if (confirmation === null) {
+            return;
+        }
+        if (this.#pg.transaction_id) {
+            // Transactions keep their session, their results are not streamed
+            this.last_result = undefined;
+            const result = await catch_error(() =>
+                this.#pg.execute_in_transaction(sql, {
+                    mode: this.mode,
+                    params,
+                    on_error: this.on_error || undefined,
+                    confirmation,
+                    keep_copy_output: this.keep_copy_output,
+                }),
+            );
+            if (result instanceof Error) {
+                this.error_message = result.message;
+            } else {
+                this.last_result = result;
             }
+            return;
         }
+        this.last_result = {statements: [], duration_ms: 0, notices: []};
+        const statement_at = (index: number) => {
+            const statements = this.last_result!.statements;
+            statements[index] ??= {
+                sql: "",
+                start: 0,
+                end: 0,
+                command_tag: "",
+                rows_affected: null,
+                columns: [],
+                rows: [],
+                duration_ms: 0,
+                status: "ok",
+                error: null,
+                copy_output: null,
+            };
+            return statements[index];
+        };
+        const result = await catch_error(() =>
+            this.#pg.stream_query(
+                sql,
+                (event) => {
+                    const statement = statement_at(event.statement);
+                    if (event.kind === "columns") {
+                        statement.columns = event.columns;
+                    } else if (event.kind === "rows") {
+                        for (const row of event.rows) {
+                            row.__index = statement.rows.length.toString(); // used to prevent re-render table rows
+                            statement.rows.push(row);
+                        }
+                    } else if (event.kind === "statement_failed") {
+                        statement.status = "error";
+                        statement.duration_ms = event.duration_ms;
+                    } else {
+                        statement.command_tag = event.command_tag;
+                        statement.rows_affected = event.rows_affected;
+                        statement.duration_ms = event.duration_ms;
+                    }
+                },
+                {
+                    mode: this.mode,
+                    params,
+                    on_error: this.on_error || undefined,
+                    on_notice: (notice) => this.last_result?.notices.push(notice),
+                    confirmation,
+                    keep_copy_output: this.keep_copy_output,
+                },
+            ),
+        );
+        if (result instanceof Error) {
+            this.error_message = result.message;
+        } else if (result !== undefined && this.last_result !== undefined) {
+            // The streamed rows are kept, the rest comes with the final result
+            result.statements.forEach(({rows, ...statement}, index) => {
+                Object.assign(statement_at(index), statement);
+            });
+            this.last_result.duration_ms = result.duration_ms;
+            this.last_result.notices = result.notices;
+        }
+    };
+
+    /**
+     * Ask before running the statements the safety policy of the connection wants confirmed.
+     * Returns the token confirming them, `undefined` when none is needed or `null` when the user declined.
+     */
+    #confirm = async (sql: string) => {
+        const analysis = await this.#pg.analyze_sql(sql);
+        if (!analysis?.confirmation_token) {
+            return undefined;
+        }
+        const statements = analysis.statements
+            .filter((statement) => statement.needs_confirmation)
+            .map((statement) => `This ${statement_kind_labels[statement.kind]}:\n${statement.sql.trim()}`);
+        const confirmed = await ask(`${statements.join("\n\n")}\n\nRun the script anyway?`, {
+            title: "Destructive statements",
+            kind: "warning",
+        });
+        return confirmed ? analysis.confirmation_token : null;
     };
 }
 const key = Symbol();
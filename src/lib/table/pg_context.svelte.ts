@@ -1,6 +1,7 @@
 import {get_connections_context} from "$lib/connection/connections_context.svelte";
 import {catch_error} from "@les3dev/catch_error";
-import {invoke} from "@tauri-apps/api/core";
+import {Channel, invoke} from "@tauri-apps/api/core";
+import {listen} from "@tauri-apps/api/event";
 import {getContext, setContext} from "svelte";
 import {value_to_sql, value_type_is_number, type PgType} from "./values";
 import {get_toast_context} from "$lib/widgets/Toaster.svelte";
@@ -28,6 +29,219 @@ export type PgColumn = {
     enum_values: string[] | null;
 };
 
+export type PgResultColumn = {
+    name: string;
+    /** Only known in `typed` mode. */
+    type_oid: number | null;
+    type_name: string | null;
+    nullable: boolean | null;
+    table_oid: number | null;
+    column_id: number | null;
+    /** The values are the `\x` hex of the binary format, for types that could not be fetched as text. */
+    binary: boolean;
+};
+/**
+ * `simple` runs the whole script at once and returns text values, `typed` runs each statement
+ * as a prepared statement and returns JSON values with column types.
+ */
+export type QueryMode = "simple" | "typed";
+/**
+ * Run the script one statement at a time and report the failed ones instead of failing
+ * the whole script. `stop` skips the statements after an error, `continue` runs them anyway.
+ */
+export type OnError = "stop" | "continue";
+/** What commands reject with. The fields after `code` are only set for database errors. */
+export type CommandError = {
+    message: string;
+    /** The SQLSTATE of database errors, or a lowercase pgditor code like `read_only`. */
+    code: string | null;
+    severity: "ERROR" | "FATAL" | "PANIC" | null;
+    /** The condition name of the SQLSTATE, like `unique_violation`. */
+    code_name: string | null;
+    code_class: {code: string; name: string | null} | null;
+    detail: string | null;
+    hint: string | null;
+    /**
+     * Where the error is in the script, lines and columns start at 1. Only set by the commands
+     * running a script or statement as given, not by `get_table_data`, `stream_table_data` or `fetch_more`.
+     */
+    position: {line: number; column: number; offset: number} | null;
+    /** 1-based character position in the query that was sent. */
+    query_position: number | null;
+    /** The error is in a query the server ran on its own, like the body of a function. */
+    internal_position: {position: number; query: string} | null;
+    where: string | null;
+    schema: string | null;
+    table: string | null;
+    column: string | null;
+    datatype: string | null;
+    constraint: string | null;
+    routine: string | null;
+};
+export type StatementResult = {
+    sql: string;
+    /** Offsets of the statement in the script. */
+    start: number;
+    end: number;
+    command_tag: string;
+    rows_affected: number | null;
+    columns: PgResultColumn[];
+    rows: Record<string, PgValue>[];
+    duration_ms: number;
+    status: "ok" | "error" | "skipped";
+    error: CommandError | null;
+    /** What a `COPY ... TO STDOUT` sent, with `keep_copy_output`. Its rows are also parsed into `rows`. */
+    copy_output: string | null;
+};
+/** A warning or other message the server sent while a query ran, like the output of `RAISE NOTICE`. */
+export type PgNotice = {
+    severity: "WARNING" | "NOTICE" | "INFO" | "LOG" | "DEBUG";
+    code: string;
+    message: string;
+    detail: string | null;
+    hint: string | null;
+};
+/** Sent as soon as the server sends a notice for a running query. */
+export type QueryNoticeEvent = PgNotice & {query_id: string};
+export type ScriptResult = {statements: StatementResult[]; duration_ms: number; notices: PgNotice[]};
+/** What a statement does, `unknown` when it could not be parsed or runs code like `CALL`. */
+export type StatementKind =
+    | "read_only"
+    | "transaction_control"
+    | "other"
+    | "dml_with_where"
+    | "dml_without_where"
+    | "truncate"
+    | "drop_or_alter"
+    | "unknown";
+export type StatementAnalysis = {
+    sql: string;
+    start: number;
+    end: number;
+    kind: StatementKind;
+    /** The safety policy of the connection wants this statement confirmed. */
+    needs_confirmation: boolean;
+    parse_error: string | null;
+};
+/** Pass `confirmation_token` as `confirmation` to run a script that needs to be confirmed. */
+export type SqlAnalysis = {statements: StatementAnalysis[]; confirmation_token: string | null};
+/** Sent by `stream_query` and `stream_table_data` while they read the rows. */
+export type ResultEvent =
+    | {kind: "columns"; statement: number; columns: PgResultColumn[]}
+    | {kind: "rows"; statement: number; rows: Record<string, PgValue>[]; first: boolean; total_rows: number}
+    | {
+          kind: "statement_complete";
+          statement: number;
+          command_tag: string;
+          rows_affected: number | null;
+          duration_ms: number;
+      }
+    | {kind: "statement_failed"; statement: number; message: string; duration_ms: number};
+
+/** A page of rows read from a server-side cursor opened with `open_cursor`. */
+export type CursorPage = {
+    cursor_id: string;
+    columns: PgResultColumn[];
+    rows: Record<string, PgValue>[];
+    fetched_rows: number;
+    /** `false` once every row was read, the cursor is then closed. */
+    has_more: boolean;
+};
+
+export type PlanProblem =
+    | {kind: "seq_scan_on_big_table"; rows: number}
+    | {kind: "nested_loop_misestimate"; factor: number}
+    | {kind: "sort_spilled"; method: string; space_kb: number}
+    | {kind: "hash_spilled"; batches: number};
+export type PlanNode = {
+    id: number;
+    node_type: string;
+    parent_relationship: string | null;
+    schema: string | null;
+    relation: string | null;
+    alias: string | null;
+    index_name: string | null;
+    join_type: string | null;
+    startup_cost: number;
+    total_cost: number;
+    self_cost: number;
+    estimated_rows: number;
+    /** Only set with `analyze`, like the other actual values. */
+    actual_rows: number | null;
+    loops: number | null;
+    total_time_ms: number | null;
+    self_time_ms: number | null;
+    misestimate: number | null;
+    buffers: {
+        shared_hit: number;
+        shared_read: number;
+        shared_dirtied: number;
+        shared_written: number;
+        local_hit: number;
+        local_read: number;
+        temp_read: number;
+        temp_written: number;
+    } | null;
+    hot: boolean;
+    problems: PlanProblem[];
+    details: Record<string, unknown>;
+    children: PlanNode[];
+};
+/** Returned by `explain_query`. */
+export type ExplainResult = {
+    plan: PlanNode;
+    analyzed: boolean;
+    planning_time_ms: number | null;
+    execution_time_ms: number | null;
+    triggers: {name: string; relation: string | null; calls: number; time_ms: number}[];
+    settings: Record<string, string>;
+    hottest: number[];
+    /** The JSON output of `EXPLAIN`, as returned by the server. */
+    raw: unknown;
+};
+
+export type TransactionOptions = {
+    isolation_level?: "read_committed" | "repeatable_read" | "serializable";
+    read_only?: boolean;
+    deferrable?: boolean;
+};
+/** Sent when a transaction stays open without running anything for a while. */
+export type TransactionIdleEvent = {transaction_id: string; connection_id: string; idle_seconds: number};
+
+export type HistoryStatus = "ok" | "error" | "cancelled";
+export type HistoryEntry = {
+    id: string;
+    sql: string;
+    connection_id: string;
+    database: string;
+    started_at: string;
+    duration_ms: number;
+    rows: number | null;
+    status: HistoryStatus;
+    /** The SQLSTATE or pgditor code of the first error. */
+    error_code: string | null;
+    /** How the script was run, `rerun_history_entry` runs it again the same way. */
+    mode: QueryMode;
+    params: PgValue[];
+    on_error: OnError | null;
+    keep_copy_output: boolean;
+};
+export type HistoryFilter = {text?: string; connection_id?: string; status?: HistoryStatus; limit?: number};
+
+/** Sent for each notification received on a channel listened to with `listen`. */
+export type PgNotification = {
+    connection_id: string;
+    channel: string;
+    /** The process id of the session that sent the notification. */
+    pid: number;
+    payload: string;
+    /** The payload indented, when it is a JSON object or array. */
+    pretty_payload: string | null;
+    received_at: string;
+};
+/** Sent when the session listening for a connection drops. */
+export type ListenClosedEvent = {connection_id: string; channels: string[]; reason: string};
+
 export type PgTableForGraph = Omit<PgTable, "column_names"> & {columns: PgColumn[]};
 export type PgValue = object | string | bigint | number | boolean | null;
 export type PgRow = Record<string, PgValue>;
@@ -107,6 +321,35 @@ class PgContext {
     current_table = $state<PgTable & {columns: PgColumn[]; rows: PgRow[]; count: number}>();
     last_query_time = $state<number>();
     is_loading = $state(false);
+    running_query_ids = $state<string[]>([]);
+    /** The transaction started with `begin_transaction`, scripts run in it until it ends. */
+    transaction_id = $state<string>();
+
+    constructor() {
+        listen<PgNotification>("pg-notification", ({payload}) => {
+            this.notifications.push(payload);
+            // Same limit as the recent notifications kept by the backend
+            if (this.notifications.length > 500) {
+                this.notifications.shift();
+            }
+        });
+        listen<ListenClosedEvent>("listen-closed", ({payload}) => {
+            this.listening_channels = this.listening_channels.filter(
+                ({connection_id}) => connection_id !== payload.connection_id,
+            );
+            this.#toast_context.toast(`Stopped listening to ${payload.channels.join(", ")}: ${payload.reason}`, {
+                kind: "error",
+            });
+        });
+        listen<TransactionIdleEvent>("transaction-idle", ({payload}) => {
+            if (payload.transaction_id === this.transaction_id) {
+                this.#toast_context.toast(
+                    `The transaction is idle for ${Math.round(payload.idle_seconds / 60)} min, commit or roll it back to release its locks`,
+                    {kind: "error"},
+                );
+            }
+        });
+    }
 
     selected_columns = $state<Set<string>>(new Set([]));
     get_selected_columns = () => {
@@ -196,10 +439,10 @@ ${this.selected_rows_json
         if (!this.connections.current) {
             return;
         }
-        const connectionString = this.connections.current.connectionString;
+        const connection = this.connections.current;
         this.is_loading = true;
         const unsortedTables = await catch_error(() =>
-            invoke<PgTable[]>("list_tables", {connectionString, hideSystemTables: this.#settings.hide_system_tables, hideViews: this.#settings.hide_views}),
+            invoke<PgTable[]>("list_tables", {connection, hideSystemTables: this.#settings.hide_system_tables, hideViews: this.#settings.hide_views}),
         );
         if (unsortedTables instanceof Error) {
             console.error(unsortedTables.message);
@@ -228,12 +471,12 @@ ${this.selected_rows_json
         if (!this.connections.current) {
             return new Error(`Couldn't connect to the database`);
         }
-        const connectionString = this.connections.current.connectionString;
+        const connection = this.connections.current;
         this.is_loading = true;
 
         const unsortedTables = await catch_error(() =>
             invoke<PgTableForGraph[]>("list_tables_for_graph", {
-                connectionString,
+                connection,
                 schema,
                 hideSystemTables: this.#settings.hide_system_tables,
                 hideViews: this.#settings.hide_views,
@@ -247,10 +490,10 @@ ${this.selected_rows_json
         if (!this.connections.current) {
             return new Error(`Couldn't connect to the database`);
         }
-        const connectionString = this.connections.current.connectionString;
+        const connection = this.connections.current;
 
         const schemas = await catch_error(() =>
-            invoke<string[]>("list_schemas", {connectionString, hideSystemTables: this.#settings.hide_system_tables}),
+            invoke<string[]>("list_schemas", {connection, hideSystemTables: this.#settings.hide_system_tables}),
         );
         return schemas;
     };
@@ -262,11 +505,11 @@ ${this.selected_rows_json
         if (!this.connections.current) {
             return;
         }
-        const connectionString = this.connections.current.connectionString;
+        const connection = this.connections.current;
         this.is_loading = true;
         const columns = await catch_error(() =>
             invoke<PgColumn[]>("list_table_columns", {
-                connectionString,
+                connection,
                 schema: table.schema,
                 table: table.name,
             }),
@@ -291,6 +534,59 @@ ${this.selected_rows_json
         this.is_loading = false;
     };
 
+    /**
+     * Invoke a cancellable query command, tracking its id in `running_query_ids` while it runs.
+     * Streaming commands get a channel calling `on_event`, and each batch of rows is acknowledged once handled.
+     * `on_notice` is called with the notices the server sends while the query runs.
+     */
+    #invoke_query = async <T>(
+        command:
+            | "raw_query"
+            | "get_table_data"
+            | "stream_query"
+            | "stream_table_data"
+            | "open_cursor"
+            | "fetch_more"
+            | "explain_query"
+            | "execute_in_transaction"
+            | "rerun_history_entry",
+        args: Record<string, unknown>,
+        on_event?: (event: ResultEvent) => void,
+        on_notice?: (notice: PgNotice) => void,
+    ) => {
+        const queryId = crypto.randomUUID();
+        const unlisten = on_notice
+            ? await listen<QueryNoticeEvent>("query-notice", ({payload: {query_id, ...notice}}) => {
+                  if (query_id === queryId) {
+                      on_notice(notice);
+                  }
+              })
+            : undefined;
+        this.running_query_ids.push(queryId);
+        const onEvent = new Channel<ResultEvent>();
+        onEvent.onmessage = (event) => {
+            on_event?.(event);
+            if (event.kind === "rows") {
+                invoke("ack_rows", {queryId});
+            }
+        };
+        try {
+            return await invoke<T>(command, on_event ? {...args, queryId, onEvent} : {...args, queryId});
+        } finally {
+            this.running_query_ids = this.running_query_ids.filter((id) => id !== queryId);
+            unlisten?.();
+        }
+    };
+
+    /**
+     * Cancel every query currently running on the server.
+     */
+    cancel_queries = async () => {
+        await Promise.all(
+            this.running_query_ids.map((queryId) => catch_error(() => invoke<boolean>("cancel_query", {queryId}))),
+        );
+    };
+
     /**
      * Return rows for the given table.
      */
@@ -298,10 +594,10 @@ ${this.selected_rows_json
         if (!this.connections.current) {
             return;
         }
-        const connectionString = this.connections.current.connectionString;
+        const connection = this.connections.current;
         const columns = await catch_error(() =>
             invoke<PgColumn[]>("list_table_columns", {
-                connectionString,
+                connection,
                 schema: table.schema,
                 table: table.name,
             }),
@@ -310,8 +606,8 @@ ${this.selected_rows_json
             return columns;
         }
         const data = await catch_error(() =>
-            invoke<{rows: PgRow[]; count: number}>("get_table_data", {
-                connectionString,
+            this.#invoke_query<{rows: PgRow[]; count: number}>("get_table_data", {
+                connection,
                 schema: table.schema,
                 table: table.name,
                 columns: "*",
@@ -337,25 +633,30 @@ ${this.selected_rows_json
         if (!this.connections.current || !this.current_table) {
             return [];
         }
-        const connectionString = this.connections.current.connectionString;
+        const connection = this.connections.current;
         const schema = this.current_table.schema;
         const table = this.current_table.name;
+        const rows: PgRow[] = [];
         const data = await catch_error(() =>
-            invoke<{rows: PgRow[]; count: number}>("get_table_data", {
-                connectionString,
-                schema,
-                table,
-                columns: "*",
-                whereClause: "",
-                offset: 0,
-                limit: undefined,
-                orderBy: "",
-            }),
+            this.#invoke_query<{rows: PgRow[]; count: number}>(
+                "stream_table_data",
+                {
+                    connection,
+                    schema,
+                    table,
+                    columns: "*",
+                    whereClause: "",
+                    offset: 0,
+                    limit: undefined,
+                    orderBy: "",
+                },
+                (event) => event.kind === "rows" && rows.push(...event.rows),
+            ),
         );
         if (data instanceof Error) {
             return [];
         }
-        return data.rows;
+        return rows;
     };
 
     /**
@@ -365,26 +666,31 @@ ${this.selected_rows_json
         if (!this.connections.current || !this.current_table) {
             return [];
         }
-        const connectionString = this.connections.current.connectionString;
+        const connection = this.connections.current;
         const schema = this.current_table.schema;
         const table = this.current_table.name;
         const where = this.where_sql;
+        const rows: PgRow[] = [];
         const data = await catch_error(() =>
-            invoke<{rows: PgRow[]; count: number}>("get_table_data", {
-                connectionString,
-                schema,
-                table,
-                columns: "*",
-                whereClause: where,
-                offset: 0,
-                limit: undefined,
-                orderBy: "",
-            }),
+            this.#invoke_query<{rows: PgRow[]; count: number}>(
+                "stream_table_data",
+                {
+                    connection,
+                    schema,
+                    table,
+                    columns: "*",
+                    whereClause: where,
+                    offset: 0,
+                    limit: undefined,
+                    orderBy: "",
+                },
+                (event) => event.kind === "rows" && rows.push(...event.rows),
+            ),
         );
         if (data instanceof Error) {
             return [];
         }
-        return data.rows;
+        return rows;
     };
 
     /**
@@ -397,13 +703,13 @@ ${this.selected_rows_json
         if (!this.connections.current || !this.current_table) {
             return;
         }
-        const connectionString = this.connections.current.connectionString;
+        const connection = this.connections.current;
         this.is_loading = true;
         const primary_key = this.current_table.columns.find((col) => col.is_primary_key === "YES");
         const {schema, name: table, column_names} = this.current_table;
         const data = await catch_error(() =>
-            invoke<{rows: PgRow[]; count: number}>("get_table_data", {
-                connectionString,
+            this.#invoke_query<{rows: PgRow[]; count: number}>("get_table_data", {
+                connection,
                 schema,
                 table,
                 columns:
@@ -446,19 +752,44 @@ ${this.selected_rows_json
      * Run the given raw sql query and call `refresh()` to update the displayed rows.
      * @param sql The raw query string to run.
      * @param throwError Throws an error by default, set to `false` if you want a toast like other helpers.
+     * @param params Values of the `$1..$n` parameters, strings are parsed by the server like literals of the parameter type.
+     * @param on_error Run the statements one at a time and report which ones failed.
+     * @param confirmation The token of `analyze_sql`, for scripts the safety policy wants confirmed.
+     * @param confirmed The user already confirmed this script, like a menu action, get its token first.
+     * @param keep_copy_output Return what `COPY ... TO STDOUT` sends in `copy_output`, to save it as a file.
      */
-    raw_query = async (sql: string, {throwError = true, refresh = true} = {}) => {
+    raw_query = async (
+        sql: string,
+        {
+            throwError = true,
+            refresh = true,
+            mode = "simple" as QueryMode,
+            params = [] as PgValue[],
+            on_error = undefined as OnError | undefined,
+            confirmation = undefined as string | undefined,
+            confirmed = false,
+            keep_copy_output = false,
+        } = {},
+    ) => {
         if (!this.connections.current || !this.current_table) {
             return;
         }
-        const connectionString = this.connections.current.connectionString;
+        const connection = this.connections.current;
         this.is_loading = true;
-        const data = await catch_error(() =>
-            invoke<{rows: Record<string, string | null>[]; duration_ms: number}>("raw_query", {
-                connectionString,
+        const data = await catch_error(async () => {
+            if (confirmed) {
+                confirmation = (await this.analyze_sql(sql))?.confirmation_token ?? undefined;
+            }
+            return await this.#invoke_query<ScriptResult>("raw_query", {
+                connection,
                 sql,
-            }),
-        );
+                mode,
+                params,
+                onError: on_error,
+                confirmation,
+                keepCopyOutput: keep_copy_output,
+            });
+        });
         this.is_loading = false;
 
         if (data instanceof Error) {
@@ -476,7 +807,257 @@ ${this.selected_rows_json
             this.is_loading = false;
         }
         this.last_query_time = data.duration_ms;
-        return data.rows;
+        return data;
+    };
+
+    /**
+     * Same as `raw_query`, but `on_event` receives the rows in batches as they are read,
+     * so large results show up right away. The returned statements have no rows.
+     * @param sql The raw query string to run.
+     * @param on_event Called with the columns, rows and completion of each statement.
+     */
+    stream_query = async (
+        sql: string,
+        on_event: (event: ResultEvent) => void,
+        {
+            refresh = true,
+            mode = "simple" as QueryMode,
+            params = [] as PgValue[],
+            on_error = undefined as OnError | undefined,
+            on_notice = undefined as ((notice: PgNotice) => void) | undefined,
+            confirmation = undefined as string | undefined,
+            keep_copy_output = false,
+        } = {},
+    ) => {
+        if (!this.connections.current) {
+            return;
+        }
+        const connection = this.connections.current;
+        this.is_loading = true;
+        const data = await catch_error(() =>
+            this.#invoke_query<ScriptResult>(
+                "stream_query",
+                {connection, sql, mode, params, onError: on_error, confirmation, keepCopyOutput: keep_copy_output},
+                (event) => {
+                    this.is_loading = false;
+                    on_event(event);
+                },
+                on_notice,
+            ),
+        );
+        this.is_loading = false;
+
+        if (data instanceof Error) {
+            console.error(data.message, sql);
+            throw data;
+        }
+        if (refresh) {
+            this.is_loading = true;
+            await this.refresh_data();
+            this.is_loading = false;
+        }
+        this.last_query_time = data.duration_ms;
+        return data;
+    };
+
+    /**
+     * Classify the statements of a script without running it, and get the token confirming
+     * the ones the safety policy of the current connection wants confirmed.
+     */
+    analyze_sql = async (sql: string) => {
+        if (!this.connections.current) {
+            return;
+        }
+        const connection = this.connections.current;
+        return await invoke<SqlAnalysis>("analyze_sql", {connection, sql});
+    };
+
+    /**
+     * Get the plan of a single statement.
+     * @param analyze Run the statement to get actual times and rows, any change it makes is rolled back.
+     * @param buffers Also count the blocks read and hit in cache, with `analyze`.
+     */
+    explain_query = async (sql: string, {analyze = false, buffers = true} = {}) => {
+        if (!this.connections.current) {
+            return;
+        }
+        const connection = this.connections.current;
+        return await this.#invoke_query<ExplainResult>("explain_query", {connection, sql, analyze, buffers});
+    };
+
+    /**
+     * Start a transaction on a session of its own. Until `commit` or `rollback`, scripts run in it.
+     */
+    begin_transaction = async (options: TransactionOptions = {}) => {
+        if (!this.connections.current || this.transaction_id) {
+            return;
+        }
+        const connection = this.connections.current;
+        this.transaction_id = await invoke<string>("begin_transaction", {connection, options});
+    };
+
+    /**
+     * Run a script in the current transaction, see `raw_query` for the options.
+     */
+    execute_in_transaction = async (
+        sql: string,
+        {
+            mode = "simple" as QueryMode,
+            params = [] as PgValue[],
+            on_error = undefined as OnError | undefined,
+            on_notice = undefined as ((notice: PgNotice) => void) | undefined,
+            confirmation = undefined as string | undefined,
+            keep_copy_output = false,
+        } = {},
+    ) => {
+        if (!this.transaction_id) {
+            return;
+        }
+        const data = await this.#invoke_query<ScriptResult>(
+            "execute_in_transaction",
+            {
+                transactionId: this.transaction_id,
+                sql,
+                mode,
+                params,
+                onError: on_error,
+                confirmation,
+                keepCopyOutput: keep_copy_output,
+            },
+            undefined,
+            on_notice,
+        );
+        this.last_query_time = data.duration_ms;
+        return data;
+    };
+
+    /**
+     * Set a savepoint in the current transaction.
+     */
+    savepoint = async (name: string) => {
+        if (!this.transaction_id) {
+            return;
+        }
+        await invoke("savepoint", {transactionId: this.transaction_id, name});
+    };
+
+    commit = async () => {
+        const transactionId = this.transaction_id;
+        this.transaction_id = undefined;
+        if (transactionId) {
+            await invoke("commit", {transactionId});
+            await this.refresh_data();
+        }
+    };
+
+    /**
+     * Roll back the current transaction, or only what happened since `savepoint`.
+     */
+    rollback = async (savepoint?: string) => {
+        const transactionId = this.transaction_id;
+        if (!transactionId) {
+            return;
+        }
+        if (savepoint === undefined) {
+            this.transaction_id = undefined;
+        }
+        await invoke("rollback", {transactionId, savepoint});
+    };
+
+    /**
+     * Open a server-side cursor for a single query and return its first page of rows.
+     * The cursor keeps a session and a transaction open until it is exhausted or closed.
+     * @param sql The query to run, it must return rows.
+     * @param page_size The number of rows of the first page.
+     */
+    open_cursor = async (sql: string, page_size = 1000) => {
+        if (!this.connections.current) {
+            return;
+        }
+        const connection = this.connections.current;
+        return await this.#invoke_query<CursorPage>("open_cursor", {connection, sql, pageSize: page_size});
+    };
+
+    /**
+     * Read the next rows of a cursor opened with `open_cursor`.
+     */
+    fetch_more = async (cursor_id: string, count = 1000) => {
+        return await this.#invoke_query<CursorPage>("fetch_more", {cursorId: cursor_id, count});
+    };
+
+    /**
+     * Close a cursor that still has rows to read.
+     */
+    close_cursor = async (cursor_id: string) => {
+        return await invoke<boolean>("close_cursor", {cursorId: cursor_id});
+    };
+
+    /** Notifications received on the channels listened to, oldest first. */
+    notifications = $state<PgNotification[]>([]);
+    listening_channels = $state<{connection_id: string; channel: string}[]>([]);
+
+    /**
+     * Listen to a channel of the current connection, its notifications are added to `notifications`.
+     */
+    listen = async (channel: string) => {
+        if (!this.connections.current) {
+            return;
+        }
+        const connection = this.connections.current;
+        await invoke("listen", {connection, channel});
+        if (!this.listening_channels.some((item) => item.connection_id === connection.id && item.channel === channel)) {
+            this.listening_channels.push({connection_id: connection.id, channel});
+        }
+    };
+
+    unlisten = async (connection_id: string, channel: string) => {
+        await invoke("unlisten", {connectionId: connection_id, channel});
+        this.listening_channels = this.listening_channels.filter(
+            (item) => item.connection_id !== connection_id || item.channel !== channel,
+        );
+    };
+
+    /**
+     * Send a notification on a channel of the current connection.
+     */
+    notify = async (channel: string, payload = "") => {
+        if (!this.connections.current) {
+            return;
+        }
+        const connection = this.connections.current;
+        await invoke("notify", {connection, channel, payload});
+    };
+
+    /**
+     * The last notifications the backend kept, for instance after reloading the window.
+     */
+    recent_notifications = async (connection_id?: string) => {
+        return await invoke<PgNotification[]>("recent_notifications", {connectionId: connection_id});
+    };
+
+    /**
+     * Search the scripts run so far, newest first.
+     */
+    search_history = async (filter: HistoryFilter = {}) => {
+        return await invoke<HistoryEntry[]>("search_history", {filter});
+    };
+
+    /**
+     * Run a history entry again on the connection it ran on.
+     * @param confirmation The token of `analyze_sql` for the SQL of the entry, when its connection wants it confirmed.
+     */
+    rerun_history_entry = async (entry: HistoryEntry, confirmation?: string) => {
+        const connection = this.connections.list.find((connection) => connection.id === entry.connection_id);
+        if (!connection) {
+            throw new Error("The connection of this history entry was removed");
+        }
+        const data = await this.#invoke_query<ScriptResult>("rerun_history_entry", {
+            connection,
+            entryId: entry.id,
+            confirmation,
+        });
+        this.last_query_time = data.duration_ms;
+        return data;
     };
 
     /**
@@ -513,7 +1094,7 @@ where ${pk.column_name} = any(array[${this.selected_rows
     };
 
     truncate_table = async () => {
-        await this.raw_query(`truncate ${this.fullname} restart identity cascade`, {throwError: false});
+        await this.raw_query(`truncate ${this.fullname} restart identity cascade`, {throwError: false, confirmed: true});
         this.reset_filters();
     };
 
@@ -104,6 +104,7 @@ export const create_context_menu = () => {
                     if (lastMenuContext.column && pg.current_table) {
                         await pg.raw_query(`UPDATE ${pg.fullname} SET ${lastMenuContext.column.column_name} = null;`, {
                             throwError: false,
+                            confirmed: true,
                         });
                         toast(`All values of column ${lastMenuContext.column.column_name} set to NULL`);
                     }
@@ -112,7 +113,7 @@ export const create_context_menu = () => {
                     if (lastMenuContext.column && pg.current_table) {
                         await pg.raw_query(
                             `UPDATE ${pg.fullname} SET ${lastMenuContext.column.column_name} = ${lastMenuContext.column.column_default};`,
-                            {throwError: false},
+                            {throwError: false, confirmed: true},
                         );
                         toast(`All values of column ${lastMenuContext.column.column_name} set to default`);
                     }